@@ -32,5 +32,8 @@ fn main() {
     });
 
     server.register("/", router);
-    server.listen(3030, None, None);
+    server
+        .bind(3030)
+        .expect("Couldn't bind on port!")
+        .listen();
 }
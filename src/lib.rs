@@ -7,3 +7,5 @@ pub use server::Server;
 pub use server::router;
 pub use server::request;
 pub use server::response;
+pub use server::test_client;
+pub use server::status;
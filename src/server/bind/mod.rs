@@ -0,0 +1,239 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, TcpListener,
+               ToSocketAddrs};
+use std::io::{Error, ErrorKind};
+use std::thread;
+#[cfg(unix)]
+use std::path::Path;
+
+use server::Server;
+use server::shutdown::{Shutdown, ShutdownHandle};
+use server::connection::Listener;
+
+/// Anything a server can be bound to.
+///
+/// A bare port binds the loopback address, anything else is resolved
+/// like `ToSocketAddrs`, eg `"0.0.0.0:8080"`, `"[::1]:8080"` or `("localhost", 8080)`.
+pub trait Bindable {
+    fn addrs(&self) -> Result<Vec<SocketAddr>, Error>;
+}
+
+impl Bindable for u16 {
+    fn addrs(&self) -> Result<Vec<SocketAddr>, Error> {
+        Ok(vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), *self)])
+    }
+}
+
+impl<T: Bindable + ?Sized> Bindable for &T {
+    fn addrs(&self) -> Result<Vec<SocketAddr>, Error> {
+        (**self).addrs()
+    }
+}
+
+macro_rules! bindable_socket_addrs {
+    ($($t:ty),*) => {
+        $(
+            impl Bindable for $t {
+                fn addrs(&self) -> Result<Vec<SocketAddr>, Error> {
+                    Ok(self.to_socket_addrs()?.collect())
+                }
+            }
+        )*
+    };
+}
+
+bindable_socket_addrs!(
+    str,
+    String,
+    SocketAddr,
+    SocketAddrV4,
+    SocketAddrV6,
+    (IpAddr, u16),
+    (Ipv4Addr, u16),
+    (Ipv6Addr, u16),
+    (String, u16)
+);
+
+impl Bindable for (&str, u16) {
+    fn addrs(&self) -> Result<Vec<SocketAddr>, Error> {
+        Ok(self.to_socket_addrs()?.collect())
+    }
+}
+
+/// Settings for a single listener.
+#[derive(Debug, Clone, Default)]
+pub struct ListenerOptions {
+    /// Whether routers registered with `Server::register_internal` are served here.
+    pub internal: bool,
+    /// The permissions of a unix socket file, eg `0o660`. Ignored for TCP.
+    pub mode: Option<u32>,
+}
+
+impl ListenerOptions {
+    pub fn new() -> ListenerOptions {
+        ListenerOptions::default()
+    }
+
+    /// Serves the internal routers on this listener as well as the public ones.
+    /// Only a loopback address or a unix socket can be bound with it, so they can't be
+    /// reached from another machine.
+    pub fn internal(mut self) -> ListenerOptions {
+        self.internal = true;
+        self
+    }
+
+    /// Sets the permissions of a unix socket file.
+    pub fn mode(mut self, mode: u32) -> ListenerOptions {
+        self.mode = Some(mode);
+        self
+    }
+}
+
+/// A server with its listeners bound, ready to start accepting.
+pub struct BoundServer {
+    server: Server,
+    listeners: Vec<(Listener, ListenerOptions)>,
+}
+
+impl BoundServer {
+    /// A server without any listeners yet.
+    pub fn new(server: Server) -> BoundServer {
+        BoundServer {
+            server,
+            listeners: Vec::new(),
+        }
+    }
+
+    /// Adds another listener, all of them share the server's routers and workers.
+    pub fn bind<A: Bindable>(self, address: A) -> Result<BoundServer, Error> {
+        self.bind_with(address, ListenerOptions::new())
+    }
+
+    /// Adds another listener with its own options.
+    /// Binds the first of the addresses that works.
+    pub fn bind_with<A: Bindable>(mut self, address: A, options: ListenerOptions) -> Result<BoundServer, Error> {
+        let mut last_error = Error::new(ErrorKind::InvalidInput, "No addresses to bind to");
+
+        for addr in address.addrs()? {
+            if options.internal && !addr.ip().is_loopback() {
+                last_error = Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("An internal listener can only bind a loopback address, not {}", addr),
+                );
+                continue;
+            }
+
+            match TcpListener::bind(addr) {
+                Ok(listener) => {
+                    self.listeners.push((Listener::Tcp(listener), options));
+                    return Ok(self);
+                }
+                Err(e) => last_error = e,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Adds a unix socket listener at `path`.
+    #[cfg(unix)]
+    pub fn bind_unix<P: AsRef<Path>>(mut self, path: P, options: ListenerOptions) -> Result<BoundServer, Error> {
+        let listener = Listener::bind_unix(path, options.mode)?;
+        self.listeners.push((listener, options));
+        Ok(self)
+    }
+
+    /// The address of the first TCP listener, handy for finding the port picked when binding port 0.
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        match self.local_addrs()?.first() {
+            Some(addr) => Ok(*addr),
+            None => Err(Error::new(ErrorKind::NotConnected, "Not bound to any address")),
+        }
+    }
+
+    /// The addresses of every TCP listener, in the order they were bound.
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>, Error> {
+        self.listeners
+            .iter()
+            .filter_map(|(listener, _)| match *listener {
+                Listener::Tcp(ref l) => Some(l.local_addr()),
+                #[cfg(unix)]
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Starts serving in the background until the returned handle stops it.
+    pub fn spawn(self) -> ShutdownHandle {
+        let shutdown = Shutdown::new();
+        let watcher = shutdown.clone();
+        let BoundServer { server, listeners } = self;
+
+        let thread = thread::spawn(move || server.serve(listeners, watcher));
+
+        ShutdownHandle::new(shutdown, thread)
+    }
+
+    /// Serves on the current thread until the process receives SIGINT or SIGTERM.
+    pub fn listen(self) {
+        let handle = self.spawn();
+        handle.on_signals();
+        handle.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_bindable_addresses() {
+        let loopback = |port| SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+        let v6 = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 8080);
+
+        assert_eq!(40000u16.addrs().unwrap(), vec![loopback(40000)]);
+        assert_eq!("127.0.0.1:8080".addrs().unwrap(), vec![loopback(8080)]);
+        assert_eq!("[::1]:8080".to_string().addrs().unwrap(), vec![v6]);
+        assert_eq!((Ipv6Addr::LOCALHOST, 8080).addrs().unwrap(), vec![v6]);
+        assert_eq!(("127.0.0.1", 0).addrs().unwrap(), vec![loopback(0)]);
+        assert!("not an address".addrs().is_err());
+    }
+
+    #[test]
+    fn reports_the_port_picked_for_port_0() {
+        let bound = Server::new().bind(0).unwrap().bind("127.0.0.1:0").unwrap();
+        let addrs = bound.local_addrs().unwrap();
+
+        assert_eq!(addrs.len(), 2);
+        assert_eq!(bound.local_addr().unwrap(), addrs[0]);
+        for addr in &addrs {
+            assert!(addr.ip().is_loopback(), "{}", addr);
+            assert_ne!(addr.port(), 0);
+        }
+        assert_ne!(addrs[0].port(), addrs[1].port());
+    }
+
+    #[test]
+    fn binds_internal_listeners_to_loopback_only() {
+        for address in &["0.0.0.0:0", "[::]:0", "192.0.2.1:0"] {
+            let error = Server::new().bind_with(*address, ListenerOptions::new().internal()).err();
+            assert_eq!(error.map(|e| e.kind()), Some(ErrorKind::InvalidInput), "{}", address);
+        }
+
+        let bound = Server::new().bind_with(0, ListenerOptions::new().internal()).unwrap();
+        assert!(bound.local_addr().unwrap().ip().is_loopback());
+        assert!(Server::new().bind_with("0.0.0.0:0", ListenerOptions::new()).is_ok());
+    }
+
+    #[test]
+    fn fails_to_bind_a_port_in_use() {
+        let first = Server::new().bind(0).unwrap();
+        let taken = first.local_addr().unwrap();
+
+        let error = Server::new().bind(taken).err().map(|e| e.kind());
+        assert_eq!(error, Some(ErrorKind::AddrInUse));
+        assert_eq!(
+            BoundServer::new(Server::new()).local_addr().err().map(|e| e.kind()),
+            Some(ErrorKind::NotConnected)
+        );
+    }
+}
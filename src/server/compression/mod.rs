@@ -0,0 +1,190 @@
+use server::deflate;
+use server::negotiate;
+
+/// Bodies smaller than this aren't worth compressing, the headers cost more than is saved.
+const DEFAULT_THRESHOLD: usize = 1024;
+
+/// Files bigger than this are streamed as they are rather than read into memory to compress.
+pub const MAX_FILE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Types that are compressed already, squeezing them again only costs time.
+const PRECOMPRESSED: [&str; 21] = [
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "audio/",
+    "video/",
+    "font/woff",
+    "font/woff2",
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/x-7z-compressed",
+    "application/vnd.rar",
+    "application/x-bzip2",
+    "application/zstd",
+    "application/pdf",
+    "application/epub+zip",
+    "application/octet-stream",
+    "application/wasm",
+    "application/vnd.openxmlformats-officedocument.",
+];
+
+/// A content coding a response body can be compressed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    /// The zlib format, which is what HTTP means by `deflate`.
+    Deflate,
+}
+
+impl Encoding {
+    /// The name used in `Accept-Encoding` and `Content-Encoding`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    pub fn encode(&self, body: &[u8]) -> Vec<u8> {
+        match *self {
+            Encoding::Gzip => deflate::gzip(body),
+            Encoding::Deflate => deflate::zlib(body),
+        }
+    }
+}
+
+/// When to compress response bodies, turn it on with `server.compression(Compression::new())`.
+///
+/// Bodies sent with `send` and files up to 8MB are compressed with gzip or deflate,
+/// whichever the client prefers in its `Accept-Encoding`. Streamed responses, ranges, and
+/// responses that already set a `Content-Encoding` are sent as they are.
+#[derive(Debug, Clone)]
+pub struct Compression {
+    threshold: usize,
+    skip: Vec<String>,
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new()
+    }
+}
+
+impl Compression {
+    /// Compresses bodies of 1KB or more, skipping already compressed types like images.
+    pub fn new() -> Compression {
+        Compression {
+            threshold: DEFAULT_THRESHOLD,
+            skip: PRECOMPRESSED.iter().map(|v| v.to_string()).collect(),
+        }
+    }
+
+    /// Only compresses bodies of at least this many bytes.
+    pub fn threshold(mut self, bytes: usize) -> Compression {
+        self.threshold = bytes;
+        self
+    }
+
+    /// Never compresses this type, or every type starting with it, eg `.skip("image/")`.
+    pub fn skip<T: ToString>(mut self, mime: T) -> Compression {
+        self.skip.push(mime.to_string().to_lowercase());
+        self
+    }
+
+    /// Whether a body of this type could be compressed, parameters like `charset` are ignored.
+    pub fn compressible(&self, content_type: Option<&str>) -> bool {
+        let mime = match content_type {
+            Some(v) => v.split(';').next().unwrap_or("").trim().to_lowercase(),
+            None => return true,
+        };
+
+        !self.skip.iter().any(|skip| {
+            if skip.ends_with('/') || skip.ends_with('.') {
+                mime.starts_with(skip.as_str())
+            } else {
+                mime == *skip
+            }
+        })
+    }
+
+    /// Whether a body this long is worth compressing.
+    pub fn worth_it(&self, length: usize) -> bool {
+        length >= self.threshold
+    }
+}
+
+/// Picks the encoding to use from an `Accept-Encoding` header, going by its q-values
+/// and preferring gzip on a tie. `None` if the client accepts neither.
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    match negotiate::encoding(Some(accept_encoding), &["gzip", "deflate"]) {
+        Some("gzip") => Some(Encoding::Gzip),
+        Some(_) => Some(Encoding::Deflate),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_by_q_value() {
+        let cases: &[(&str, Option<Encoding>)] = &[
+            ("gzip", Some(Encoding::Gzip)),
+            ("deflate", Some(Encoding::Deflate)),
+            ("deflate, gzip", Some(Encoding::Gzip)),
+            ("gzip;q=0.5, deflate;q=0.8", Some(Encoding::Deflate)),
+            ("gzip;q=0, deflate", Some(Encoding::Deflate)),
+            ("*", Some(Encoding::Gzip)),
+            ("*, gzip;q=0", Some(Encoding::Deflate)),
+            ("gzip;q=0, deflate;q=0", None),
+            ("*;q=0", None),
+            ("identity", None),
+            ("br", None),
+            ("", None),
+        ];
+
+        for &(header, expected) in cases {
+            assert_eq!(negotiate(header), expected, "{}", header);
+        }
+    }
+
+    #[test]
+    fn skips_types_that_are_already_compressed() {
+        let compression = Compression::new().skip("text/csv");
+        let cases: &[(Option<&str>, bool)] = &[
+            (None, true),
+            (Some("text/html; charset=utf-8"), true),
+            (Some("application/json"), true),
+            (Some("image/svg+xml"), true),
+            (Some("image/PNG"), false),
+            (Some("video/mp4"), false),
+            (Some("audio/mpeg"), false),
+            (Some("application/vnd.openxmlformats-officedocument.wordprocessingml.document"), false),
+            (Some("text/csv; charset=utf-8"), false),
+        ];
+
+        for &(mime, expected) in cases {
+            assert_eq!(compression.compressible(mime), expected, "{:?}", mime);
+        }
+    }
+
+    #[test]
+    fn only_compresses_bodies_over_the_threshold() {
+        let compression = Compression::new();
+        assert!(!compression.worth_it(DEFAULT_THRESHOLD - 1));
+        assert!(compression.worth_it(DEFAULT_THRESHOLD));
+        assert!(Compression::new().threshold(0).worth_it(0));
+    }
+
+    #[test]
+    fn encodings_round_trip() {
+        let body = b"hello hello hello hello".to_vec();
+        assert_eq!(deflate::gunzip(&Encoding::Gzip.encode(&body), 1024).unwrap(), body);
+        assert_eq!(deflate::unzlib(&Encoding::Deflate.encode(&body), 1024).unwrap(), body);
+    }
+}
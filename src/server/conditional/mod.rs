@@ -0,0 +1,267 @@
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use server::date;
+use server::response::RequestInfo;
+use server::status::StatusCode;
+
+/// An entity tag, identifying one version of a resource.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ETag {
+    /// Weak tags only promise the content is equivalent, not byte for byte the same.
+    pub weak: bool,
+    /// The opaque tag, without quotes.
+    pub tag: String,
+}
+
+impl ETag {
+    pub fn strong<T: ToString>(tag: T) -> ETag {
+        ETag {
+            weak: false,
+            tag: tag.to_string(),
+        }
+    }
+
+    pub fn weak<T: ToString>(tag: T) -> ETag {
+        ETag {
+            weak: true,
+            tag: tag.to_string(),
+        }
+    }
+
+    /// A tag from a hash of the body.
+    pub fn from_bytes(body: &[u8], weak: bool) -> ETag {
+        ETag {
+            weak,
+            tag: format!("{:016x}", fnv1a(body)),
+        }
+    }
+
+    /// A tag from a file's size and modification time, which changes whenever the file does
+    /// without having to read it.
+    pub fn from_metadata(length: u64, modified: Option<SystemTime>) -> ETag {
+        let modified = modified
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() * 1_000_000_000 + u64::from(d.subsec_nanos()))
+            .unwrap_or(0);
+
+        ETag::strong(format!("{:x}-{:x}", modified, length))
+    }
+
+    /// Parses a single tag like `"abc"` or `W/"abc"`.
+    pub fn parse(value: &str) -> Option<ETag> {
+        let value = value.trim();
+        let (weak, quoted) = match value.strip_prefix("W/") {
+            Some(v) => (true, v),
+            None => (false, value),
+        };
+
+        if quoted.len() < 2 || !quoted.starts_with('"') || !quoted.ends_with('"') {
+            return None;
+        }
+
+        let tag = &quoted[1..quoted.len() - 1];
+        if tag.contains('"') {
+            return None;
+        }
+
+        Some(ETag {
+            weak,
+            tag: tag.to_string(),
+        })
+    }
+
+    /// Both tags are strong and identical.
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// The tags are identical, weak or not.
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl Display for ETag {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.weak {
+            write!(f, "W/\"{}\"", self.tag)
+        } else {
+            write!(f, "\"{}\"", self.tag)
+        }
+    }
+}
+
+/// 64 bit FNV-1a, stable across builds unlike the std hasher.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+/// Whether an `If-Match`/`If-None-Match` list matches the current tag.
+/// `*` matches whenever the resource exists.
+fn list_matches(list: &str, current: Option<&ETag>, strong: bool) -> bool {
+    let current = match current {
+        Some(v) => v,
+        None => return false,
+    };
+
+    if list.trim() == "*" {
+        return true;
+    }
+
+    list.split(',')
+        .filter_map(ETag::parse)
+        .any(|tag| if strong { tag.strong_eq(current) } else { tag.weak_eq(current) })
+}
+
+/// Evaluates the request's preconditions against the resource's current validators,
+/// in the order RFC 9110 gives. Returns the status to answer with instead if one fails,
+/// `304 Not Modified` for reads or `412 Precondition Failed`.
+pub fn evaluate(
+    request: &RequestInfo,
+    etag: Option<&ETag>,
+    last_modified: Option<SystemTime>,
+) -> Option<StatusCode> {
+    let is_read = request.method.eq_ignore_ascii_case("GET") || request.method.eq_ignore_ascii_case("HEAD");
+    // dates only have second precision.
+    let last_modified = last_modified.map(truncate_to_seconds);
+
+    if let Some(if_match) = request.header("If-Match") {
+        if !list_matches(if_match, etag, true) {
+            return Some(StatusCode::PRECONDITION_FAILED);
+        }
+    } else if let Some(since) = request.header("If-Unmodified-Since").and_then(date::parse) {
+        if let Some(modified) = last_modified {
+            if modified > since {
+                return Some(StatusCode::PRECONDITION_FAILED);
+            }
+        }
+    }
+
+    if let Some(if_none_match) = request.header("If-None-Match") {
+        if list_matches(if_none_match, etag, false) {
+            return Some(if is_read {
+                StatusCode::NOT_MODIFIED
+            } else {
+                StatusCode::PRECONDITION_FAILED
+            });
+        }
+    } else if is_read {
+        if let Some(since) = request.header("If-Modified-Since").and_then(date::parse) {
+            if let Some(modified) = last_modified {
+                if modified <= since {
+                    return Some(StatusCode::NOT_MODIFIED);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+fn truncate_to_seconds(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => UNIX_EPOCH + ::std::time::Duration::from_secs(d.as_secs()),
+        Err(_) => time,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    type Headers<'a> = &'a [(&'a str, &'a str)];
+
+    fn request(method: &str, headers: Headers) -> RequestInfo {
+        RequestInfo {
+            method: method.to_string(),
+            route: "/".to_string(),
+            headers: headers
+                .iter()
+                .map(|&(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<String, String>>(),
+        }
+    }
+
+    #[test]
+    fn parses_tags() {
+        let cases: &[(&str, Option<ETag>)] = &[
+            ("\"abc\"", Some(ETag::strong("abc"))),
+            ("W/\"abc\"", Some(ETag::weak("abc"))),
+            (" \"\" ", Some(ETag::strong(""))),
+            ("abc", None),
+            ("\"abc", None),
+            ("\"a\"b\"", None),
+            ("w/\"abc\"", None),
+        ];
+
+        for &(value, ref expected) in cases {
+            assert_eq!(ETag::parse(value), *expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn evaluates_preconditions() {
+        let etag = ETag::strong("v2");
+        // 1994-11-06 08:49:37 GMT
+        let modified = UNIX_EPOCH + Duration::from_secs(784_111_777);
+
+        let cases: &[(&str, Headers, Option<StatusCode>)] = &[
+            ("GET", &[], None),
+            ("GET", &[("If-None-Match", "\"v2\"")], Some(StatusCode::NOT_MODIFIED)),
+            ("HEAD", &[("If-None-Match", "\"v2\"")], Some(StatusCode::NOT_MODIFIED)),
+            ("GET", &[("If-None-Match", "W/\"v2\"")], Some(StatusCode::NOT_MODIFIED)),
+            ("GET", &[("If-None-Match", "\"v1\", \"v2\"")], Some(StatusCode::NOT_MODIFIED)),
+            ("GET", &[("If-None-Match", "*")], Some(StatusCode::NOT_MODIFIED)),
+            ("GET", &[("If-None-Match", "\"v1\"")], None),
+            ("PUT", &[("If-None-Match", "*")], Some(StatusCode::PRECONDITION_FAILED)),
+            ("PUT", &[("If-Match", "\"v2\"")], None),
+            ("PUT", &[("If-Match", "W/\"v2\"")], Some(StatusCode::PRECONDITION_FAILED)),
+            ("PUT", &[("If-Match", "\"v1\"")], Some(StatusCode::PRECONDITION_FAILED)),
+            ("GET", &[("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT")], Some(StatusCode::NOT_MODIFIED)),
+            ("HEAD", &[("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT")], Some(StatusCode::NOT_MODIFIED)),
+            ("GET", &[("If-Modified-Since", "Sun, 06 Nov 1994 08:49:36 GMT")], None),
+            ("POST", &[("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT")], None),
+            ("PUT", &[("If-Unmodified-Since", "Sun, 06 Nov 1994 08:49:36 GMT")], Some(StatusCode::PRECONDITION_FAILED)),
+            ("PUT", &[("If-Unmodified-Since", "Sun, 06 Nov 1994 08:49:37 GMT")], None),
+            // an entity tag overrides the date it's sent with.
+            (
+                "GET",
+                &[("If-None-Match", "\"v1\""), ("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT")],
+                None,
+            ),
+            (
+                "PUT",
+                &[("If-Match", "\"v2\""), ("If-Unmodified-Since", "Sun, 06 Nov 1994 08:49:36 GMT")],
+                None,
+            ),
+        ];
+
+        for &(method, headers, expected) in cases {
+            assert_eq!(
+                evaluate(&request(method, headers), Some(&etag), Some(modified)),
+                expected,
+                "{} {:?}",
+                method,
+                headers
+            );
+        }
+    }
+
+    #[test]
+    fn no_current_tag_matches_nothing() {
+        let req = request("PUT", &[("If-Match", "*")]);
+        assert_eq!(evaluate(&req, None, None), Some(StatusCode::PRECONDITION_FAILED));
+
+        let req = request("GET", &[("If-None-Match", "*")]);
+        assert_eq!(evaluate(&req, None, None), None);
+    }
+}
@@ -0,0 +1,263 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::io::{Error, Read, Write};
+use std::time::Duration;
+use std::fmt;
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::io::ErrorKind;
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// Anything requests can be read from and responses written to,
+/// eg a socket, a TLS wrapper or an in-memory buffer.
+pub trait Transport: Read + Write + Send + 'static {}
+
+impl<T: Read + Write + Send + 'static> Transport for T {}
+
+/// A client connection accepted by one of the server's listeners.
+pub enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        match *self {
+            Connection::Tcp(ref mut s) => s.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        match *self {
+            Connection::Tcp(ref mut s) => s.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match *self {
+            Connection::Tcp(ref mut s) => s.flush(),
+            #[cfg(unix)]
+            Connection::Unix(ref mut s) => s.flush(),
+        }
+    }
+}
+
+impl From<TcpStream> for Connection {
+    fn from(stream: TcpStream) -> Connection {
+        Connection::Tcp(stream)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for Connection {
+    fn from(stream: UnixStream) -> Connection {
+        Connection::Unix(stream)
+    }
+}
+
+/// Where a listener can be reached from inside the server, eg to wake it up on shutdown.
+#[derive(Debug, Clone)]
+pub enum Address {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Address {
+    pub fn connect(&self) -> Result<Connection, Error> {
+        match *self {
+            Address::Tcp(ref addr) => TcpStream::connect_timeout(addr, Duration::from_secs(1)).map(Connection::Tcp),
+            #[cfg(unix)]
+            Address::Unix(ref path) => UnixStream::connect(path).map(Connection::Unix),
+        }
+    }
+}
+
+/// A bound socket the server accepts connections from.
+pub enum Listener {
+    Tcp(TcpListener),
+    /// Keeps the path around to remove the socket file once the listener is dropped.
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    /// Binds a unix socket at `path`, replacing a stale socket left behind by a
+    /// server that didn't shut down cleanly. `mode` sets the file permissions, eg `0o660`.
+    ///
+    /// Fails if another server is still listening on the socket, or if `path`
+    /// is something other than a socket.
+    #[cfg(unix)]
+    pub fn bind_unix<P: AsRef<Path>>(path: P, mode: Option<u32>) -> Result<Listener, Error> {
+        let path = path.as_ref();
+
+        if let Ok(meta) = fs::symlink_metadata(path) {
+            if !meta.file_type().is_socket() {
+                return Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} exists and isn't a socket", path.display()),
+                ));
+            }
+
+            if UnixStream::connect(path).is_ok() {
+                return Err(Error::new(
+                    ErrorKind::AddrInUse,
+                    format!("{} is in use by another server", path.display()),
+                ));
+            }
+
+            // nothing is listening, it's left over from an earlier run.
+            fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+
+        if let Some(mode) = mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+
+        Ok(Listener::Unix(listener, path.to_path_buf()))
+    }
+
+    pub fn accept(&self) -> Result<Connection, Error> {
+        match *self {
+            Listener::Tcp(ref l) => l.accept().map(|(s, _)| Connection::Tcp(s)),
+            #[cfg(unix)]
+            Listener::Unix(ref l, _) => l.accept().map(|(s, _)| Connection::Unix(s)),
+        }
+    }
+
+    /// The address to connect to this listener on, loopback for one bound to every interface.
+    pub fn address(&self) -> Result<Address, Error> {
+        match *self {
+            Listener::Tcp(ref l) => {
+                let mut addr = l.local_addr()?;
+                if addr.ip().is_unspecified() {
+                    addr.set_ip(match addr.ip() {
+                        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                    });
+                }
+                Ok(Address::Tcp(addr))
+            }
+            #[cfg(unix)]
+            Listener::Unix(_, ref path) => Ok(Address::Unix(path.clone())),
+        }
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Listener::Tcp(ref l) => match l.local_addr() {
+                Ok(addr) => write!(f, "{}", addr),
+                Err(_) => write!(f, "tcp"),
+            },
+            #[cfg(unix)]
+            Listener::Unix(_, ref path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, ref path) = *self {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// A path in a fresh directory, with nothing at it yet.
+    fn socket_path(test: &str) -> PathBuf {
+        let dir = ::std::env::temp_dir().join(format!("rusty_server-{}-{}", test, ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("app.sock")
+    }
+
+    #[test]
+    fn replaces_a_stale_socket() {
+        let path = socket_path("stale-socket");
+        // a listener dropped without cleaning up, as after a crash.
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(fs::symlink_metadata(&path).unwrap().file_type().is_socket());
+
+        let listener = Listener::bind_unix(&path, None).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"hi").unwrap();
+        let mut accepted = listener.accept().unwrap();
+        let mut buf = [0; 2];
+        accepted.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hi");
+
+        drop(listener);
+        assert!(!path.exists());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_socket_in_use() {
+        let path = socket_path("busy-socket");
+        let listening = Listener::bind_unix(&path, None).unwrap();
+
+        let error = Listener::bind_unix(&path, None).err().map(|e| e.kind());
+        assert_eq!(error, Some(ErrorKind::AddrInUse));
+        assert!(UnixStream::connect(&path).is_ok());
+
+        drop(listening);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn refuses_to_remove_what_isnt_a_socket() {
+        let path = socket_path("not-a-socket");
+        fs::write(&path, "important").unwrap();
+
+        let error = Listener::bind_unix(&path, None).err().map(|e| e.kind());
+        assert_eq!(error, Some(ErrorKind::AlreadyExists));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "important");
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn sets_the_socket_mode() {
+        for &mode in &[0o600, 0o660, 0o666] {
+            let path = socket_path(&format!("socket-mode-{:o}", mode));
+            let listener = Listener::bind_unix(&path, Some(mode)).unwrap();
+
+            let permissions = fs::metadata(&path).unwrap().permissions();
+            assert_eq!(permissions.mode() & 0o777, mode, "{:o}", mode);
+
+            drop(listener);
+            fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn addresses_loopback_for_unspecified_ips() {
+        let listener = Listener::Tcp(TcpListener::bind("0.0.0.0:0").unwrap());
+        match listener.address().unwrap() {
+            Address::Tcp(addr) => assert_eq!(addr.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            other => panic!("{:?}", other),
+        }
+        assert!(listener.address().unwrap().connect().is_ok());
+    }
+}
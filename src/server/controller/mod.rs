@@ -0,0 +1,213 @@
+use std::sync::Arc;
+
+use server::request::Request;
+use server::response::Response;
+use server::router::{Methods, Middleware, RouterAction};
+use server::status::StatusCode;
+
+/// The route param holding the record's id, eg `req.params["id"]` in `show`.
+pub const ID_PARAM: &str = "id";
+
+/// The actions of a RESTful resource, routed with `router.resource("/dogs", DogController)`:
+///
+/// * `GET /dogs` to `index`, listing them
+/// * `GET /dogs/new` to `new`, a form for adding one
+/// * `POST /dogs` to `create`
+/// * `GET /dogs/:id` to `show`
+/// * `GET /dogs/:id/edit` to `edit`, a form for changing one
+/// * `PATCH` or `PUT /dogs/:id` to `update`
+/// * `DELETE /dogs/:id` to `destroy`
+///
+/// HTML forms can only GET and POST, so a POST with a `_method=PATCH` field
+/// (or an `X-HTTP-Method-Override` header) is routed as a PATCH, and likewise PUT and DELETE.
+/// Only the actions in `ACTIONS` are routed, so the others answer `405 Method Not Allowed`
+/// (or `404 Not Found` where no action shares their path) and are left out of `Allow`.
+pub trait Controller: Send + Sync + 'static {
+    /// The actions the controller implements, eg `&[Action::Index, Action::Show]`.
+    const ACTIONS: &'static [Action];
+
+    fn index(&self, request: Request, response: Response) {
+        not_implemented(request, response)
+    }
+
+    fn show(&self, request: Request, response: Response) {
+        not_implemented(request, response)
+    }
+
+    // named for the conventional action, not a constructor.
+    #[allow(clippy::new_ret_no_self, clippy::wrong_self_convention)]
+    fn new(&self, request: Request, response: Response) {
+        not_implemented(request, response)
+    }
+
+    fn create(&self, request: Request, response: Response) {
+        not_implemented(request, response)
+    }
+
+    fn edit(&self, request: Request, response: Response) {
+        not_implemented(request, response)
+    }
+
+    fn update(&self, request: Request, response: Response) {
+        not_implemented(request, response)
+    }
+
+    fn destroy(&self, request: Request, response: Response) {
+        not_implemented(request, response)
+    }
+}
+
+/// An action listed in `ACTIONS` without a method of its own, which is a bug in the controller.
+fn not_implemented(_request: Request, mut response: Response) {
+    let _ = response
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .content_type("text/plain; charset=utf-8")
+        .send("Internal Server Error");
+}
+
+/// One of a `Controller`'s actions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Index,
+    Show,
+    New,
+    Create,
+    Edit,
+    Update,
+    Destroy,
+}
+
+impl Action {
+    pub const ALL: [Action; 7] = [
+        Action::Index,
+        Action::Show,
+        Action::New,
+        Action::Create,
+        Action::Edit,
+        Action::Update,
+        Action::Destroy,
+    ];
+
+    /// The methods routed to the action.
+    pub(crate) fn methods(&self) -> Vec<Methods> {
+        match *self {
+            Action::Index | Action::Show | Action::New | Action::Edit => vec![Methods::GET],
+            Action::Create => vec![Methods::POST],
+            Action::Update => vec![Methods::PATCH, Methods::PUT],
+            Action::Destroy => vec![Methods::DELETE],
+        }
+    }
+
+    /// The action's path under the resource's.
+    pub(crate) fn suffix(&self) -> &'static str {
+        match *self {
+            Action::Index | Action::Create => "",
+            Action::New => "/new",
+            Action::Show | Action::Update | Action::Destroy => "/:id",
+            Action::Edit => "/:id/edit",
+        }
+    }
+
+    fn dispatch<C: Controller>(&self, controller: &C, request: Request, response: Response) {
+        match *self {
+            Action::Index => controller.index(request, response),
+            Action::Show => controller.show(request, response),
+            Action::New => controller.new(request, response),
+            Action::Create => controller.create(request, response),
+            Action::Edit => controller.edit(request, response),
+            Action::Update => controller.update(request, response),
+            Action::Destroy => controller.destroy(request, response),
+        }
+    }
+}
+
+/// Middleware with the actions it's limited to, `None` for all of them.
+type ScopedMiddleware = (Arc<dyn Middleware>, Option<Vec<Action>>);
+
+/// A controller with the options for routing it, mount it with `Router::resource_with`.
+///
+/// `Resource::new(DogController).except(&[Action::Destroy]).before_only(&[Action::Create], require_login)`
+pub struct Resource<C: Controller> {
+    controller: Arc<C>,
+    actions: Vec<Action>,
+    /// Middleware in the order it runs.
+    middleware: Vec<ScopedMiddleware>,
+}
+
+impl<C: Controller> Resource<C> {
+    /// Routes every action the controller implements.
+    pub fn new(controller: C) -> Resource<C> {
+        Resource {
+            controller: Arc::new(controller),
+            actions: C::ACTIONS.to_vec(),
+            middleware: Vec::new(),
+        }
+    }
+
+    /// Routes just these of the controller's actions.
+    pub fn only(mut self, actions: &[Action]) -> Resource<C> {
+        self.actions.retain(|a| actions.contains(a));
+        self
+    }
+
+    /// Routes every one of the controller's actions but these.
+    pub fn except(mut self, actions: &[Action]) -> Resource<C> {
+        self.actions.retain(|a| !actions.contains(a));
+        self
+    }
+
+    /// Runs middleware before every action.
+    pub fn before<M: Middleware>(mut self, middleware: M) -> Resource<C> {
+        self.middleware.push((Arc::new(middleware), None));
+        self
+    }
+
+    /// Runs middleware before just these actions, eg a login check on the ones that change things.
+    pub fn before_only<M: Middleware>(mut self, actions: &[Action], middleware: M) -> Resource<C> {
+        self.middleware.push((Arc::new(middleware), Some(actions.to_vec())));
+        self
+    }
+
+    /// The handler for each routed action, with its middleware.
+    pub(crate) fn actions(&self) -> Vec<(Action, Arc<dyn RouterAction>)> {
+        self.actions
+            .iter()
+            .map(|&action| {
+                let middleware = self.middleware
+                    .iter()
+                    .filter(|(_, only)| only.as_ref().map(|o| o.contains(&action)).unwrap_or(true))
+                    .map(|(m, _)| m.clone())
+                    .collect();
+
+                let handler: Arc<dyn RouterAction> = Arc::new(ResourceAction {
+                    controller: self.controller.clone(),
+                    action,
+                    middleware,
+                });
+                (action, handler)
+            })
+            .collect()
+    }
+}
+
+struct ResourceAction<C: Controller> {
+    controller: Arc<C>,
+    action: Action,
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl<C: Controller> RouterAction for ResourceAction<C> {
+    fn call(&self, mut request: Request, mut response: Response) {
+        for middleware in &self.middleware {
+            if !middleware.before(&mut request, &mut response) {
+                return;
+            }
+        }
+
+        self.action.dispatch(&*self.controller, request, response);
+    }
+
+    fn accepts_method_override(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,176 @@
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// The last formatted `Date` header, as `(unix seconds, formatted)`.
+static CACHED: Mutex<Option<(u64, String)>> = Mutex::new(None);
+
+/// The current time as an IMF-fixdate, eg `Sun, 06 Nov 1994 08:49:37 GMT`.
+///
+/// Formatting only happens once a second, every other call reuses the cached string.
+pub fn now() -> String {
+    let secs = unix_seconds(SystemTime::now());
+    let mut cached = CACHED.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    if let Some((at, ref formatted)) = *cached {
+        if at == secs {
+            return formatted.clone();
+        }
+    }
+
+    let formatted = format_seconds(secs);
+    *cached = Some((secs, formatted.clone()));
+    formatted
+}
+
+/// Formats a time as an IMF-fixdate.
+pub fn format(time: SystemTime) -> String {
+    format_seconds(unix_seconds(time))
+}
+
+/// Parses an HTTP date, the IMF-fixdate `format` writes or the obsolete RFC 850
+/// and asctime forms older clients may still send. `None` if it isn't one.
+pub fn parse(value: &str) -> Option<SystemTime> {
+    let tokens = value
+        .split(|c: char| c.is_whitespace() || c == ',' || c == '-')
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<&str>>();
+
+    let mut month = None;
+    let mut clock = None;
+    let mut numbers = Vec::new();
+    for token in tokens {
+        if token.contains(':') {
+            clock = Some(token);
+        } else if let Ok(n) = token.parse::<u32>() {
+            numbers.push((n, token.len()));
+        } else if let Some(i) = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(token)) {
+            month = Some(i as u32 + 1);
+        }
+    }
+
+    // the day always comes before the year, in every format.
+    let (month, clock) = (month?, clock?);
+    let (day, year) = match numbers.as_slice() {
+        [(day, _), (year, digits)] => {
+            let year = match *digits {
+                2 if *year < 70 => 2000 + year,
+                2 => 1900 + year,
+                _ => *year,
+            };
+            (*day, year)
+        }
+        _ => return None,
+    };
+
+    let mut clock = clock.split(':').map(|t| t.parse::<u64>());
+    let (hour, minute, second) = match (clock.next(), clock.next(), clock.next(), clock.next()) {
+        (Some(Ok(h)), Some(Ok(m)), Some(Ok(s)), None) if h < 24 && m < 60 && s <= 60 => (h, m, s),
+        _ => return None,
+    };
+
+    if day == 0 || day > 31 || year < 1970 {
+        return None;
+    }
+
+    let days = days_from_civil(i64::from(year), month, day) as u64;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86_400 + hour * 3600 + minute * 60 + second))
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn format_seconds(secs: u64) -> String {
+    let days = secs / 86_400;
+    let rem = secs % 86_400;
+    let (year, month, day) = civil_from_days(days as i64);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60
+    )
+}
+
+/// Turns days since 1970-01-01 into `(year, month, day)`, from Howard Hinnant's date algorithms.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+/// The inverse of `civil_from_days`.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_http_date_format() {
+        // 1994-11-06 08:49:37 GMT
+        let expected = Some(UNIX_EPOCH + Duration::from_secs(784_111_777));
+
+        let cases: &[(&str, Option<SystemTime>)] = &[
+            ("Sun, 06 Nov 1994 08:49:37 GMT", expected),
+            ("Sunday, 06-Nov-94 08:49:37 GMT", expected),
+            ("Sun Nov  6 08:49:37 1994", expected),
+            ("Thu, 01 Jan 1970 00:00:00 GMT", Some(UNIX_EPOCH)),
+            ("Sun, 06 Nov 1994 24:00:00 GMT", None),
+            ("Sun, 32 Nov 1994 08:49:37 GMT", None),
+            ("Sun, 06 Nov 1969 08:49:37 GMT", None),
+            ("Sun, 06 Nov 1994", None),
+            ("yesterday", None),
+            ("", None),
+        ];
+
+        for &(value, expected) in cases {
+            assert_eq!(parse(value), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn formats_imf_fixdates() {
+        let cases: &[(u64, &str)] = &[
+            (0, "Thu, 01 Jan 1970 00:00:00 GMT"),
+            (784_111_777, "Sun, 06 Nov 1994 08:49:37 GMT"),
+            (951_782_400, "Tue, 29 Feb 2000 00:00:00 GMT"),
+            (4_102_444_799, "Thu, 31 Dec 2099 23:59:59 GMT"),
+        ];
+
+        for &(secs, expected) in cases {
+            let time = UNIX_EPOCH + Duration::from_secs(secs);
+            assert_eq!(format(time), expected);
+            assert_eq!(parse(expected), Some(time));
+        }
+    }
+}
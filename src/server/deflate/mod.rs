@@ -0,0 +1,1044 @@
+//! DEFLATE (RFC 1951) and the zlib (RFC 1950) and gzip (RFC 1952) wrappers around it,
+//! kept in the crate so the server has no dependencies.
+
+use std::cmp::{self, Reverse};
+use std::collections::BinaryHeap;
+use std::error::Error as StdError;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+/// Base length for each length code, 257 to 285.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+/// Base distance for each distance code, 0 to 29.
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order code length code lengths are sent in.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: usize = 15;
+/// How many earlier positions with the same hash are tried, more compresses better but slower.
+const MAX_CHAIN: usize = 64;
+/// Roughly how many input bytes go in each block, so each gets codes fitted to its own content
+/// and a stored block, capped at 65535 bytes, can always hold one.
+const BLOCK_SIZE: usize = 60 * 1024;
+const END_OF_BLOCK: usize = 256;
+
+/// Compresses data into a raw DEFLATE stream.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let tokens = lz77(data);
+    let mut out = BitWriter::new();
+
+    let mut start = 0;
+    let mut raw_start = 0;
+    loop {
+        let mut end = start;
+        let mut raw_end = raw_start;
+        while end < tokens.len() && raw_end - raw_start < BLOCK_SIZE {
+            raw_end += tokens[end].raw_len();
+            end += 1;
+        }
+
+        let last = end == tokens.len();
+        write_block(&mut out, &tokens[start..end], &data[raw_start..raw_end], last);
+
+        start = end;
+        raw_start = raw_end;
+        if last {
+            break;
+        }
+    }
+
+    out.finish()
+}
+
+/// Compresses data into a zlib stream, what HTTP calls `deflate`.
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    // 32K window, default compression level, no dictionary.
+    let mut out = vec![0x78, 0x9c];
+    out.extend(deflate(data));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Compresses data into a gzip stream.
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    // magic, deflate, no flags, no mtime, no extra flags, unknown OS.
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    out.extend(deflate(data));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut c = i as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+        }
+        *entry = c;
+    }
+
+    !data.iter().fold(!0u32, |crc, &b| {
+        table[((crc ^ u32::from(b)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // sums can't overflow a u32 within 5552 bytes.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= 65_521;
+        b %= 65_521;
+    }
+    (b << 16) | a
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+impl Token {
+    fn raw_len(&self) -> usize {
+        match *self {
+            Token::Literal(_) => 1,
+            Token::Match { length, .. } => length as usize,
+        }
+    }
+}
+
+/// Finds repeats within the last 32K with hash chains, greedily taking the longest match.
+fn lz77(data: &[u8]) -> Vec<Token> {
+    const NONE: usize = usize::MAX;
+    let mut head = vec![NONE; 1 << HASH_BITS];
+    let mut prev = vec![NONE; WINDOW];
+    let mut tokens = Vec::with_capacity(data.len() / 2);
+
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_len, mut best_dist) = (0, 0);
+
+        if i + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - i);
+            let mut candidate = head[hash(data, i)];
+            let mut chain = 0;
+
+            while candidate != NONE && candidate < i && i - candidate <= WINDOW && chain < MAX_CHAIN {
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[i..i + max_len])
+                    .take_while(|&(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = i - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+
+                // the slot may have been reused by a newer position, which would loop.
+                let next = prev[candidate % WINDOW];
+                if next == NONE || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            tokens.push(Token::Match {
+                length: best_len as u16,
+                distance: best_dist as u16,
+            });
+            for j in i..i + best_len {
+                insert(data, j, &mut head, &mut prev);
+            }
+            i += best_len;
+        } else {
+            tokens.push(Token::Literal(data[i]));
+            insert(data, i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+
+    tokens
+}
+
+fn hash(data: &[u8], i: usize) -> usize {
+    let v = (u32::from(data[i]) << 16) | (u32::from(data[i + 1]) << 8) | u32::from(data[i + 2]);
+    (v.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Adds position `i` to the front of its hash chain.
+fn insert(data: &[u8], i: usize, head: &mut [usize], prev: &mut [usize]) {
+    if i + MIN_MATCH <= data.len() {
+        let h = hash(data, i);
+        prev[i % WINDOW] = head[h];
+        head[h] = i;
+    }
+}
+
+fn length_code(length: u16) -> usize {
+    LENGTH_BASE.iter().rposition(|&base| base <= length).unwrap_or(0)
+}
+
+fn dist_code(distance: u16) -> usize {
+    DIST_BASE.iter().rposition(|&base| base <= distance).unwrap_or(0)
+}
+
+fn fixed_lengths() -> (Vec<u8>, Vec<u8>) {
+    let mut lit = vec![8u8; 288];
+    for l in lit.iter_mut().take(256).skip(144) {
+        *l = 9;
+    }
+    for l in lit.iter_mut().take(280).skip(256) {
+        *l = 7;
+    }
+    (lit, vec![5u8; 30])
+}
+
+/// Writes one block, as whichever of stored, fixed or fitted codes comes out smallest.
+fn write_block(out: &mut BitWriter, tokens: &[Token], raw: &[u8], last: bool) {
+    let mut lit_freq = vec![0u32; 286];
+    let mut dist_freq = vec![0u32; 30];
+    let mut extra_bits = 0u64;
+    for token in tokens {
+        match *token {
+            Token::Literal(b) => lit_freq[b as usize] += 1,
+            Token::Match { length, distance } => {
+                let lc = length_code(length);
+                let dc = dist_code(distance);
+                lit_freq[257 + lc] += 1;
+                dist_freq[dc] += 1;
+                extra_bits += u64::from(LENGTH_EXTRA[lc]) + u64::from(DIST_EXTRA[dc]);
+            }
+        }
+    }
+    lit_freq[END_OF_BLOCK] = 1;
+
+    let (fixed_lit, fixed_dist) = fixed_lengths();
+    let dyn_lit = code_lengths(&lit_freq, 15);
+    let dyn_dist = code_lengths(&dist_freq, 15);
+    let header = DynamicHeader::new(&dyn_lit, &dyn_dist);
+
+    let cost = |lit: &[u8], dist: &[u8]| {
+        let lit_bits: u64 = lit_freq.iter().zip(lit).map(|(&f, &l)| u64::from(f) * u64::from(l)).sum();
+        let dist_bits: u64 = dist_freq.iter().zip(dist).map(|(&f, &l)| u64::from(f) * u64::from(l)).sum();
+        lit_bits + dist_bits + extra_bits
+    };
+    let fixed_cost = cost(&fixed_lit, &fixed_dist);
+    let dynamic_cost = cost(&dyn_lit, &dyn_dist) + header.cost();
+    // alignment, plus LEN and NLEN.
+    let stored_cost = 7 + 32 + 8 * raw.len() as u64;
+
+    out.write_bits(last as u32, 1);
+    if stored_cost <= fixed_cost.min(dynamic_cost) {
+        out.write_bits(0, 2);
+        out.align();
+        out.write_bits(raw.len() as u32, 16);
+        out.write_bits(!(raw.len() as u32) & 0xffff, 16);
+        out.write_bytes(raw);
+    } else if fixed_cost <= dynamic_cost {
+        out.write_bits(1, 2);
+        write_tokens(out, tokens, &fixed_lit, &fixed_dist);
+    } else {
+        out.write_bits(2, 2);
+        header.write(out);
+        write_tokens(out, tokens, &dyn_lit, &dyn_dist);
+    }
+}
+
+fn write_tokens(out: &mut BitWriter, tokens: &[Token], lit_lengths: &[u8], dist_lengths: &[u8]) {
+    let lit_codes = canonical_codes(lit_lengths);
+    let dist_codes = canonical_codes(dist_lengths);
+
+    for token in tokens {
+        match *token {
+            Token::Literal(b) => out.write_code(lit_codes[b as usize], lit_lengths[b as usize]),
+            Token::Match { length, distance } => {
+                let lc = length_code(length);
+                out.write_code(lit_codes[257 + lc], lit_lengths[257 + lc]);
+                out.write_bits(u32::from(length - LENGTH_BASE[lc]), LENGTH_EXTRA[lc]);
+
+                let dc = dist_code(distance);
+                out.write_code(dist_codes[dc], dist_lengths[dc]);
+                out.write_bits(u32::from(distance - DIST_BASE[dc]), DIST_EXTRA[dc]);
+            }
+        }
+    }
+    out.write_code(lit_codes[END_OF_BLOCK], lit_lengths[END_OF_BLOCK]);
+}
+
+/// The code lengths of a dynamic block, run length encoded with codes 16 to 18.
+struct DynamicHeader {
+    hlit: usize,
+    hdist: usize,
+    hclen: usize,
+    /// `(symbol, extra bits value)` pairs.
+    symbols: Vec<(usize, u32)>,
+    lengths: Vec<u8>,
+}
+
+impl DynamicHeader {
+    fn new(lit: &[u8], dist: &[u8]) -> DynamicHeader {
+        let hlit = cmp::max(257, lit.iter().rposition(|&l| l != 0).map_or(0, |p| p + 1));
+        let hdist = cmp::max(1, dist.iter().rposition(|&l| l != 0).map_or(0, |p| p + 1));
+        let all = lit[..hlit]
+            .iter()
+            .chain(&dist[..hdist])
+            .cloned()
+            .collect::<Vec<u8>>();
+
+        let mut symbols = Vec::new();
+        let mut i = 0;
+        while i < all.len() {
+            let len = all[i];
+            let run = all[i..].iter().take_while(|&&l| l == len).count();
+
+            if len == 0 && run >= 3 {
+                let run = run.min(138);
+                if run <= 10 {
+                    symbols.push((17, run as u32 - 3));
+                } else {
+                    symbols.push((18, run as u32 - 11));
+                }
+                i += run;
+            } else if len != 0 && run >= 4 {
+                // the first is sent as it is, 16 repeats the previous length.
+                symbols.push((len as usize, 0));
+                let run = (run - 1).min(6);
+                symbols.push((16, run as u32 - 3));
+                i += run + 1;
+            } else {
+                symbols.push((len as usize, 0));
+                i += 1;
+            }
+        }
+
+        let mut freq = vec![0u32; 19];
+        for &(symbol, _) in &symbols {
+            freq[symbol] += 1;
+        }
+        let lengths = code_lengths(&freq, 7);
+        let hclen = cmp::max(
+            4,
+            CODE_LENGTH_ORDER
+                .iter()
+                .rposition(|&s| lengths[s] != 0)
+                .map_or(0, |p| p + 1),
+        );
+
+        DynamicHeader {
+            hlit,
+            hdist,
+            hclen,
+            symbols,
+            lengths,
+        }
+    }
+
+    fn cost(&self) -> u64 {
+        let symbols: u64 = self.symbols
+            .iter()
+            .map(|&(s, _)| u64::from(self.lengths[s]) + u64::from(repeat_extra_bits(s)))
+            .sum();
+        14 + 3 * self.hclen as u64 + symbols
+    }
+
+    fn write(&self, out: &mut BitWriter) {
+        out.write_bits(self.hlit as u32 - 257, 5);
+        out.write_bits(self.hdist as u32 - 1, 5);
+        out.write_bits(self.hclen as u32 - 4, 4);
+        for &symbol in CODE_LENGTH_ORDER.iter().take(self.hclen) {
+            out.write_bits(u32::from(self.lengths[symbol]), 3);
+        }
+
+        let codes = canonical_codes(&self.lengths);
+        for &(symbol, extra) in &self.symbols {
+            out.write_code(codes[symbol], self.lengths[symbol]);
+            out.write_bits(extra, repeat_extra_bits(symbol));
+        }
+    }
+}
+
+fn repeat_extra_bits(symbol: usize) -> u8 {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+/// Huffman code lengths for the frequencies, no longer than `limit`.
+/// Codes that would be too long are avoided by flattening the frequencies and trying again.
+fn code_lengths(freq: &[u32], limit: u8) -> Vec<u8> {
+    let mut freq = freq.to_vec();
+
+    // a single used symbol still needs a complete code, so give it a partner.
+    let used = freq.iter().filter(|&&f| f > 0).count();
+    if used < 2 {
+        for f in freq.iter_mut().take(2) {
+            *f = (*f).max(1);
+        }
+    }
+
+    loop {
+        let lengths = huffman_lengths(&freq);
+        if lengths.iter().all(|&l| l <= limit) {
+            return lengths;
+        }
+        for f in freq.iter_mut().filter(|f| **f > 0) {
+            *f = (*f >> 1).max(1);
+        }
+    }
+}
+
+fn huffman_lengths(freq: &[u32]) -> Vec<u8> {
+    // leaves are the symbols, then internal nodes as `(left, right)`.
+    let mut children = Vec::new();
+    let mut heap = BinaryHeap::new();
+    for (symbol, &f) in freq.iter().enumerate() {
+        if f > 0 {
+            heap.push(Reverse((u64::from(f), symbol)));
+        }
+    }
+
+    while heap.len() > 1 {
+        let Reverse((a_weight, a)) = heap.pop().unwrap();
+        let Reverse((b_weight, b)) = heap.pop().unwrap();
+        children.push((a, b));
+        heap.push(Reverse((a_weight + b_weight, freq.len() + children.len() - 1)));
+    }
+
+    let mut lengths = vec![0u8; freq.len()];
+    let mut stack = match heap.pop() {
+        Some(Reverse((_, root))) => vec![(root, 0u8)],
+        None => return lengths,
+    };
+    while let Some((node, depth)) = stack.pop() {
+        if node < freq.len() {
+            lengths[node] = depth;
+        } else {
+            let (left, right) = children[node - freq.len()];
+            stack.push((left, depth + 1));
+            stack.push((right, depth + 1));
+        }
+    }
+
+    lengths
+}
+
+/// Assigns the canonical codes for a set of code lengths.
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let max = lengths.iter().cloned().max().unwrap_or(0) as usize;
+    let mut count = vec![0u16; max + 1];
+    for &l in lengths.iter().filter(|&&l| l > 0) {
+        count[l as usize] += 1;
+    }
+
+    let mut next = vec![0u16; max + 2];
+    let mut code = 0u16;
+    for bits in 1..=max {
+        code = (code + count[bits - 1]) << 1;
+        next[bits] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|&l| {
+            if l == 0 {
+                return 0;
+            }
+            let code = next[l as usize];
+            next[l as usize] += 1;
+            code
+        })
+        .collect()
+}
+
+/// Packs bits least significant first, as DEFLATE wants.
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u8,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            out: Vec::new(),
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    fn write_bits(&mut self, value: u32, count: u8) {
+        self.bits |= u64::from(value) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes go most significant bit first, so reverse them.
+    fn write_code(&mut self, code: u16, length: u8) {
+        let reversed = code.reverse_bits() >> (16 - u32::from(length));
+        self.write_bits(u32::from(reversed), length);
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            let pad = 8 - self.count;
+            self.write_bits(0, pad);
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.out.extend_from_slice(bytes);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.out
+    }
+}
+
+/// Why a compressed stream couldn't be decompressed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InflateError {
+    /// It decompresses to more than the limit allowed.
+    TooLarge,
+    /// It isn't valid, or is cut short.
+    Invalid(&'static str),
+}
+
+impl Display for InflateError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            InflateError::TooLarge => write!(f, "Decompressed data is over the size limit"),
+            InflateError::Invalid(reason) => write!(f, "Invalid compressed data: {}", reason),
+        }
+    }
+}
+
+impl StdError for InflateError {}
+
+/// Decompresses a raw DEFLATE stream, failing once the output passes `limit` bytes
+/// so a small bomb can't fill the memory.
+pub fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>, InflateError> {
+    let mut out = Vec::new();
+    Inflater::new(data, &mut out, limit).run()?;
+    Ok(out)
+}
+
+/// Decompresses a zlib stream, checking its checksum.
+pub fn unzlib(data: &[u8], limit: usize) -> Result<Vec<u8>, InflateError> {
+    if data.len() < 6 {
+        return Err(InflateError::Invalid("truncated zlib stream"));
+    }
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0f != 8 || (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 {
+        return Err(InflateError::Invalid("bad zlib header"));
+    }
+    if flg & 0x20 != 0 {
+        return Err(InflateError::Invalid("zlib preset dictionaries aren't supported"));
+    }
+
+    let mut out = Vec::new();
+    let used = Inflater::new(&data[2..], &mut out, limit).run()?;
+    let checksum = data
+        .get(2 + used..2 + used + 4)
+        .ok_or(InflateError::Invalid("truncated zlib stream"))?;
+
+    if u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) != adler32(&out) {
+        return Err(InflateError::Invalid("zlib checksum mismatch"));
+    }
+    Ok(out)
+}
+
+/// Decompresses a gzip stream, every member of it if there are several, checking their checksums.
+pub fn gunzip(data: &[u8], limit: usize) -> Result<Vec<u8>, InflateError> {
+    const FHCRC: u8 = 2;
+    const FEXTRA: u8 = 4;
+    const FNAME: u8 = 8;
+    const FCOMMENT: u8 = 16;
+    let truncated = InflateError::Invalid("truncated gzip stream");
+
+    let mut out = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        if rest.len() < 18 || rest[0] != 0x1f || rest[1] != 0x8b || rest[2] != 8 {
+            return Err(InflateError::Invalid("bad gzip header"));
+        }
+
+        let flags = rest[3];
+        let mut pos = 10;
+        if flags & FEXTRA != 0 {
+            let extra = rest.get(pos..pos + 2).ok_or_else(|| truncated.clone())?;
+            pos += 2 + (usize::from(extra[0]) | usize::from(extra[1]) << 8);
+        }
+        for &flag in &[FNAME, FCOMMENT] {
+            if flags & flag != 0 {
+                let end = rest
+                    .get(pos..)
+                    .and_then(|r| r.iter().position(|&b| b == 0))
+                    .ok_or_else(|| truncated.clone())?;
+                pos += end + 1;
+            }
+        }
+        if flags & FHCRC != 0 {
+            pos += 2;
+        }
+        let body = rest.get(pos..).ok_or_else(|| truncated.clone())?;
+
+        let start = out.len();
+        let used = Inflater::new(body, &mut out, limit).run()?;
+        let trailer = body.get(used..used + 8).ok_or_else(|| truncated.clone())?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+
+        if crc != crc32(&out[start..]) || size != (out.len() - start) as u32 {
+            return Err(InflateError::Invalid("gzip checksum mismatch"));
+        }
+        rest = &body[used + 8..];
+    }
+
+    Ok(out)
+}
+
+/// A canonical Huffman code, decoded a bit at a time as in zlib's `puff`.
+struct Huffman {
+    /// How many codes there are of each length.
+    count: [u16; 16],
+    /// The symbols, ordered by code.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, InflateError> {
+        let mut count = [0u16; 16];
+        for &l in lengths {
+            count[l as usize] += 1;
+        }
+
+        // more codes of a length than there is room for.
+        let mut left = 1i32;
+        for &c in count.iter().skip(1) {
+            left = (left << 1) - i32::from(c);
+            if left < 0 {
+                return Err(InflateError::Invalid("over-subscribed Huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + count[len];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &l) in lengths.iter().enumerate() {
+            if l != 0 {
+                symbols[offsets[l as usize] as usize] = symbol as u16;
+                offsets[l as usize] += 1;
+            }
+        }
+
+        Ok(Huffman { count, symbols })
+    }
+}
+
+struct Inflater<'a> {
+    input: &'a [u8],
+    pos: usize,
+    bits: u32,
+    count: u8,
+    out: &'a mut Vec<u8>,
+    /// Where this stream's output starts, earlier output can't be referred back to.
+    start: usize,
+    limit: usize,
+}
+
+impl<'a> Inflater<'a> {
+    fn new(input: &'a [u8], out: &'a mut Vec<u8>, limit: usize) -> Inflater<'a> {
+        let start = out.len();
+        Inflater {
+            input,
+            pos: 0,
+            bits: 0,
+            count: 0,
+            out,
+            start,
+            limit,
+        }
+    }
+
+    /// Decompresses every block, returning how many bytes of input were used.
+    fn run(mut self) -> Result<usize, InflateError> {
+        loop {
+            let last = self.read_bits(1)? == 1;
+            match self.read_bits(2)? {
+                0 => self.stored()?,
+                1 => {
+                    let (lit, dist) = fixed_lengths();
+                    self.codes(&Huffman::new(&lit)?, &Huffman::new(&dist)?)?
+                }
+                2 => self.dynamic()?,
+                _ => return Err(InflateError::Invalid("reserved block type")),
+            }
+
+            if last {
+                return Ok(self.pos);
+            }
+        }
+    }
+
+    fn read_bits(&mut self, n: u8) -> Result<u32, InflateError> {
+        while self.count < n {
+            let byte = *self.input
+                .get(self.pos)
+                .ok_or(InflateError::Invalid("unexpected end of data"))?;
+            self.bits |= u32::from(byte) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+
+        let value = self.bits & ((1u32 << n) - 1);
+        self.bits >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    fn push(&mut self, byte: u8) -> Result<(), InflateError> {
+        if self.out.len() >= self.limit {
+            return Err(InflateError::TooLarge);
+        }
+        self.out.push(byte);
+        Ok(())
+    }
+
+    fn stored(&mut self) -> Result<(), InflateError> {
+        self.bits = 0;
+        self.count = 0;
+
+        let header = self.input
+            .get(self.pos..self.pos + 4)
+            .ok_or(InflateError::Invalid("unexpected end of data"))?;
+        let len = u16::from(header[0]) | u16::from(header[1]) << 8;
+        let nlen = u16::from(header[2]) | u16::from(header[3]) << 8;
+        if len != !nlen {
+            return Err(InflateError::Invalid("stored block length mismatch"));
+        }
+        self.pos += 4;
+
+        let block = self.input
+            .get(self.pos..self.pos + len as usize)
+            .ok_or(InflateError::Invalid("unexpected end of data"))?;
+        if self.out.len() + block.len() > self.limit {
+            return Err(InflateError::TooLarge);
+        }
+        self.out.extend_from_slice(block);
+        self.pos += len as usize;
+        Ok(())
+    }
+
+    fn dynamic(&mut self) -> Result<(), InflateError> {
+        let nlen = self.read_bits(5)? as usize + 257;
+        let ndist = self.read_bits(5)? as usize + 1;
+        let ncode = self.read_bits(4)? as usize + 4;
+        if nlen > 286 || ndist > 30 {
+            return Err(InflateError::Invalid("too many length or distance codes"));
+        }
+
+        let mut code_lengths = [0u8; 19];
+        for &symbol in CODE_LENGTH_ORDER.iter().take(ncode) {
+            code_lengths[symbol] = self.read_bits(3)? as u8;
+        }
+        let code_lengths = Huffman::new(&code_lengths)?;
+
+        let mut lengths = vec![0u8; nlen + ndist];
+        let mut i = 0;
+        while i < lengths.len() {
+            let symbol = self.decode(&code_lengths)?;
+            let (value, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 if i > 0 => (lengths[i - 1], 3 + self.read_bits(2)? as usize),
+                16 => return Err(InflateError::Invalid("repeat with no previous length")),
+                17 => (0, 3 + self.read_bits(3)? as usize),
+                _ => (0, 11 + self.read_bits(7)? as usize),
+            };
+            if i + repeat > lengths.len() {
+                return Err(InflateError::Invalid("too many code lengths"));
+            }
+            for l in &mut lengths[i..i + repeat] {
+                *l = value;
+            }
+            i += repeat;
+        }
+
+        if lengths[END_OF_BLOCK] == 0 {
+            return Err(InflateError::Invalid("no end of block code"));
+        }
+
+        let lit = Huffman::new(&lengths[..nlen])?;
+        let dist = Huffman::new(&lengths[nlen..])?;
+        self.codes(&lit, &dist)
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> Result<u16, InflateError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= self.read_bits(1)? as i32;
+            let count = i32::from(huffman.count[len]);
+            if code - first < count {
+                return Ok(huffman.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(InflateError::Invalid("invalid Huffman code"))
+    }
+
+    fn codes(&mut self, lit: &Huffman, dist: &Huffman) -> Result<(), InflateError> {
+        loop {
+            let symbol = self.decode(lit)? as usize;
+            if symbol < 256 {
+                self.push(symbol as u8)?;
+                continue;
+            }
+            if symbol == END_OF_BLOCK {
+                return Ok(());
+            }
+
+            let lc = symbol - 257;
+            if lc >= LENGTH_BASE.len() {
+                return Err(InflateError::Invalid("invalid length code"));
+            }
+            let length = LENGTH_BASE[lc] as usize + self.read_bits(LENGTH_EXTRA[lc])? as usize;
+
+            let dc = self.decode(dist)? as usize;
+            if dc >= DIST_BASE.len() {
+                return Err(InflateError::Invalid("invalid distance code"));
+            }
+            let distance = DIST_BASE[dc] as usize + self.read_bits(DIST_EXTRA[dc])? as usize;
+            if distance > self.out.len() - self.start {
+                return Err(InflateError::Invalid("distance too far back"));
+            }
+
+            // copied a byte at a time, a match can overlap what it's copying.
+            let from = self.out.len() - distance;
+            for i in 0..length {
+                let byte = self.out[from + i];
+                self.push(byte)?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes that don't compress, from a linear congruential generator.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn lines(count: usize) -> Vec<u8> {
+        (0..count).map(|i| format!("line {}: value {}\n", i, i * i)).collect::<String>().into_bytes()
+    }
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// The type of the first block, 0 stored, 1 fixed and 2 dynamic codes.
+    fn block_type(stream: &[u8]) -> u8 {
+        (stream[0] >> 1) & 3
+    }
+
+    #[test]
+    fn round_trips_every_block_type() {
+        let cases: &[(Vec<u8>, u8)] = &[
+            (noise(1000), 0),
+            (b"hello".to_vec(), 1),
+            (lines(40), 2),
+        ];
+
+        for &(ref data, kind) in cases {
+            let compressed = deflate(data);
+            assert_eq!(block_type(&compressed), kind);
+            assert_eq!(inflate(&compressed, data.len()).unwrap(), *data);
+            assert_eq!(unzlib(&zlib(data), data.len()).unwrap(), *data);
+            assert_eq!(gunzip(&gzip(data), data.len()).unwrap(), *data);
+        }
+    }
+
+    #[test]
+    fn round_trips_several_blocks() {
+        let mut data = lines(5000);
+        data.extend(noise(100 * 1024));
+        data.extend(vec![b'a'; 70 * 1024]);
+
+        let compressed = gzip(&data);
+        assert!(compressed.len() < data.len());
+        assert_eq!(gunzip(&compressed, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn round_trips_nothing() {
+        assert_eq!(inflate(&deflate(b""), 0).unwrap(), b"");
+        assert_eq!(gunzip(&gzip(b""), 0).unwrap(), b"");
+    }
+
+    #[test]
+    fn decodes_streams_from_zlib() {
+        let zlib_fixed = unhex(
+            "78da0bc94855282ccd4cce56482aca2fcf5348cbaf50c82acd2d2856c82f4b2d5228014ae72456552aa4e4a7\
+             eb29848c2a1e553caa98da8a0147a5431c",
+        );
+        let expected = b"The quick brown fox jumps over the lazy dog. ".repeat(20);
+        assert_eq!(unzlib(&zlib_fixed, 1000).unwrap(), expected);
+
+        let gzip_member = unhex("1f8b0800000000000203cb48cdc9c9d75128cf2fca49e10200537424f40d000000");
+        assert_eq!(gunzip(&gzip_member, 100).unwrap(), b"hello, world\n");
+
+        let raw_dynamic = unhex(
+            "4d923d0e42310c83774ef18ed0c4fde5360c0c484f6c707e408d1db6da8a922f4ecfc7f37e94ebf1be9dafefe3\
+             72feb451dbd64e5db706f5dabaaabe6fa3d1f0b68d4e035131d4327a4c1a3d862c1a33284c9856089aa4ce2a\
+             c15a8d4e86e48b6996c82b882ca1496d3df76095c07db297d0e19cb8725f6628fa4a7a177dadaccaa867f472\
+             d1378f892efa3682cb45df49efa2efaa12fd502fd10f4dcce8c905d12fd243f48b3b22b32f8c02197e6162c8\
+             f48dc1a2e529b9017a7a3c13b48281d7c4fcbb39e76a096bdfbff101",
+        );
+        assert_eq!(block_type(&raw_dynamic), 2);
+        assert_eq!(inflate(&raw_dynamic, 1000).unwrap(), lines(40));
+    }
+
+    #[test]
+    fn decodes_concatenated_gzip_members() {
+        let mut stream = gzip(b"first ");
+        stream.extend(gzip(b"second"));
+
+        assert_eq!(gunzip(&stream, 100).unwrap(), b"first second");
+    }
+
+    #[test]
+    fn truncated_streams_fail() {
+        let data = lines(40);
+        let streams = [deflate(&data), zlib(&data), gzip(&data), deflate(&noise(100))];
+
+        for (kind, stream) in streams.iter().enumerate() {
+            for end in 0..stream.len() {
+                let cut = &stream[..end];
+                let result = match kind {
+                    1 => unzlib(cut, data.len()),
+                    2 => gunzip(cut, data.len()),
+                    _ => inflate(cut, data.len()),
+                };
+                // an empty gzip stream has no members, so decodes to nothing.
+                if kind == 2 && end == 0 {
+                    assert_eq!(result, Ok(Vec::new()));
+                    continue;
+                }
+                assert!(result.is_err(), "stream {} cut at {} of {}", kind, end, stream.len());
+            }
+        }
+    }
+
+    #[test]
+    fn bad_checksums_fail() {
+        let data = lines(40);
+
+        let mut zlibbed = zlib(&data);
+        let last = zlibbed.len() - 1;
+        zlibbed[last] ^= 1;
+        assert_eq!(unzlib(&zlibbed, data.len()), Err(InflateError::Invalid("zlib checksum mismatch")));
+
+        // the CRC, then the length.
+        for back in &[8, 1] {
+            let mut gzipped = gzip(&data);
+            let at = gzipped.len() - back;
+            gzipped[at] ^= 1;
+            assert_eq!(gunzip(&gzipped, data.len()), Err(InflateError::Invalid("gzip checksum mismatch")));
+        }
+    }
+
+    #[test]
+    fn bad_headers_fail() {
+        let data = b"hello".to_vec();
+
+        let mut zlibbed = zlib(&data);
+        zlibbed[1] ^= 1;
+        assert_eq!(unzlib(&zlibbed, 100), Err(InflateError::Invalid("bad zlib header")));
+
+        let mut gzipped = gzip(&data);
+        gzipped[0] = 0;
+        assert_eq!(gunzip(&gzipped, 100), Err(InflateError::Invalid("bad gzip header")));
+
+        // block type 3 is reserved.
+        assert_eq!(inflate(&[0x07], 100), Err(InflateError::Invalid("reserved block type")));
+    }
+
+    #[test]
+    fn output_is_held_to_the_limit() {
+        let cases: &[(Vec<u8>, &str)] = &[
+            (noise(1000), "stored"),
+            (b"hello".to_vec(), "fixed"),
+            (lines(40), "dynamic"),
+            (vec![0; 1024 * 1024], "a bomb"),
+        ];
+
+        for &(ref data, name) in cases {
+            let compressed = deflate(data);
+            assert_eq!(inflate(&compressed, data.len()).unwrap(), *data, "{}", name);
+            assert_eq!(inflate(&compressed, data.len() - 1), Err(InflateError::TooLarge), "{}", name);
+            assert_eq!(gunzip(&gzip(data), data.len() - 1), Err(InflateError::TooLarge), "{}", name);
+        }
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+}
@@ -0,0 +1,94 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+
+/// Values attached to a single request, one of each type, for middleware to hand on
+/// to the middleware and handler after it, eg the user a login check found.
+///
+/// `req.extensions.insert(CurrentUser(id))` then `req.extensions.get::<CurrentUser>()`
+#[derive(Default)]
+pub struct Extensions {
+    values: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    pub fn new() -> Extensions {
+        Extensions::default()
+    }
+
+    /// Attaches a value, returning the one of the same type it replaces.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.values
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast::<T>().ok())
+            .map(|old| *old)
+    }
+
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.values.get(&TypeId::of::<T>()).and_then(|v| v.downcast_ref::<T>())
+    }
+
+    pub fn get_mut<T: Send + Sync + 'static>(&mut self) -> Option<&mut T> {
+        self.values.get_mut(&TypeId::of::<T>()).and_then(|v| v.downcast_mut::<T>())
+    }
+
+    /// Takes a value off, eg for a handler to own it.
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> Option<T> {
+        self.values
+            .remove(&TypeId::of::<T>())
+            .and_then(|v| v.downcast::<T>().ok())
+            .map(|v| *v)
+    }
+
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.values.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+}
+
+impl Debug for Extensions {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Extensions({} values)", self.values.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct CurrentUser(u32);
+
+    #[test]
+    fn holds_one_value_per_type() {
+        let mut extensions = Extensions::new();
+        assert!(extensions.is_empty());
+        assert_eq!(extensions.insert(CurrentUser(1)), None);
+        assert_eq!(extensions.insert("a str"), None);
+
+        assert_eq!(extensions.insert(CurrentUser(2)), Some(CurrentUser(1)));
+        assert_eq!(extensions.len(), 2);
+        assert_eq!(extensions.get::<CurrentUser>(), Some(&CurrentUser(2)));
+        assert_eq!(extensions.get::<&str>(), Some(&"a str"));
+        assert_eq!(extensions.get::<u32>(), None);
+    }
+
+    #[test]
+    fn changes_and_takes_values() {
+        let mut extensions = Extensions::new();
+        extensions.insert(CurrentUser(1));
+
+        extensions.get_mut::<CurrentUser>().unwrap().0 = 5;
+        assert!(extensions.contains::<CurrentUser>());
+        assert_eq!(extensions.remove::<CurrentUser>(), Some(CurrentUser(5)));
+        assert!(!extensions.contains::<CurrentUser>());
+        assert_eq!(extensions.remove::<CurrentUser>(), None);
+    }
+}
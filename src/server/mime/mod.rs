@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Sent for files with an extension nobody knows about.
+pub const DEFAULT: &str = "application/octet-stream";
+
+/// The built in extension to MIME type table.
+fn builtin(extension: &str) -> Option<&'static str> {
+    let mime = match extension {
+        // text
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "txt" | "text" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "md" | "markdown" => "text/markdown; charset=utf-8",
+        "xml" => "application/xml; charset=utf-8",
+        "json" | "map" => "application/json",
+        "jsonld" => "application/ld+json",
+        "webmanifest" => "application/manifest+json",
+        "ics" => "text/calendar; charset=utf-8",
+
+        // images
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "bmp" => "image/bmp",
+        "tif" | "tiff" => "image/tiff",
+
+        // fonts
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "eot" => "application/vnd.ms-fontobject",
+
+        // audio & video
+        "mp3" => "audio/mpeg",
+        "ogg" | "oga" => "audio/ogg",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        "m4a" => "audio/mp4",
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "ogv" => "video/ogg",
+        "mov" => "video/quicktime",
+
+        // documents & archives
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "7z" => "application/x-7z-compressed",
+        "rar" => "application/vnd.rar",
+        "wasm" => "application/wasm",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "epub" => "application/epub+zip",
+        _ => return None,
+    };
+
+    Some(mime)
+}
+
+/// Looks up the MIME type for a file from its extension,
+/// falling back to the built in table for anything not overridden.
+///
+/// `server.mime_type("wasm", "application/wasm")` adds an override for a server.
+#[derive(Debug, Clone, Default)]
+pub struct MimeTypes {
+    overrides: HashMap<String, String>,
+}
+
+impl MimeTypes {
+    pub fn new() -> MimeTypes {
+        MimeTypes::default()
+    }
+
+    /// Sets the type for an extension, without the leading `.`.
+    pub fn insert<E: ToString, T: ToString>(&mut self, extension: E, mime: T) {
+        self.overrides
+            .insert(extension.to_string().to_lowercase(), mime.to_string());
+    }
+
+    /// The type for an extension, if known.
+    pub fn get(&self, extension: &str) -> Option<&str> {
+        let extension = extension.to_lowercase();
+
+        match self.overrides.get(&extension) {
+            Some(v) => Some(v.as_str()),
+            None => builtin(&extension),
+        }
+    }
+
+    /// The type for a file, `application/octet-stream` if the extension is unknown.
+    pub fn for_path(&self, path: &Path) -> &str {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(|e| self.get(e))
+            .unwrap_or(DEFAULT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_types_by_extension() {
+        let types = MimeTypes::new();
+        let cases: &[(&str, &str)] = &[
+            ("index.html", "text/html; charset=utf-8"),
+            ("site.CSS", "text/css; charset=utf-8"),
+            ("photos/DOG.JPG", "image/jpeg"),
+            ("app.min.js", "text/javascript; charset=utf-8"),
+            ("archive.tar.gz", "application/gzip"),
+            ("font.woff2", "font/woff2"),
+            ("README", DEFAULT),
+            (".hidden", DEFAULT),
+            ("data.unknown", DEFAULT),
+            ("dir.html/file", DEFAULT),
+        ];
+
+        for &(path, mime) in cases {
+            assert_eq!(types.for_path(Path::new(path)), mime, "{}", path);
+        }
+    }
+
+    #[test]
+    fn overrides_take_the_place_of_the_builtin_types() {
+        let mut types = MimeTypes::new();
+        types.insert("LOG", "text/plain; charset=utf-8");
+        types.insert("json", "application/vnd.api+json");
+
+        assert_eq!(types.for_path(Path::new("server.log")), "text/plain; charset=utf-8");
+        assert_eq!(types.get("JSON"), Some("application/vnd.api+json"));
+        assert_eq!(types.get("png"), Some("image/png"));
+        assert_eq!(MimeTypes::new().get("log"), None);
+    }
+}
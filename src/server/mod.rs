@@ -1,24 +1,107 @@
 pub mod router;
 pub mod request;
 pub mod response;
-mod thread_pool;
+pub mod thread_pool;
+pub mod shutdown;
+pub mod bind;
+pub mod connection;
+pub mod test_client;
+pub mod status;
+pub mod date;
+pub mod mime;
+pub mod static_files;
+pub mod range;
+pub mod conditional;
+pub mod deflate;
+pub mod compression;
+pub mod negotiate;
+pub mod redirect;
+pub mod controller;
+pub mod value;
+pub mod template;
+pub mod repository;
+pub mod state;
+pub mod extensions;
 
 
-use std::net::{TcpListener, TcpStream};
 use std::io::{Error, ErrorKind};
 use std::sync::{Arc, Mutex};
+use std::cmp::Reverse;
 use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+#[cfg(unix)]
+use std::path::Path;
 
-use self::request::Request;
-use self::router::{Router, RouterAction};
-use self::response::Response;
+use self::request::{Rejection, Request, DEFAULT_MAX_BODY_SIZE};
+use self::router::{Router, RouteMatch};
+use self::response::{RequestInfo, Response};
+use self::status::StatusCode;
+use self::mime::MimeTypes;
+use self::compression::Compression;
+use self::template::Templates;
+use self::state::State;
+use self::thread_pool::{JobPanic, PanicHandler, Task};
+use self::shutdown::Shutdown;
+use self::bind::{Bindable, BoundServer, ListenerOptions};
+use self::connection::{Connection, Listener, Transport};
+
+/// The first and longest waits before retrying a failed accept.
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(10);
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+const DEFAULT_SERVER_NAME: &str = "rusty_server";
 
 pub struct ServerInner {
     inner_routers: Mutex<HashMap<String, Router>>,
+    /// Routers only reachable through listeners marked as internal.
+    internal_routers: Mutex<HashMap<String, Router>>,
+}
+
+/// What to do with a new connection when the job queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// Stop accepting until a worker frees up a spot in the queue.
+    Block,
+    /// Answer straight away with `503 Service Unavailable`,
+    /// asking the client to come back after `retry_after` seconds.
+    Shed { retry_after: u32 },
+}
+
+/// A connection waiting for a worker, kept whole so it can still be shed if the queue is full.
+struct Incoming {
+    server: Arc<Server>,
+    stream: Connection,
+    options: Arc<ListenerOptions>,
+}
+
+impl Task for Incoming {
+    fn run(self: Box<Self>) {
+        let _ = self.server.parse_incoming(self.stream, &self.options);
+    }
 }
 
+#[derive(Clone)]
 pub struct Server {
     inner: Arc<ServerInner>,
+    panic_handler: Option<Arc<PanicHandler>>,
+    queue_capacity: Option<usize>,
+    queue_policy: QueuePolicy,
+    grace_period: Duration,
+    threads: usize,
+    server_name: Option<String>,
+    mime_types: Arc<MimeTypes>,
+    compression: Option<Arc<Compression>>,
+    templates: Option<Arc<Templates>>,
+    development: bool,
+    state: State,
+    max_body_size: usize,
+}
+
+impl Default for Server {
+    fn default() -> Server {
+        Server::new()
+    }
 }
 
 impl Server {
@@ -26,14 +109,149 @@ impl Server {
         Server {
             inner: Arc::new(ServerInner { 
                 inner_routers: Mutex::new(HashMap::new()),
+                internal_routers: Mutex::new(HashMap::new()),
             }),
+            panic_handler: None,
+            queue_capacity: None,
+            queue_policy: QueuePolicy::Block,
+            grace_period: Duration::from_secs(30),
+            threads: 4,
+            server_name: Some(DEFAULT_SERVER_NAME.to_string()),
+            mime_types: Arc::new(MimeTypes::new()),
+            compression: None,
+            templates: None,
+            development: false,
+            state: State::new(),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
         }
     }
 
+    /// Overrides the `Content-Type` sent for files with an extension (without the `.`).
+    ///
+    /// `server.mime_type("log", "text/plain; charset=utf-8")`
+    pub fn mime_type<E: ToString, T: ToString>(&mut self, extension: E, mime: T) -> &mut Server {
+        Arc::make_mut(&mut self.mime_types).insert(extension, mime);
+        self
+    }
+
+    /// Compresses response bodies for clients that accept it, off by default.
+    ///
+    /// `server.compression(Compression::new().threshold(512))`
+    pub fn compression(&mut self, compression: Compression) -> &mut Server {
+        self.compression = Some(Arc::new(compression));
+        self
+    }
+
+    /// The templates `res.render` renders from.
+    ///
+    /// `server.templates(Templates::new("views"))`
+    pub fn templates(&mut self, templates: Templates) -> &mut Server {
+        self.templates = Some(Arc::new(templates));
+        self
+    }
+
+    /// Development mode, off by default. Templates are compiled again when their files change
+    /// and ones that fail are shown on an error page, and files are sent with `Cache-Control: no-cache`
+    /// so browsers pick up changes straight away. Off, templates are compiled once and failures
+    /// are a plain `500 Internal Server Error`.
+    ///
+    /// `server.development(cfg!(debug_assertions))`
+    pub fn development(&mut self, on: bool) -> &mut Server {
+        self.development = on;
+        self
+    }
+
+    /// Shares a value with every handler, read with `req.state::<T>()`. One value is kept per type,
+    /// and a router's own state takes the place of the server's for the same type.
+    ///
+    /// `server.with_state(Config::load()).with_state(pool)`
+    pub fn with_state<T: Send + Sync + 'static>(&mut self, value: T) -> &mut Server {
+        self.state.insert(value);
+        self
+    }
+
+    /// The largest request body accepted, 16MB by default. Compressed bodies are held to it
+    /// once decompressed too, bigger ones are answered with `413 Content Too Large`.
+    pub fn max_body_size(&mut self, bytes: usize) -> &mut Server {
+        self.max_body_size = bytes;
+        self
+    }
+
+    /// Sets the `Server` header sent with every response, `rusty_server` by default.
+    pub fn server_header<T: ToString>(&mut self, name: T) -> &mut Server {
+        self.server_name = Some(name.to_string());
+        self
+    }
+
+    /// Stops sending a `Server` header.
+    pub fn no_server_header(&mut self) -> &mut Server {
+        self.server_name = None;
+        self
+    }
+
+    /// Sets how many worker threads handle requests, 4 by default.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the count is zero.
+    pub fn threads(&mut self, count: usize) -> &mut Server {
+        assert!(count > 0);
+
+        self.threads = count;
+        self
+    }
+
+    /// How long a shutdown waits for the requests in flight to finish, 30 seconds by default.
+    pub fn grace_period(&mut self, grace: Duration) -> &mut Server {
+        self.grace_period = grace;
+        self
+    }
+
+    /// Limits how many connections can wait for a worker, and what happens to the rest.
+    /// By default the queue is unbounded.
+    ///
+    /// `server.queue(256, QueuePolicy::Shed { retry_after: 5 })`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the capacity is zero.
+    pub fn queue(&mut self, capacity: usize, policy: QueuePolicy) -> &mut Server {
+        assert!(capacity > 0);
+
+        self.queue_capacity = Some(capacity);
+        self.queue_policy = policy;
+        self
+    }
+
+    /// Sets a hook that is called whenever a handler panics.
+    /// The worker running the handler survives the panic, by default it is just logged.
+    ///
+    /// `server.on_panic(|panic| eprintln!("handler died: {}", panic.message))`
+    pub fn on_panic<F>(&mut self, handler: F) -> &mut Server
+    where
+        F: Fn(&JobPanic) + Send + Sync + 'static,
+    {
+        self.panic_handler = Some(Arc::new(handler));
+        self
+    }
+
     /// Registers a new router for the server.
     pub fn register<T: ToString>(&mut self, path: T, router: Router) -> &mut Server {
-        let inner = self.inner.clone();
-        let mut routers = inner.inner_routers.lock().expect("Could not lock!");
+        Server::insert_router(&self.inner.inner_routers, path, router);
+        self
+    }
+
+    /// Registers a router that is only served on listeners bound as internal,
+    /// eg admin pages that shouldn't be reachable from the public address.
+    ///
+    /// `server.register_internal("/admin", admin_router)`
+    pub fn register_internal<T: ToString>(&mut self, path: T, router: Router) -> &mut Server {
+        Server::insert_router(&self.inner.internal_routers, path, router);
+        self
+    }
+
+    fn insert_router<T: ToString>(routers: &Mutex<HashMap<String, Router>>, path: T, router: Router) {
+        let mut routers = routers.lock().expect("Could not lock!");
         let empty_path = "/".to_string();
 
         let path = if path.to_string() == empty_path {
@@ -43,69 +261,244 @@ impl Server {
         };
 
         routers.insert(path, router);
-
-        self
     }
 
     // Parsing!
-    pub fn parse_incoming(&self, mut stream: &mut TcpStream) -> Result<(), Error> {
-        let mut request = Request::new(&mut stream)?;
-        let (method, params) = self.find_route(&request.method, &request.route)?;
-        if params.len() > 0 {
+    pub fn parse_incoming<S: Transport>(&self, mut stream: S, listener: &ListenerOptions) -> Result<(), Error> {
+        let mut request = match Request::with_max_body(&mut stream, self.max_body_size) {
+            Ok(v) => v,
+            Err(e) => return self.reject(stream, e),
+        };
+
+        let mut found = self.route(&request.method, &request.route, listener.internal);
+        // a POST standing in for a PUT, PATCH or DELETE, only for the resource routes expecting one.
+        if let Some(requested) = request.method_override() {
+            if let Ok(overridden) = self.route(&requested, &request.route, listener.internal) {
+                if (overridden.0).0.accepts_method_override() {
+                    request.method = requested;
+                    found = Ok(overridden);
+                }
+            }
+        }
+
+        let ((method, params), state) = match found {
+            Ok(v) => v,
+            Err(e) => return self.reject(stream, e),
+        };
+        if !params.is_empty() {
             request.params = Some(params);
         }
+        request.state = self.state.overlaid(&state);
+
+        let mut response = self.new_response(stream);
+        response.request = RequestInfo::new(&request);
 
-        let stream_copy = stream.try_clone().unwrap();
-        let response = Response::new(stream_copy);
-        
         method.call(request, response);
         Ok(())
     }
 
-    // finds the specified route's action
-    pub fn find_route(&self, method: &String, path: &String) -> Result<(Arc<RouterAction>, HashMap<String, String>), Error> {
-        let inner = self.inner.clone();
-        let inner = inner.inner_routers.lock();
+    /// Answers a request that can't be handled, if the error says why, then returns the error.
+    fn reject<S: Transport>(&self, stream: S, error: Error) -> Result<(), Error> {
+        if let Some(rejection) = Rejection::from_error(&error) {
+            let mut response = self.new_response(stream);
+            if let Rejection::MethodNotAllowed(ref allowed) = *rejection {
+                response.headers.insert("Allow".to_string(), allowed.to_string());
+            }
+            response
+                .status(rejection.status())
+                .content_type("text/plain; charset=utf-8")
+                .send(rejection)?;
+        }
+        Err(error)
+    }
+
+    // finds the specified route's action, looking through the internal routers first if allowed.
+    pub fn find_route(&self, method: &str, path: &str, internal: bool) -> Result<RouteMatch, Error> {
+        self.route(method, path, internal).map(|(found, _)| found)
+    }
+
+    /// `find_route`, along with the state of the router the route is on.
+    fn route(&self, method: &str, path: &str, internal: bool) -> Result<(RouteMatch, State), Error> {
+        let mut missed = None;
+        if internal {
+            match Server::find_in(&self.inner.internal_routers, method, path) {
+                Err(e) if e.kind() == ErrorKind::NotFound => missed = Some(e),
+                result => return result,
+            }
+        }
+
+        match Server::find_in(&self.inner.inner_routers, method, path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(closer_miss(missed, e)),
+            result => result,
+        }
+    }
+
+    fn find_in(routers: &Mutex<HashMap<String, Router>>, method: &str, path: &str) -> Result<(RouteMatch, State), Error> {
+        let inner = routers.lock();
         let routers = match inner {
             Ok(v) => v,
-            _ => return Err(Error::new(ErrorKind::Other, "Lock Error")), 
+            _ => return Err(Error::other("Lock Error")), 
         };
 
-        let routers = routers.iter();
+        // the longest mount gets the first look, so `/api` is tried before `/`.
+        let mut routers = routers.iter().collect::<Vec<(&String, &Router)>>();
+        routers.sort_by_key(|r| Reverse(r.0.len()));
 
+        let path = path.trim_start();
+        let mut missed = None;
         for (routing, router) in routers {
-            let routing = routing.to_string();
-            if path.trim_left().starts_with(&routing) {
-                let (method, params) = router.find_route(
-                    method.to_string(),
-                    path.trim_left_matches(&routing).to_string(),
-                )?;
-    
-                return Ok((method, params));
+            let rest = match path.strip_prefix(routing.as_str()) {
+                Some(rest) if rest.is_empty() || rest.starts_with('/') || routing.ends_with('/') => rest,
+                _ => continue,
+            };
+            let rest = if rest.is_empty() { "/" } else { rest };
+
+            match router.find_route(method.to_string(), rest.to_string()) {
+                Err(e) if e.kind() == ErrorKind::NotFound => missed = Some(closer_miss(missed, e)),
+                result => return result.map(|found| (found, router.state.clone())),
             }
         }
 
-        return Err(Error::new(ErrorKind::NotFound, "404"));
+        Err(missed.unwrap_or_else(|| Rejection::NotFound.into_error()))
+    }
+
+    /// A response with the headers the server adds to everything.
+    fn new_response<S: Transport>(&self, stream: S) -> Response {
+        let mut response = Response::new(stream);
+        response.mime_types = self.mime_types.clone();
+        response.compression = self.compression.clone();
+        response.templates = self.templates.clone();
+        response.development = self.development;
+        if let Some(ref name) = self.server_name {
+            response.headers.insert("Server".to_string(), name.to_string());
+        }
+
+        response
+    }
+
+    /// Turns a connection away because the server is too busy to queue it.
+    fn shed<S: Transport>(&self, stream: S, retry_after: u32) -> Result<(), Error> {
+        let mut response = self.new_response(stream);
+        response.headers.insert("Retry-After".to_string(), retry_after.to_string());
+        response.status(StatusCode::SERVICE_UNAVAILABLE).send("Service Unavailable")?;
+        Ok(())
+    }
+
+    /// Binds the server to an address, ready to `listen` or `spawn`.
+    /// A bare port binds the loopback address (IPV4), port 0 lets the OS pick one.
+    /// Chain `.bind` on the result to accept on more addresses at once.
+    ///
+    /// `server.bind(3030)?.listen()` or `server.bind("0.0.0.0:3030")?.bind("[::]:3030")?.spawn()`
+    pub fn bind<A: Bindable>(&self, address: A) -> Result<BoundServer, Error> {
+        self.bind_with(address, ListenerOptions::new())
+    }
+
+    /// Binds the server to an address with options for that listener,
+    /// eg `ListenerOptions::new().internal()` to also serve internal routers on it.
+    pub fn bind_with<A: Bindable>(&self, address: A, options: ListenerOptions) -> Result<BoundServer, Error> {
+        BoundServer::new(self.clone()).bind_with(address, options)
     }
 
-    /// Attaches the Router to a port with an optional address (default loopback address IPV4)
+    /// Binds the server to a unix socket, cleaning up a stale socket file if one is left over.
+    /// Set `ListenerOptions::mode` to control who can connect to it.
     ///
-    /// # Panics if the post is closed or any other connection issue.
-    pub fn listen(self, port: i16, address: Option<String>, threads: Option<usize>) {
-        let address = address.unwrap_or(String::from("127.0.0.1"));
-        let binding =
-            TcpListener::bind(format!("{}:{}", address, port)).expect("Couldn't bind on port!");
-        let pool = thread_pool::ThreadPool::new(threads.unwrap_or(4));
+    /// `server.bind_unix("/run/app.sock", ListenerOptions::new().mode(0o660))?.listen()`
+    #[cfg(unix)]
+    pub fn bind_unix<P: AsRef<Path>>(&self, path: P, options: ListenerOptions) -> Result<BoundServer, Error> {
+        BoundServer::new(self.clone()).bind_unix(path, options)
+    }
+
+    /// Accepts on every listener until `shutdown` is triggered, then drains the pool.
+    fn serve(self, listeners: Vec<(Listener, ListenerOptions)>, shutdown: Shutdown) {
+        let handler = self.panic_handler.clone().unwrap_or_else(|| Arc::new(thread_pool::log_panic));
+        let pool = thread_pool::ThreadPool::build(self.threads, self.queue_capacity, handler);
+        let pool = Arc::new(pool);
+        let grace = self.grace_period;
         let shared_self = Arc::new(self);
 
-        for stream in binding.incoming() {
-            let mut stream = match stream {
+        // the accepts block, so shutting down connects to each listener to wake it.
+        for (binding, _) in &listeners {
+            match binding.address() {
+                Ok(address) => shutdown.wake_on_trigger(address),
+                Err(e) => eprintln!("Couldn't find the address of {} to wake it on shutdown: {}", binding, e),
+            }
+        }
+
+        let acceptors = listeners
+            .into_iter()
+            .map(|(binding, options)| {
+                let server = shared_self.clone();
+                let pool = pool.clone();
+                let shutdown = shutdown.clone();
+
+                thread::spawn(move || server.accept(binding, options, &pool, &shutdown))
+            })
+            .collect::<Vec<_>>();
+
+        for acceptor in acceptors {
+            let _ = acceptor.join();
+        }
+
+        // every acceptor has let go of the pool by now.
+        if let Ok(pool) = Arc::try_unwrap(pool) {
+            pool.shutdown(grace);
+        }
+    }
+
+    /// The accept loop for a single listener.
+    fn accept(
+        self: Arc<Self>,
+        binding: Listener,
+        options: ListenerOptions,
+        pool: &thread_pool::ThreadPool,
+        shutdown: &Shutdown,
+    ) {
+        let options = Arc::new(options);
+        let mut backoff = ACCEPT_BACKOFF_MIN;
+
+        while !shutdown.is_triggered() {
+            let stream = match binding.accept() {
                 Ok(v) => v,
-                Err(e) => panic!(e),  // TODO: Redirect to internal Router error page.
+                Err(e) => {
+                    // usually running out of file descriptors, give the workers
+                    // a chance to close some before trying again.
+                    eprintln!("Couldn't accept a connection on {} ({}), retrying in {:?}", binding, e, backoff);
+                    thread::sleep(backoff);
+                    backoff = ::std::cmp::min(backoff * 2, ACCEPT_BACKOFF_MAX);
+                    continue;
+                }
             };
+            backoff = ACCEPT_BACKOFF_MIN;
 
-            let self_clone = shared_self.clone();
-            pool.execute(move || { self_clone.parse_incoming(&mut stream); });
+            // the connection that woke the loop up to stop.
+            if shutdown.is_triggered() {
+                break;
+            }
+
+            let incoming = Incoming {
+                server: self.clone(),
+                stream,
+                options: options.clone(),
+            };
+            match self.queue_policy {
+                QueuePolicy::Block => pool.execute(incoming),
+                QueuePolicy::Shed { retry_after } => {
+                    if let Err(incoming) = pool.try_execute(incoming) {
+                        let _ = self.shed(incoming.stream, retry_after);
+                    }
+                }
+            }
         }
     }
 }
+
+/// Of two routers' misses, the one to answer with, a `405` tells the client more than a `404`.
+fn closer_miss(first: Option<Error>, next: Error) -> Error {
+    match first {
+        Some(first) => match Rejection::from_error(&next) {
+            Some(&Rejection::MethodNotAllowed(_)) if Rejection::from_error(&first) == Some(&Rejection::NotFound) => next,
+            _ => first,
+        },
+        None => next,
+    }
+}
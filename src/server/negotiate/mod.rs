@@ -0,0 +1,250 @@
+//! Picking between what the server can offer and what an `Accept`, `Accept-Language`,
+//! `Accept-Charset` or `Accept-Encoding` header asks for.
+//!
+//! Each takes the header, `None` if the request had none, and the offers in the server's
+//! order of preference. The offer with the highest q-value wins, the earlier one on a tie.
+
+/// Splits a header like `text/html;level=1, */*;q=0.5` into `(value, q)` pairs,
+/// with any parameters besides `q` kept on the value.
+pub fn quality_list(header: &str) -> Vec<(String, f32)> {
+    header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(|p| p.trim());
+            let value = parts.next().filter(|v| !v.is_empty())?;
+
+            let mut quality = 1.0;
+            let mut params = Vec::new();
+            for param in parts {
+                match param.strip_prefix("q=").or_else(|| param.strip_prefix("Q=")) {
+                    Some(q) => quality = q.trim().parse::<f32>().unwrap_or(0.0).clamp(0.0, 1.0),
+                    None => params.push(param),
+                }
+            }
+
+            let value = if params.is_empty() {
+                value.to_string()
+            } else {
+                format!("{};{}", value, params.join(";"))
+            };
+            Some((value, quality))
+        })
+        .collect()
+}
+
+/// Picks the offer whose best matching range has the highest q-value.
+/// `specificity` says how closely a range matches an offer, `None` if it doesn't,
+/// and the most specific matching range is the one that counts.
+fn best<'a, F>(header: Option<&str>, offers: &[&'a str], specificity: F) -> Option<&'a str>
+where
+    F: Fn(&str, &str) -> Option<u8>,
+{
+    let ranges = match header {
+        Some(v) if !v.trim().is_empty() => quality_list(v),
+        _ => return offers.first().cloned(),
+    };
+
+    let mut chosen: Option<(&str, f32)> = None;
+    for offer in offers {
+        let quality = ranges
+            .iter()
+            .filter_map(|&(ref range, q)| specificity(range, offer).map(|s| (s, q)))
+            .max_by(|a, b| a.0.cmp(&b.0))
+            .map(|(_, q)| q)
+            .unwrap_or(0.0);
+
+        if quality > 0.0 && chosen.map(|(_, q)| quality > q).unwrap_or(true) {
+            chosen = Some((offer, quality));
+        }
+    }
+
+    chosen.map(|(offer, _)| offer)
+}
+
+/// The best media type from an `Accept` header, eg `text/html` over `*/*;q=0.8`.
+/// Parameters like `charset` on the offers are ignored when matching.
+pub fn media_type<'a>(accept: Option<&str>, offers: &[&'a str]) -> Option<&'a str> {
+    best(accept, offers, |range, offer| {
+        let (range_type, range_sub, range_params) = split_media(range);
+        let (offer_type, offer_sub, offer_params) = split_media(offer);
+
+        if range_type == "*" && range_sub == "*" {
+            Some(0)
+        } else if range_type != offer_type {
+            None
+        } else if range_sub == "*" {
+            Some(1)
+        } else if range_sub != offer_sub {
+            None
+        } else if range_params.is_empty() {
+            Some(2)
+        } else if range_params.iter().all(|p| offer_params.contains(p)) {
+            // `text/html;level=1` is more specific than `text/html`.
+            Some(3)
+        } else {
+            None
+        }
+    })
+}
+
+/// The best language from an `Accept-Language` header. A range matches the tags
+/// it's a prefix of, so `en` matches `en-GB`, and longer ranges count over shorter ones.
+pub fn language<'a>(accept_language: Option<&str>, offers: &[&'a str]) -> Option<&'a str> {
+    best(accept_language, offers, |range, offer| {
+        if range == "*" {
+            return Some(0);
+        }
+
+        let range = range.to_lowercase();
+        let offer = offer.to_lowercase();
+        let matches = offer == range
+            || (offer.starts_with(&range) && offer.as_bytes().get(range.len()) == Some(&b'-'));
+
+        if matches {
+            Some(1 + range.split('-').count().min(254) as u8)
+        } else {
+            None
+        }
+    })
+}
+
+/// The best charset from an `Accept-Charset` header.
+pub fn charset<'a>(accept_charset: Option<&str>, offers: &[&'a str]) -> Option<&'a str> {
+    best(accept_charset, offers, |range, offer| {
+        if range == "*" {
+            Some(0)
+        } else if range.eq_ignore_ascii_case(offer) {
+            Some(1)
+        } else {
+            None
+        }
+    })
+}
+
+/// The best content coding from an `Accept-Encoding` header. Unlike the others, a request
+/// without the header, or with an empty one, gets nothing since it's always fine to send
+/// a body uncompressed.
+pub fn encoding<'a>(accept_encoding: Option<&str>, offers: &[&'a str]) -> Option<&'a str> {
+    if accept_encoding.map(|v| v.trim().is_empty()).unwrap_or(true) {
+        return None;
+    }
+
+    best(accept_encoding, offers, |range, offer| {
+        if range == "*" {
+            Some(0)
+        } else if range.eq_ignore_ascii_case(offer)
+            || (range.eq_ignore_ascii_case("x-gzip") && offer.eq_ignore_ascii_case("gzip"))
+        {
+            Some(1)
+        } else {
+            None
+        }
+    })
+}
+
+/// Splits `text/html; charset=utf-8` into `("text", "html", ["charset=utf-8"])`, lowercased.
+fn split_media(media: &str) -> (String, String, Vec<String>) {
+    let mut parts = media.split(';');
+    let essence = parts.next().unwrap_or("").trim().to_lowercase();
+    let params = parts
+        .map(|p| p.trim().to_lowercase().replace(' ', ""))
+        .filter(|p| !p.is_empty())
+        .collect();
+
+    let mut essence = essence.splitn(2, '/');
+    let main = essence.next().unwrap_or("").to_string();
+    let sub = essence.next().unwrap_or("").to_string();
+    (main, sub, params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Case<'a> = (Option<&'a str>, &'a [&'a str], Option<&'a str>);
+
+    #[test]
+    fn splits_quality_lists() {
+        let list = quality_list("text/html;level=1, */*;q=0.5, ;q=1, a;q=x, b;Q=2");
+
+        assert_eq!(
+            list,
+            vec![
+                ("text/html;level=1".to_string(), 1.0),
+                ("*/*".to_string(), 0.5),
+                ("a".to_string(), 0.0),
+                ("b".to_string(), 1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn picks_media_types_by_q_value() {
+        let cases: &[Case] = &[
+            (None, &["application/json", "text/html"], Some("application/json")),
+            (Some(""), &["application/json", "text/html"], Some("application/json")),
+            (Some("text/html"), &["application/json", "text/html"], Some("text/html")),
+            (Some("text/html;q=0.5, application/json"), &["text/html", "application/json"], Some("application/json")),
+            (Some("*/*;q=0.1, text/html"), &["application/json", "text/html"], Some("text/html")),
+            // ties go to the server's earlier offer.
+            (Some("text/html, application/json"), &["application/json", "text/html"], Some("application/json")),
+            // the most specific matching range sets the q-value.
+            (
+                Some("text/*;q=0.3, text/html;q=0.7, */*;q=0.5"),
+                &["text/plain", "text/html", "image/png"],
+                Some("text/html"),
+            ),
+            (Some("text/*, text/html;q=0"), &["text/html", "text/plain"], Some("text/plain")),
+            (Some("TEXT/HTML;Q=0.1, application/json;q=0.2"), &["text/html", "application/json"], Some("application/json")),
+            (Some("text/html;q=0"), &["text/html"], None),
+            (Some("application/json"), &["text/html; charset=utf-8"], None),
+            (Some("text/html"), &["text/html; charset=utf-8"], Some("text/html; charset=utf-8")),
+        ];
+
+        for &(header, offers, expected) in cases {
+            assert_eq!(media_type(header, offers), expected, "{:?} {:?}", header, offers);
+        }
+    }
+
+    #[test]
+    fn picks_languages_by_prefix_and_q_value() {
+        let cases: &[Case] = &[
+            (Some("en-GB, en;q=0.8, fr;q=0.5"), &["fr", "en", "en-GB"], Some("en-GB")),
+            (Some("en-GB;q=0.5, en"), &["en-GB", "en-US"], Some("en-US")),
+            (Some("en"), &["fr-CA", "en-US"], Some("en-US")),
+            (Some("fr-CA"), &["fr"], None),
+            (Some("en"), &["english"], None),
+            (Some("*;q=0.1, de"), &["en", "de"], Some("de")),
+            (Some("en-us"), &["EN-US"], Some("EN-US")),
+        ];
+
+        for &(header, offers, expected) in cases {
+            assert_eq!(language(header, offers), expected, "{:?} {:?}", header, offers);
+        }
+    }
+
+    #[test]
+    fn picks_charsets_and_encodings() {
+        let charsets: &[Case] = &[
+            (Some("utf-8;q=0.5, iso-8859-1"), &["utf-8", "iso-8859-1"], Some("iso-8859-1")),
+            (Some("*"), &["utf-8"], Some("utf-8")),
+            (Some("UTF-8"), &["utf-8"], Some("utf-8")),
+        ];
+        for &(header, offers, expected) in charsets {
+            assert_eq!(charset(header, offers), expected, "{:?} {:?}", header, offers);
+        }
+
+        let encodings: &[Case] = &[
+            (None, &["gzip"], None),
+            (Some(" "), &["gzip"], None),
+            (Some("gzip, deflate"), &["deflate", "gzip"], Some("deflate")),
+            (Some("gzip;q=0.5, deflate;q=0.9"), &["gzip", "deflate"], Some("deflate")),
+            (Some("x-gzip"), &["gzip"], Some("gzip")),
+            (Some("gzip;q=0, *"), &["gzip", "deflate"], Some("deflate")),
+            (Some("identity"), &["gzip"], None),
+        ];
+        for &(header, offers, expected) in encodings {
+            assert_eq!(encoding(header, offers), expected, "{:?} {:?}", header, offers);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+/// More ranges than this in one request are ignored and the whole file is sent,
+/// to stop a client asking for thousands of tiny pieces. Overlapping ones are merged.
+const MAX_RANGES: usize = 32;
+
+/// What to do with a `Range` header for a resource of a known length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ByteRanges {
+    /// The header is missing, malformed, or for a unit other than bytes; send everything.
+    Full,
+    /// None of the ranges overlap the resource, answer `416 Range Not Satisfiable`.
+    Unsatisfiable,
+    /// Inclusive `(first, last)` byte positions in order, with any that overlap or touch merged,
+    /// so no byte is sent twice.
+    Ranges(Vec<(u64, u64)>),
+}
+
+/// Parses a `Range` header like `bytes=0-499, 1000-, -500` against a resource of `length` bytes.
+pub fn parse(header: &str, length: u64) -> ByteRanges {
+    let header = header.trim();
+    let specs = match header.find('=') {
+        Some(i) if header[..i].trim().eq_ignore_ascii_case("bytes") => &header[i + 1..],
+        _ => return ByteRanges::Full,
+    };
+
+    let specs = specs
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<&str>>();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return ByteRanges::Full;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let mut bounds = spec.splitn(2, '-');
+        let (first, last) = match (bounds.next(), bounds.next()) {
+            (Some(f), Some(l)) => (f.trim(), l.trim()),
+            _ => return ByteRanges::Full,
+        };
+
+        let range = if first.is_empty() {
+            // `-500`, the last 500 bytes.
+            let suffix = match last.parse::<u64>() {
+                Ok(v) => v,
+                Err(_) => return ByteRanges::Full,
+            };
+            if suffix == 0 || length == 0 {
+                None
+            } else {
+                Some((length.saturating_sub(suffix), length - 1))
+            }
+        } else {
+            let first = match first.parse::<u64>() {
+                Ok(v) => v,
+                Err(_) => return ByteRanges::Full,
+            };
+            let last = if last.is_empty() {
+                None
+            } else {
+                match last.parse::<u64>() {
+                    Ok(v) if v >= first => Some(v),
+                    _ => return ByteRanges::Full,
+                }
+            };
+
+            if first >= length {
+                None
+            } else {
+                Some((first, last.map(|l| l.min(length - 1)).unwrap_or(length - 1)))
+            }
+        };
+
+        if let Some(range) = range {
+            ranges.push(range);
+        }
+    }
+
+    if ranges.is_empty() {
+        ByteRanges::Unsatisfiable
+    } else {
+        ByteRanges::Ranges(merge(ranges))
+    }
+}
+
+/// Sorts ranges and joins the ones that overlap or are next to each other.
+fn merge(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort();
+
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some(previous) if first <= previous.1.saturating_add(1) => previous.1 = previous.1.max(last),
+            _ => merged.push((first, last)),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ranges() {
+        let cases: &[(&str, u64, ByteRanges)] = &[
+            ("bytes=0-499", 1000, ByteRanges::Ranges(vec![(0, 499)])),
+            ("bytes=500-", 1000, ByteRanges::Ranges(vec![(500, 999)])),
+            ("bytes=900-5000", 1000, ByteRanges::Ranges(vec![(900, 999)])),
+            ("Bytes = 0-0", 1000, ByteRanges::Ranges(vec![(0, 0)])),
+            // suffixes, the last n bytes.
+            ("bytes=-500", 1000, ByteRanges::Ranges(vec![(500, 999)])),
+            ("bytes=-5000", 1000, ByteRanges::Ranges(vec![(0, 999)])),
+            ("bytes=-0", 1000, ByteRanges::Unsatisfiable),
+            // several, in order with overlapping and adjacent ones merged.
+            ("bytes=0-99,,200-299", 1000, ByteRanges::Ranges(vec![(0, 99), (200, 299)])),
+            ("bytes=-10, 0-9", 1000, ByteRanges::Ranges(vec![(0, 9), (990, 999)])),
+            ("bytes=0-99, 50-149, -10", 1000, ByteRanges::Ranges(vec![(0, 149), (990, 999)])),
+            ("bytes=0-99, 100-199", 1000, ByteRanges::Ranges(vec![(0, 199)])),
+            ("bytes=0-99, 101-199", 1000, ByteRanges::Ranges(vec![(0, 99), (101, 199)])),
+            ("bytes=10-19, 0-999, 500-", 1000, ByteRanges::Ranges(vec![(0, 999)])),
+            ("bytes=0-, -1", 1000, ByteRanges::Ranges(vec![(0, 999)])),
+            // unsatisfiable ones are dropped, and it's only a 416 if none are left.
+            ("bytes=1000-", 1000, ByteRanges::Unsatisfiable),
+            ("bytes=1000-2000, 0-9", 1000, ByteRanges::Ranges(vec![(0, 9)])),
+            ("bytes=0-", 0, ByteRanges::Unsatisfiable),
+            ("bytes=-10", 0, ByteRanges::Unsatisfiable),
+            // anything malformed is ignored in favour of the whole resource.
+            ("bytes=500-499", 1000, ByteRanges::Full),
+            ("bytes=a-b", 1000, ByteRanges::Full),
+            ("bytes=0-99, x", 1000, ByteRanges::Full),
+            ("bytes=", 1000, ByteRanges::Full),
+            ("items=0-9", 1000, ByteRanges::Full),
+            ("0-9", 1000, ByteRanges::Full),
+        ];
+
+        for &(header, length, ref expected) in cases {
+            assert_eq!(parse(header, length), *expected, "{} of {}", header, length);
+        }
+    }
+
+    #[test]
+    fn too_many_ranges_are_ignored() {
+        let header = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+        assert_eq!(parse(&header, 1000), ByteRanges::Full);
+    }
+
+    #[test]
+    fn repeated_ranges_are_sent_once() {
+        let header = format!("bytes={}", vec!["0-"; MAX_RANGES].join(","));
+        assert_eq!(parse(&header, 1000), ByteRanges::Ranges(vec![(0, 999)]));
+
+        let header = format!("bytes={}", vec!["0-0, 0-1"; MAX_RANGES / 2].join(","));
+        assert_eq!(parse(&header, 1000), ByteRanges::Ranges(vec![(0, 1)]));
+    }
+}
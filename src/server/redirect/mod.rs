@@ -0,0 +1,183 @@
+/// Resolves a redirect target against the path of the request being redirected,
+/// following RFC 3986. Absolute URLs, including `//host/path`, are kept as they are.
+///
+/// `resolve("/posts/5/", "edit") == "/posts/5/edit"`, `resolve("/a/b", "../c") == "/c"`
+pub fn resolve(base: &str, location: &str) -> String {
+    let location = encode(location.trim());
+    if has_scheme(&location) || location.starts_with("//") {
+        return location;
+    }
+
+    let base = base.split(['?', '#']).next().unwrap_or("");
+    let base = if base.starts_with('/') { base.to_string() } else { format!("/{}", base) };
+
+    if location.is_empty() {
+        return base;
+    }
+    if location.starts_with('?') || location.starts_with('#') {
+        return format!("{}{}", base, location);
+    }
+
+    let (path, rest) = match location.find(['?', '#']) {
+        Some(i) => location.split_at(i),
+        None => (location.as_str(), ""),
+    };
+
+    let merged = if path.starts_with('/') {
+        path.to_string()
+    } else {
+        // everything after the base's last `/` is replaced.
+        let dir = &base[..base.rfind('/').map(|i| i + 1).unwrap_or(0)];
+        format!("{}{}", dir, path)
+    };
+
+    format!("{}{}", remove_dot_segments(&merged), rest)
+}
+
+/// Whether a URL starts with a scheme like `https:`.
+fn has_scheme(url: &str) -> bool {
+    match url.find(':') {
+        Some(i) => {
+            let scheme = &url[..i];
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme.chars().all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+        }
+        None => false,
+    }
+}
+
+/// Collapses `.` and `..` segments, never climbing above the root.
+fn remove_dot_segments(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    let parts = path.split('/').skip(1).collect::<Vec<&str>>();
+
+    for (i, segment) in parts.iter().enumerate() {
+        let last = i == parts.len() - 1;
+        match *segment {
+            "." => {
+                if last {
+                    segments.push("");
+                }
+            }
+            ".." => {
+                segments.pop();
+                if last {
+                    segments.push("");
+                }
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    format!("/{}", segments.join("/"))
+}
+
+/// Percent encodes anything that can't appear in a URL as it is, like spaces, quotes
+/// and line breaks, so the `Location` header stays a single valid header.
+fn encode(url: &str) -> String {
+    url.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => (b as char).to_string(),
+            b'-' | b'.' | b'_' | b'~' | b':' | b'/' | b'?' | b'#' | b'[' | b']' | b'@' | b'!'
+            | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' | b'%' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// The path, query and fragment of a `Referer` if it's for the same host as the request,
+/// so `back` never sends anyone off to another site.
+pub fn same_site_referer(referer: &str, host: Option<&str>) -> Option<String> {
+    let referer = referer.trim();
+
+    if referer.starts_with('/') {
+        return Some(referer.to_string()).filter(|path| is_local(path));
+    }
+
+    let after_scheme = match referer.find("://") {
+        Some(i) if has_scheme(&referer[..i + 1]) => &referer[i + 3..],
+        _ => return None,
+    };
+    let (authority, path) = match after_scheme.find(['/', '?', '#']) {
+        Some(i) => after_scheme.split_at(i),
+        None => (after_scheme, "/"),
+    };
+
+    match host {
+        Some(host) if host.trim().eq_ignore_ascii_case(authority) => {
+            let path = if path.starts_with('/') { path.to_string() } else { format!("/{}", path) };
+            Some(path).filter(|path| is_local(path))
+        }
+        _ => None,
+    }
+}
+
+/// Whether a path stays on this site as a `Location`, which `//host` and `/\host` don't.
+fn is_local(path: &str) -> bool {
+    !path.starts_with("//") && !path.starts_with("/\\")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_against_the_request_path() {
+        let cases: &[(&str, &str, &str)] = &[
+            ("/posts/5/", "edit", "/posts/5/edit"),
+            ("/posts/5", "edit", "/posts/edit"),
+            ("/a/b", "../c", "/c"),
+            ("/a/b/c", "./d", "/a/b/d"),
+            ("/a/b/c", "..", "/a/"),
+            ("/a/b/c", ".", "/a/b/"),
+            ("/a/b/c", "../../../../d", "/d"),
+            ("/a/b", "/x/./y/../z", "/x/z"),
+            ("/a/b", "/x/y/..", "/x/"),
+            ("a/b", "c", "/a/c"),
+            ("/a/b?q=1", "?page=2", "/a/b?page=2"),
+            ("/a/b#old", "#top", "/a/b#top"),
+            ("/a/b?q=1", "", "/a/b"),
+            ("/a/b", "c?x=../y#z", "/a/c?x=../y#z"),
+            ("/a/b", "https://example.com/x/../y", "https://example.com/x/../y"),
+            ("/a/b", "//cdn.example.com/x", "//cdn.example.com/x"),
+            ("/a/b", "c d", "/a/c%20d"),
+            ("/", "/x\r\nSet-Cookie: a=b", "/x%0D%0ASet-Cookie:%20a=b"),
+        ];
+
+        for &(base, location, expected) in cases {
+            assert_eq!(resolve(base, location), expected, "{} + {}", base, location);
+        }
+    }
+
+    #[test]
+    fn only_follows_referers_from_the_same_host() {
+        let cases: &[(&str, Option<&str>, Option<&str>)] = &[
+            ("/back", None, Some("/back")),
+            ("//evil.com/x", Some("example.com"), None),
+            ("/\\evil.com/x", Some("example.com"), None),
+            ("http://example.com//evil.com/x", Some("example.com"), None),
+            ("http://example.com/\\evil.com/x", Some("example.com"), None),
+            ("http://example.com/a//b", Some("example.com"), Some("/a//b")),
+            ("http://example.com/a?b", Some("example.com"), Some("/a?b")),
+            ("https://example.com", Some("example.com"), Some("/")),
+            ("https://EXAMPLE.com?x", Some("example.com"), Some("/?x")),
+            ("https://example.com:8080/a", Some("example.com:8080"), Some("/a")),
+            ("https://example.com:8080/a", Some("example.com"), None),
+            ("https://evil.com/a", Some("example.com"), None),
+            ("http://example.com/a", None, None),
+            ("javascript:alert(1)", Some("example.com"), None),
+        ];
+
+        for &(referer, host, expected) in cases {
+            assert_eq!(
+                same_site_referer(referer, host).as_deref(),
+                expected,
+                "{} from {:?}",
+                referer,
+                host
+            );
+        }
+    }
+}
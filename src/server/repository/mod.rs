@@ -0,0 +1,426 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use server::value::Value;
+
+/// The id a repository gives a record, counting up from 1.
+pub type Id = u64;
+
+/// A record a `FileRepository` can store, by converting it to and from a `Value`.
+///
+/// ```text
+/// impl Record for Dog {
+///     fn to_value(&self) -> Value {
+///         Value::object().set("name", &self.name).set("age", self.age)
+///     }
+///
+///     fn from_value(value: &Value) -> Option<Dog> {
+///         Some(Dog {
+///             name: value.get("name")?.as_str()?.to_string(),
+///             age: value.get("age")?.as_f64()? as u32,
+///         })
+///     }
+/// }
+/// ```
+pub trait Record: Clone + Send + Sync + 'static {
+    fn to_value(&self) -> Value;
+
+    /// `None` if the value doesn't hold a valid record.
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+/// Values store as they are, for records without a type of their own.
+impl Record for Value {
+    fn to_value(&self) -> Value {
+        self.clone()
+    }
+
+    fn from_value(value: &Value) -> Option<Value> {
+        Some(value.clone())
+    }
+}
+
+/// Stores records of one type by id. Repositories are thread safe, so one can be shared
+/// between handlers, eg as an `Arc<dyn Repository<Dog>>` that can be swapped for another store.
+pub trait Repository<T>: Send + Sync {
+    fn find(&self, id: Id) -> Result<Option<T>, Error>;
+
+    /// The records the predicate is true for, in id order.
+    fn filter(&self, predicate: &dyn Fn(&T) -> bool) -> Result<Vec<(Id, T)>, Error>;
+
+    /// Adds a record, returning the id it was given.
+    fn insert(&self, record: T) -> Result<Id, Error>;
+
+    /// Replaces a record, `false` if there isn't one with the id.
+    fn update(&self, id: Id, record: T) -> Result<bool, Error>;
+
+    /// Removes a record, returning it, `None` if there isn't one with the id.
+    fn delete(&self, id: Id) -> Result<Option<T>, Error>;
+
+    fn count(&self) -> Result<usize, Error>;
+
+    /// Every record, in id order.
+    fn all(&self) -> Result<Vec<(Id, T)>, Error> {
+        self.filter(&|_| true)
+    }
+
+    /// The record with the lowest id the predicate is true for,
+    /// eg `dogs.find_by(&|dog| dog.name == "Rex")`.
+    fn find_by(&self, predicate: &dyn Fn(&T) -> bool) -> Result<Option<(Id, T)>, Error> {
+        Ok(self.filter(predicate)?.into_iter().next())
+    }
+}
+
+/// The records and the next id to give out, kept the same way by both stores.
+#[derive(Debug, Clone)]
+struct Table<T> {
+    next_id: Id,
+    records: BTreeMap<Id, T>,
+}
+
+impl<T: Clone> Table<T> {
+    fn new() -> Table<T> {
+        Table {
+            next_id: 1,
+            records: BTreeMap::new(),
+        }
+    }
+
+    fn filter(&self, predicate: &dyn Fn(&T) -> bool) -> Vec<(Id, T)> {
+        self.records
+            .iter()
+            .filter(|&(_, record)| predicate(record))
+            .map(|(&id, record)| (id, record.clone()))
+            .collect()
+    }
+
+    fn insert(&mut self, record: T) -> Id {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.records.insert(id, record);
+        id
+    }
+
+    fn update(&mut self, id: Id, record: T) -> bool {
+        match self.records.get_mut(&id) {
+            Some(existing) => {
+                *existing = record;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn read_lock<T>(lock: &RwLock<T>) -> Result<RwLockReadGuard<'_, T>, Error> {
+    lock.read().map_err(|_| Error::other("Lock Error"))
+}
+
+fn write_lock<T>(lock: &RwLock<T>) -> Result<RwLockWriteGuard<'_, T>, Error> {
+    lock.write().map_err(|_| Error::other("Lock Error"))
+}
+
+/// Keeps records in memory, gone when the server stops. For prototypes and tests.
+///
+/// `let dogs: Arc<dyn Repository<Dog>> = Arc::new(MemoryRepository::new());`
+pub struct MemoryRepository<T> {
+    table: RwLock<Table<T>>,
+}
+
+impl<T: Clone> Default for MemoryRepository<T> {
+    fn default() -> MemoryRepository<T> {
+        MemoryRepository::new()
+    }
+}
+
+impl<T: Clone> MemoryRepository<T> {
+    pub fn new() -> MemoryRepository<T> {
+        MemoryRepository {
+            table: RwLock::new(Table::new()),
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync> Repository<T> for MemoryRepository<T> {
+    fn find(&self, id: Id) -> Result<Option<T>, Error> {
+        Ok(read_lock(&self.table)?.records.get(&id).cloned())
+    }
+
+    fn filter(&self, predicate: &dyn Fn(&T) -> bool) -> Result<Vec<(Id, T)>, Error> {
+        Ok(read_lock(&self.table)?.filter(predicate))
+    }
+
+    fn insert(&self, record: T) -> Result<Id, Error> {
+        Ok(write_lock(&self.table)?.insert(record))
+    }
+
+    fn update(&self, id: Id, record: T) -> Result<bool, Error> {
+        Ok(write_lock(&self.table)?.update(id, record))
+    }
+
+    fn delete(&self, id: Id) -> Result<Option<T>, Error> {
+        Ok(write_lock(&self.table)?.records.remove(&id))
+    }
+
+    fn count(&self) -> Result<usize, Error> {
+        Ok(read_lock(&self.table)?.records.len())
+    }
+}
+
+/// Keeps records in a file of JSON lines, a `{"next_id":2}` header then one
+/// `{"id":1,"record":{...}}` per record, read when opened and kept in memory after.
+///
+/// Every change rewrites the file to a temporary one next to it, then renames that over it,
+/// so a crash part way leaves either the old records or the new ones, never half of each.
+/// The header keeps the ids of deleted records from being given out again once reopened.
+///
+/// `let dogs = FileRepository::<Dog>::open("data/dogs.jsonl")?;`
+pub struct FileRepository<T> {
+    path: PathBuf,
+    table: RwLock<Table<T>>,
+}
+
+impl<T: Record> FileRepository<T> {
+    /// Opens the store in a file, which is created with the first record if it doesn't exist.
+    /// A line that isn't a valid record is an `InvalidData` error.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<FileRepository<T>, Error> {
+        let path = path.as_ref().to_path_buf();
+        let contents = match fs::read_to_string(&path) {
+            Ok(v) => v,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        let mut table = Table::new();
+        let mut lines = contents
+            .lines()
+            .enumerate()
+            .filter(|&(_, line)| !line.trim().is_empty())
+            .peekable();
+        // files from before the header start with a record, and go on from the highest id.
+        if let Some(next_id) = lines.peek().and_then(|&(_, line)| parse_header(line)) {
+            table.next_id = next_id;
+            lines.next();
+        }
+
+        for (number, line) in lines {
+            let (id, record) = parse_line(line).map_err(|message| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}, line {}: {}", path.display(), number + 1, message),
+                )
+            })?;
+            table.records.insert(id, record);
+            table.next_id = table.next_id.max(id + 1);
+        }
+
+        Ok(FileRepository {
+            path,
+            table: RwLock::new(table),
+        })
+    }
+
+    /// Makes a change to a copy of the records and saves it, only keeping it if that worked.
+    fn change<R, F: FnOnce(&mut Table<T>) -> R>(&self, change: F) -> Result<R, Error> {
+        let mut table = write_lock(&self.table)?;
+        let mut changed = table.clone();
+        let result = change(&mut changed);
+
+        self.save(&changed)?;
+        *table = changed;
+        Ok(result)
+    }
+
+    fn save(&self, table: &Table<T>) -> Result<(), Error> {
+        let mut contents = Value::object().set("next_id", table.next_id).to_json();
+        contents.push('\n');
+        for (&id, record) in &table.records {
+            contents.push_str(&Value::object().set("id", id).set("record", record.to_value()).to_json());
+            contents.push('\n');
+        }
+
+        let mut temp_name = self.path.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+        temp_name.push(".tmp");
+        let temp = self.path.with_file_name(temp_name);
+
+        let written = File::create(&temp).and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        });
+        if let Err(e) = written.and_then(|_| fs::rename(&temp, &self.path)) {
+            let _ = fs::remove_file(&temp);
+            return Err(e);
+        }
+
+        // makes the rename itself durable, where directories can be synced.
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            if let Ok(dir) = File::open(dir) {
+                let _ = dir.sync_all();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The next id from a store's header line, `None` if the line isn't one.
+fn parse_header(line: &str) -> Option<Id> {
+    let value = Value::from_json(line).ok()?;
+    if value.get("id").is_some() {
+        return None;
+    }
+
+    match value.get("next_id")?.as_f64()? {
+        id if id >= 1.0 && id.fract() == 0.0 && id < u64::MAX as f64 => Some(id as Id),
+        _ => None,
+    }
+}
+
+/// Reads one line of a store, the error saying what's wrong with it.
+fn parse_line<T: Record>(line: &str) -> Result<(Id, T), String> {
+    let value = Value::from_json(line).map_err(|e| e.to_string())?;
+
+    let id = match value.get("id").and_then(|v| v.as_f64()) {
+        Some(id) if id >= 1.0 && id.fract() == 0.0 && id < u64::MAX as f64 => id as Id,
+        _ => return Err("Expected a whole number `id` of at least 1".to_string()),
+    };
+    let record = value
+        .get("record")
+        .and_then(T::from_value)
+        .ok_or_else(|| format!("Record {} isn't valid", id))?;
+
+    Ok((id, record))
+}
+
+impl<T: Record> Repository<T> for FileRepository<T> {
+    fn find(&self, id: Id) -> Result<Option<T>, Error> {
+        Ok(read_lock(&self.table)?.records.get(&id).cloned())
+    }
+
+    fn filter(&self, predicate: &dyn Fn(&T) -> bool) -> Result<Vec<(Id, T)>, Error> {
+        Ok(read_lock(&self.table)?.filter(predicate))
+    }
+
+    fn insert(&self, record: T) -> Result<Id, Error> {
+        self.change(|table| table.insert(record))
+    }
+
+    fn update(&self, id: Id, record: T) -> Result<bool, Error> {
+        if !read_lock(&self.table)?.records.contains_key(&id) {
+            return Ok(false);
+        }
+        self.change(|table| table.update(id, record))
+    }
+
+    fn delete(&self, id: Id) -> Result<Option<T>, Error> {
+        if !read_lock(&self.table)?.records.contains_key(&id) {
+            return Ok(None);
+        }
+        self.change(|table| table.records.remove(&id))
+    }
+
+    fn count(&self) -> Result<usize, Error> {
+        Ok(read_lock(&self.table)?.records.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A path in a fresh directory, with nothing at it yet.
+    fn store_path(test: &str) -> PathBuf {
+        let dir = ::std::env::temp_dir().join(format!("rusty_server-{}-{}", test, ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("dogs.jsonl")
+    }
+
+    fn dog(name: &str) -> Value {
+        Value::object().set("name", name)
+    }
+
+    fn names(repository: &dyn Repository<Value>) -> Vec<(Id, String)> {
+        repository
+            .all()
+            .unwrap()
+            .into_iter()
+            .map(|(id, dog)| (id, dog.get("name").and_then(|n| n.as_str()).unwrap_or("").to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn memory_repository_stores_by_id() {
+        let dogs = MemoryRepository::new();
+        assert_eq!(dogs.insert(dog("Rex")).unwrap(), 1);
+        assert_eq!(dogs.insert(dog("Fido")).unwrap(), 2);
+
+        assert_eq!(dogs.find(2).unwrap(), Some(dog("Fido")));
+        assert!(dogs.update(1, dog("Max")).unwrap());
+        assert!(!dogs.update(9, dog("Nobody")).unwrap());
+        assert_eq!(dogs.find_by(&|d| d == &dog("Max")).unwrap().map(|(id, _)| id), Some(1));
+
+        assert_eq!(dogs.delete(2).unwrap(), Some(dog("Fido")));
+        assert_eq!(dogs.delete(2).unwrap(), None);
+        assert_eq!(dogs.insert(dog("Spot")).unwrap(), 3);
+        assert_eq!(names(&dogs), vec![(1, "Max".to_string()), (3, "Spot".to_string())]);
+        assert_eq!(dogs.count().unwrap(), 2);
+    }
+
+    #[test]
+    fn file_repository_keeps_records_and_ids_across_opens() {
+        let path = store_path("repository-reopen");
+        let dogs = FileRepository::<Value>::open(&path).unwrap();
+        assert_eq!(dogs.count().unwrap(), 0);
+        assert_eq!(dogs.insert(dog("Rex")).unwrap(), 1);
+        assert_eq!(dogs.insert(dog("Fido")).unwrap(), 2);
+        assert!(dogs.update(1, dog("Max")).unwrap());
+        assert_eq!(dogs.delete(2).unwrap(), Some(dog("Fido")));
+
+        let dogs = FileRepository::<Value>::open(&path).unwrap();
+        assert_eq!(names(&dogs), vec![(1, "Max".to_string())]);
+        // the deleted record's id isn't given out again.
+        assert_eq!(dogs.insert(dog("Spot")).unwrap(), 3);
+
+        let dogs = FileRepository::<Value>::open(&path).unwrap();
+        assert_eq!(names(&dogs), vec![(1, "Max".to_string()), (3, "Spot".to_string())]);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn file_repository_opens_files_without_a_header() {
+        let path = store_path("repository-no-header");
+        fs::write(&path, "{\"id\":4,\"record\":{\"name\":\"Rex\"}}\n\n{\"id\":2,\"record\":{\"name\":\"Fido\"}}\n").unwrap();
+
+        let dogs = FileRepository::<Value>::open(&path).unwrap();
+        assert_eq!(names(&dogs), vec![(2, "Fido".to_string()), (4, "Rex".to_string())]);
+        assert_eq!(dogs.insert(dog("Spot")).unwrap(), 5);
+        assert!(fs::read_to_string(&path).unwrap().starts_with("{\"next_id\":6}\n"));
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn file_repository_refuses_corrupt_lines() {
+        let path = store_path("repository-corrupt");
+        let cases: &[(&str, &str)] = &[
+            ("{\"next_id\":3}\n{\"id\":1,\"record\":{}}\nnot json\n", "line 3"),
+            ("{\"id\":0,\"record\":{}}\n", "line 1"),
+            ("{\"id\":1.5,\"record\":{}}\n", "line 1"),
+            ("{\"next_id\":2}\n{\"record\":{}}\n", "line 2"),
+            ("{\"id\":1}\n", "line 1"),
+        ];
+
+        for &(contents, line) in cases {
+            fs::write(&path, contents).unwrap();
+            let error = FileRepository::<Value>::open(&path).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{:?}", contents);
+            assert!(error.to_string().contains(line), "{:?}: {}", contents, error);
+        }
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -1,10 +1,26 @@
 use std::collections::HashMap;
-use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+use std::error::Error as StdError;
+use std::io::{BufReader, Cursor, Error, ErrorKind};
 use std::io::prelude::*;
-use std::net::TcpStream;
 use std::fmt::{Display, Formatter};
 use std::fmt;
 
+use server::deflate::{self, InflateError};
+use server::extensions::Extensions;
+use server::negotiate;
+use server::state::State;
+use server::status::StatusCode;
+
+/// The most the request line and headers together can be.
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// The longest chunk size or trailer line in a chunked body.
+const MAX_CHUNK_LINE: usize = 4 * 1024;
+
+/// The largest body accepted by default, after decompression.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
 impl Display for Request {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         let headers = self.headers
@@ -44,6 +60,108 @@ impl Display for Request {
     }
 }
 
+/// Why a request was refused before reaching a handler. It's carried inside the `io::Error`
+/// from `Request::new` or from finding a route, `Rejection::from_error` gets it back out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    /// The request line and headers are too long.
+    HeadTooLarge,
+    /// The body is over the size limit, as sent or once decompressed.
+    BodyTooLarge,
+    /// The body is encoded with something other than gzip or deflate.
+    UnsupportedEncoding(String),
+    /// The body is sent with a `Transfer-Encoding` other than chunked.
+    UnsupportedTransferEncoding(String),
+    /// The body says it's compressed, but can't be decompressed.
+    BadEncoding(String),
+    /// No route matches the path.
+    NotFound,
+    /// Routes match the path, but not for the method. Holds the methods that do, for `Allow`.
+    MethodNotAllowed(String),
+    /// The method isn't one routes can be set for, eg `OPTIONS` or `TRACE`.
+    NotImplemented(String),
+}
+
+impl Rejection {
+    /// The status to answer the request with.
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            Rejection::HeadTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            Rejection::BodyTooLarge => StatusCode::CONTENT_TOO_LARGE,
+            Rejection::UnsupportedEncoding(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Rejection::UnsupportedTransferEncoding(_) => StatusCode::NOT_IMPLEMENTED,
+            Rejection::BadEncoding(_) => StatusCode::BAD_REQUEST,
+            Rejection::NotFound => StatusCode::NOT_FOUND,
+            Rejection::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            Rejection::NotImplemented(_) => StatusCode::NOT_IMPLEMENTED,
+        }
+    }
+
+    pub fn from_error(error: &Error) -> Option<&Rejection> {
+        error.get_ref().and_then(|e| e.downcast_ref::<Rejection>())
+    }
+
+    pub(crate) fn into_error(self) -> Error {
+        let kind = match self {
+            Rejection::NotFound | Rejection::MethodNotAllowed(_) => ErrorKind::NotFound,
+            Rejection::NotImplemented(_) | Rejection::UnsupportedTransferEncoding(_) => ErrorKind::Unsupported,
+            _ => ErrorKind::InvalidData,
+        };
+        Error::new(kind, self)
+    }
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Rejection::HeadTooLarge => write!(f, "Request headers are too large"),
+            Rejection::BodyTooLarge => write!(f, "Request body is too large"),
+            Rejection::UnsupportedEncoding(ref coding) => write!(f, "Unsupported Content-Encoding: {}", coding),
+            Rejection::UnsupportedTransferEncoding(ref coding) => {
+                write!(f, "Unsupported Transfer-Encoding: {}", coding)
+            }
+            Rejection::BadEncoding(ref reason) => write!(f, "Request body can't be decoded: {}", reason),
+            Rejection::NotFound => write!(f, "Not Found"),
+            Rejection::MethodNotAllowed(_) => write!(f, "Method Not Allowed"),
+            Rejection::NotImplemented(ref method) => write!(f, "{} isn't supported", method),
+        }
+    }
+}
+
+impl StdError for Rejection {}
+
+/// Decodes a form field name or value, where `+` is a space and `%XX` a byte.
+fn decode_form_component(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match component.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 2;
+                }
+                None => decoded.push(b'%'),
+            },
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Looks a header up in a map, ignoring the case of its name.
+pub fn find_header<'a>(headers: &'a HashMap<String, String>, name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
 #[derive(Debug)]
 pub struct Request {
     /// The route without the query parameters
@@ -61,19 +179,30 @@ pub struct Request {
     pub raw_full: String,
     /// just the headers in string form
     pub raw_headers: String,
-    /// the body of the request in string form.
+    /// the body of the request in string form, lossily decoded.
     pub body: Option<String>,
+    /// the body as bytes, for uploads that aren't text.
+    /// Bodies sent with a gzip or deflate `Content-Encoding` are already decompressed.
+    pub body_bytes: Option<Vec<u8>>,
+    /// Values shared by the server and router, see `state`.
+    pub(crate) state: State,
+    /// Values attached to this request by middleware, for what runs after it.
+    pub extensions: Extensions,
 }
 
 
 impl Request {
     /// Creates a new request object
     /// This bundles the HTTP Request into one object for ease.
-    pub fn new(mut stream: &mut TcpStream) -> Result<Request, Error> {
-        let buf = Request::read_stream(&mut stream);
+    pub fn new<R: Read>(stream: &mut R) -> Result<Request, Error> {
+        Request::with_max_body(stream, DEFAULT_MAX_BODY_SIZE)
+    }
 
-        let buf_clone = buf.clone();
-        let mut lines = buf_clone.lines();
+    /// `Request::new`, refusing bodies over `max_body` bytes, before or after decompression.
+    pub fn with_max_body<R: Read>(stream: &mut R, max_body: usize) -> Result<Request, Error> {
+        let (head, rest) = Request::read_head(stream)?;
+
+        let mut lines = head.lines();
 
         // gets the first line of the HTTP request
         let first = match lines.next() {
@@ -82,25 +211,25 @@ impl Request {
         };
 
         let (method, route, maybe_query) = Request::parse_route(first)?;
-        let headers = Request::parse_headers(&buf)?;
+        let mut headers = Request::parse_headers(&head)?;
 
         let query = match maybe_query {
             Some(v) => Request::parse_query(&v),
             None => None
         };
 
-        // sep the headers and the body
-        let mut req = buf_clone.split("\r\n\r\n");
-
-        let raw_headers = match req.next() {
-            Some(v) => v.to_string(),
-            None => return Err(Error::new(ErrorKind::InvalidInput, "Malformed Input")),
-        };
-
-        let body = match req.next() {
-            Some(v) => Some(v.to_string()),
-            None => None,
+        // a head cut off before its blank line has no body.
+        let (raw_full, body_bytes) = match rest {
+            Some(rest) => {
+                let sent = Request::read_body(stream, rest, &mut headers, max_body)?;
+                let raw_full = format!("{}\r\n\r\n{}", head, String::from_utf8_lossy(&sent));
+                (raw_full, Some(Request::decode_body(sent, &mut headers, max_body)?))
+            }
+            None => (head.clone(), None),
         };
+        let body = body_bytes
+            .as_ref()
+            .map(|v| String::from_utf8_lossy(v).to_string());
 
         Ok(Request {
             route,
@@ -109,30 +238,253 @@ impl Request {
             query,
             params: None, // params like `dog/:id` are handled by the router, added after parsing
             body,
-            raw_headers,
-            raw_full: buf,
+            body_bytes,
+            raw_headers: head,
+            raw_full,
+            state: State::new(),
+            extensions: Extensions::new(),
         })
     }
 
-    /// Read the entire stream into a string
-    /// TODO: Impl an request limit size to prevent overflow attacks.
-    fn read_stream(stream: &mut TcpStream) -> String {
-        let mut buf = String::new();
+    /// A value shared with `Server::with_state` or `Router::with_state`, `None` if none of the type was.
+    ///
+    /// `let config = req.state::<Config>().expect("config is set at startup");`
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.state.get::<T>()
+    }
+
+    /// Gets a header by name, ignoring case, eg `req.header("content-type")`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// The fields of an `application/x-www-form-urlencoded` body, as HTML forms send,
+    /// empty for any other kind of body.
+    pub fn form(&self) -> HashMap<String, String> {
+        let is_form = self
+            .header("Content-Type")
+            .map(|v| v.trim().to_lowercase().starts_with("application/x-www-form-urlencoded"))
+            .unwrap_or(false);
+
+        match self.body {
+            Some(ref body) if is_form => body
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| {
+                    let mut pair = pair.splitn(2, '=');
+                    let key = decode_form_component(pair.next().unwrap_or(""));
+                    let value = decode_form_component(pair.next().unwrap_or(""));
+                    (key, value)
+                })
+                .collect(),
+            _ => HashMap::new(),
+        }
+    }
+
+    /// The PUT, PATCH or DELETE a POST stands in for, which HTML forms can't send,
+    /// going by a `_method` form field or an `X-HTTP-Method-Override` header.
+    pub(crate) fn method_override(&self) -> Option<String> {
+        if !self.method.eq_ignore_ascii_case("POST") {
+            return None;
+        }
+
+        let requested = match self.header("X-HTTP-Method-Override") {
+            Some(v) => Some(v.to_string()),
+            None => self.form().remove("_method"),
+        };
+
+        requested
+            .map(|m| m.trim().to_uppercase())
+            .filter(|m| m == "PUT" || m == "PATCH" || m == "DELETE")
+    }
+
+    /// Picks the best of the offered media types for the `Accept` header,
+    /// `None` if the client accepts none of them.
+    ///
+    /// `req.accepts(&["application/json", "text/html"])`
+    pub fn accepts<'a>(&self, offers: &[&'a str]) -> Option<&'a str> {
+        negotiate::media_type(self.header("Accept"), offers)
+    }
+
+    /// Picks the best of the offered languages for the `Accept-Language` header,
+    /// eg `req.accepts_language(&["en", "fr-CA"])`.
+    pub fn accepts_language<'a>(&self, offers: &[&'a str]) -> Option<&'a str> {
+        negotiate::language(self.header("Accept-Language"), offers)
+    }
+
+    /// Picks the best of the offered charsets for the `Accept-Charset` header.
+    pub fn accepts_charset<'a>(&self, offers: &[&'a str]) -> Option<&'a str> {
+        negotiate::charset(self.header("Accept-Charset"), offers)
+    }
+
+    /// Reads up to the blank line after the headers, returning the head
+    /// and whatever of the body arrived with it, `None` if the stream ended first.
+    fn read_head<R: Read>(stream: &mut R) -> Result<(String, Option<Vec<u8>>), Error> {
+        let mut buf = Vec::new();
+        let mut chunk = [0; 4096];
 
         loop {
-            let mut buffer = vec![0; 256];
-            stream.read(&mut buffer).unwrap();
+            let searched = buf.len().saturating_sub(3);
+            let read = stream.read(&mut chunk)?;
+            if read == 0 {
+                return Ok((String::from_utf8_lossy(&buf).to_string(), None));
+            }
+            buf.extend_from_slice(&chunk[..read]);
+
+            if let Some(end) = buf[searched..].windows(4).position(|w| w == b"\r\n\r\n") {
+                let end = searched + end;
+                if end > MAX_HEAD_SIZE {
+                    return Err(Rejection::HeadTooLarge.into_error());
+                }
+                let rest = buf.split_off(end + 4);
+                buf.truncate(end);
+                return Ok((String::from_utf8_lossy(&buf).to_string(), Some(rest)));
+            }
+
+            if buf.len() > MAX_HEAD_SIZE {
+                return Err(Rejection::HeadTooLarge.into_error());
+            }
+        }
+    }
+
+    /// Reads the rest of a body sent chunked or with a `Content-Length`, without either
+    /// the body is whatever arrived along with the head.
+    fn read_body<R: Read>(
+        stream: &mut R,
+        mut body: Vec<u8>,
+        headers: &mut HashMap<String, String>,
+        max_body: usize,
+    ) -> Result<Vec<u8>, Error> {
+        if let Some(coding) = find_header(headers, "Transfer-Encoding").map(|v| v.trim().to_string()) {
+            if !coding.eq_ignore_ascii_case("chunked") {
+                return Err(Rejection::UnsupportedTransferEncoding(coding).into_error());
+            }
+
+            // a chunked body ignores any `Content-Length`, and once joined is framed by its own.
+            let body = Request::read_chunked(stream, body, max_body)?;
+            headers.retain(|k, _| {
+                !k.eq_ignore_ascii_case("Transfer-Encoding") && !k.eq_ignore_ascii_case("Content-Length")
+            });
+            headers.insert("Content-Length".to_string(), body.len().to_string());
+            return Ok(body);
+        }
+
+        let length = match find_header(headers, "Content-Length") {
+            Some(v) => v
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid Content-Length"))?,
+            None => body.len(),
+        };
+        if length > max_body {
+            return Err(Rejection::BodyTooLarge.into_error());
+        }
 
-            let buf_as_string = String::from_utf8_lossy(&mut buffer);
-            let polished_buffer = buf_as_string.trim_matches('\u{0}');
-            buf.push_str(polished_buffer);
+        let mut chunk = [0; 4096];
+        while body.len() < length {
+            let read = stream.read(&mut chunk)?;
+            if read == 0 {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "Body ended before its Content-Length"));
+            }
+            body.extend_from_slice(&chunk[..read]);
+        }
+
+        body.truncate(length);
+        Ok(body)
+    }
+
+    /// Joins the chunks of a `Transfer-Encoding: chunked` body, dropping any trailers.
+    fn read_chunked<R: Read>(stream: &mut R, rest: Vec<u8>, max_body: usize) -> Result<Vec<u8>, Error> {
+        let mut reader = BufReader::new(Cursor::new(rest).chain(stream));
+        let malformed = |reason: &str| Rejection::BadEncoding(reason.to_string()).into_error();
+        let mut body = Vec::new();
 
-            if buf_as_string.contains('\u{0}') {
+        loop {
+            let line = Request::read_chunk_line(&mut reader)?;
+            // chunk extensions after a `;` are ignored.
+            let size = line.split(';').next().unwrap_or("").trim();
+            let size = usize::from_str_radix(size, 16).map_err(|_| malformed("invalid chunk size"))?;
+            if size == 0 {
                 break;
             }
+            if size > max_body - body.len() {
+                return Err(Rejection::BodyTooLarge.into_error());
+            }
+
+            let read = (&mut reader).take(size as u64).read_to_end(&mut body)?;
+            if read < size {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "Body ended inside a chunk"));
+            }
+            if !Request::read_chunk_line(&mut reader)?.is_empty() {
+                return Err(malformed("chunk is longer than its size"));
+            }
+        }
+
+        let mut trailers = 0;
+        loop {
+            let line = Request::read_chunk_line(&mut reader)?;
+            if line.is_empty() {
+                return Ok(body);
+            }
+            trailers += line.len();
+            if trailers > MAX_HEAD_SIZE {
+                return Err(Rejection::HeadTooLarge.into_error());
+            }
+        }
+    }
+
+    /// A chunk size or trailer line, without its line ending.
+    fn read_chunk_line<R: BufRead>(reader: &mut R) -> Result<String, Error> {
+        let mut line = Vec::new();
+        reader.take(MAX_CHUNK_LINE as u64).read_until(b'\n', &mut line)?;
+
+        if !line.ends_with(b"\n") {
+            if line.len() >= MAX_CHUNK_LINE {
+                return Err(Rejection::BadEncoding("chunk line is too long".to_string()).into_error());
+            }
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Body ended inside a chunk"));
+        }
+        Ok(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    /// Undoes the body's `Content-Encoding`, stopping at `max_body` bytes of output.
+    /// Once decoded the headers describe the body as handlers see it.
+    fn decode_body(
+        body: Vec<u8>,
+        headers: &mut HashMap<String, String>,
+        max_body: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let codings = match find_header(headers, "Content-Encoding") {
+            Some(v) => v.to_lowercase(),
+            None => return Ok(body),
+        };
+
+        let mut body = body;
+        // codings are listed in the order they were applied.
+        for coding in codings.split(',').map(|c| c.trim()).rev() {
+            let decoded = match coding {
+                "" | "identity" => continue,
+                "gzip" | "x-gzip" => deflate::gunzip(&body, max_body),
+                // meant to be zlib, but some clients send raw deflate.
+                "deflate" => match deflate::unzlib(&body, max_body) {
+                    Err(InflateError::Invalid(_)) => deflate::inflate(&body, max_body),
+                    result => result,
+                },
+                _ => return Err(Rejection::UnsupportedEncoding(coding.to_string()).into_error()),
+            };
+
+            body = match decoded {
+                Ok(v) => v,
+                Err(InflateError::TooLarge) => return Err(Rejection::BodyTooLarge.into_error()),
+                Err(e) => return Err(Rejection::BadEncoding(e.to_string()).into_error()),
+            };
         }
 
-        buf
+        headers.retain(|k, _| {
+            !k.eq_ignore_ascii_case("Content-Encoding") && !k.eq_ignore_ascii_case("Content-Length")
+        });
+        headers.insert("Content-Length".to_string(), body.len().to_string());
+        Ok(body)
     }
 
 
@@ -170,9 +522,10 @@ impl Request {
                 break;
             }
 
-            let mut sep = line.split(":");
+            // only split on the first `:`, values like dates and hosts have their own.
+            let mut sep = line.splitn(2, ':');
             let (key, value) = match (sep.next(), sep.next()) {
-                (Some(m), Some(p)) => (m.to_string(), p.to_string()),
+                (Some(m), Some(p)) => (m.trim().to_string(), p.trim().to_string()),
                 _ => return Err(Error::new(ErrorKind::InvalidInput, "Malformed Input")),
             };
             headers.insert(key, value);
@@ -187,7 +540,7 @@ impl Request {
     /// A none value signifies the query is just `?something` without a value.
     fn parse_query(query_path: &str) -> Option<HashMap<String, Option<String>>> {
         let mut queries: HashMap<String, Option<String>> = HashMap::new();
-        let seperated = query_path.split(|c| c == '&' || c == ';');
+        let seperated = query_path.split(['&', ';']);
 
         for query in seperated {
             let mut query = query.split('=');
@@ -206,3 +559,172 @@ impl Request {
         Some(queries)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn splits_headers_on_the_first_colon() {
+        let cases: &[(&str, &str, &str)] = &[
+            ("Host: example.com:8080", "Host", "example.com:8080"),
+            ("If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT", "If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT"),
+            ("X-Empty:", "X-Empty", ""),
+            ("  Spaced  :   out  ", "Spaced", "out"),
+            ("Referer: http://example.com/a?b=c:d", "Referer", "http://example.com/a?b=c:d"),
+        ];
+
+        for &(line, name, value) in cases {
+            let head = format!("GET / HTTP/1.1\r\n{}\r\n", line);
+            let headers = Request::parse_headers(&head).unwrap();
+            assert_eq!(find_header(&headers, name), Some(value), "{}", line);
+        }
+    }
+
+    #[test]
+    fn rejects_header_lines_without_a_colon() {
+        assert!(Request::parse_headers("GET / HTTP/1.1\r\nNo colon here\r\n").is_err());
+    }
+
+    #[test]
+    fn reads_a_request_with_a_port_in_its_host() {
+        let raw = "GET /dogs HTTP/1.1\r\nHost: example.com:8080\r\n\r\n";
+        let request = Request::new(&mut Cursor::new(raw)).unwrap();
+
+        assert_eq!(request.header("host"), Some("example.com:8080"));
+    }
+
+    fn with_body(headers: &str, body: &[u8], max_body: usize) -> Result<Request, Error> {
+        let mut raw = format!("POST / HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n", headers, body.len()).into_bytes();
+        raw.extend_from_slice(body);
+        Request::with_max_body(&mut Cursor::new(raw), max_body)
+    }
+
+    fn rejection(result: Result<Request, Error>) -> Option<Rejection> {
+        result.err().and_then(|e| Rejection::from_error(&e).cloned())
+    }
+
+    #[test]
+    fn decodes_compressed_bodies() {
+        let body = b"name=Rex&age=3".to_vec();
+        let cases: &[(&str, Vec<u8>)] = &[
+            ("gzip", deflate::gzip(&body)),
+            ("x-gzip", deflate::gzip(&body)),
+            ("deflate", deflate::zlib(&body)),
+            // raw deflate, as some clients send for `deflate`.
+            ("deflate", deflate::deflate(&body)),
+            ("identity", body.clone()),
+            ("gzip, identity", deflate::gzip(&body)),
+            ("deflate, gzip", deflate::gzip(&deflate::zlib(&body))),
+        ];
+
+        for &(coding, ref sent) in cases {
+            let headers = format!("Content-Encoding: {}\r\n", coding);
+            let request = with_body(&headers, sent, 1024).unwrap();
+            assert_eq!(request.body_bytes.as_ref(), Some(&body), "{}", coding);
+            assert_eq!(request.header("Content-Encoding"), None, "{}", coding);
+            assert_eq!(request.header("Content-Length"), Some("14"), "{}", coding);
+        }
+    }
+
+    #[test]
+    fn refuses_bodies_it_cant_decode() {
+        let result = with_body("Content-Encoding: br\r\n", b"abc", 1024);
+        assert_eq!(rejection(result), Some(Rejection::UnsupportedEncoding("br".to_string())));
+
+        let result = with_body("Content-Encoding: gzip\r\n", b"not gzip at all", 1024);
+        assert_eq!(rejection(result).map(|r| r.status()), Some(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn holds_bodies_to_the_limit() {
+        let result = with_body("", &[b'a'; 100], 99);
+        assert_eq!(rejection(result), Some(Rejection::BodyTooLarge));
+        assert!(with_body("", &[b'a'; 100], 100).is_ok());
+
+        // small when sent, too big once decompressed.
+        let bomb = deflate::gzip(&[0; 1024 * 1024]);
+        assert!(bomb.len() < 2048);
+        let result = with_body("Content-Encoding: gzip\r\n", &bomb, 64 * 1024);
+        assert_eq!(rejection(result), Some(Rejection::BodyTooLarge));
+    }
+
+    fn chunked(chunks: &str, max_body: usize) -> Result<Request, Error> {
+        let raw = format!("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{}", chunks);
+        Request::with_max_body(&mut Cursor::new(raw), max_body)
+    }
+
+    #[test]
+    fn joins_chunked_bodies() {
+        let cases: &[(&str, &str)] = &[
+            ("0\r\n\r\n", ""),
+            ("4\r\nname\r\n4\r\n=Rex\r\n0\r\n\r\n", "name=Rex"),
+            ("8;ext=1\r\nname=Rex\r\n0\r\n\r\n", "name=Rex"),
+            ("A\r\n0123456789\r\n0\r\n\r\n", "0123456789"),
+            ("3\nabc\n0\n\n", "abc"),
+            ("3\r\nabc\r\n0\r\nX-Checksum: 1\r\nX-More: 2\r\n\r\n", "abc"),
+        ];
+
+        for &(chunks, body) in cases {
+            let request = chunked(chunks, 1024).unwrap();
+            assert_eq!(request.body_bytes.as_ref().map(|b| &b[..]), Some(body.as_bytes()), "{:?}", chunks);
+            assert_eq!(request.header("Transfer-Encoding"), None, "{:?}", chunks);
+            assert_eq!(request.header("Content-Length"), Some(&*body.len().to_string()), "{:?}", chunks);
+        }
+    }
+
+    #[test]
+    fn chunked_overrides_content_length() {
+        let raw = "POST / HTTP/1.1\r\nContent-Length: 2\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n";
+        let request = Request::new(&mut Cursor::new(raw)).unwrap();
+        assert_eq!(request.body_bytes.as_ref().map(|b| &b[..]), Some(&b"abc"[..]));
+        assert_eq!(request.header("Content-Length"), Some("3"));
+    }
+
+    #[test]
+    fn refuses_bad_chunked_bodies() {
+        let cases: &[&str] = &["zz\r\nabc\r\n0\r\n\r\n", "2\r\nabc\r\n0\r\n\r\n", "-1\r\n\r\n"];
+        for &chunks in cases {
+            let result = chunked(chunks, 1024);
+            assert_eq!(rejection(result).map(|r| r.status()), Some(StatusCode::BAD_REQUEST), "{:?}", chunks);
+        }
+
+        let long = format!("{}\r\n", "0".repeat(MAX_CHUNK_LINE));
+        assert_eq!(rejection(chunked(&long, 1024)).map(|r| r.status()), Some(StatusCode::BAD_REQUEST));
+
+        let truncated: &[&str] = &["", "3\r\nab", "3\r\nabc", "3\r\nabc\r\n", "0\r\n", "0\r\nX-Trailer: 1\r\n"];
+        for &chunks in truncated {
+            let kind = chunked(chunks, 1024).err().map(|e| e.kind());
+            assert_eq!(kind, Some(ErrorKind::UnexpectedEof), "{:?}", chunks);
+        }
+    }
+
+    #[test]
+    fn holds_chunked_bodies_to_the_limit() {
+        assert!(chunked("4\r\nabcd\r\n4\r\nefgh\r\n0\r\n\r\n", 8).is_ok());
+
+        let result = chunked("4\r\nabcd\r\n5\r\nefghi\r\n0\r\n\r\n", 8);
+        assert_eq!(rejection(result), Some(Rejection::BodyTooLarge));
+
+        // the size alone is enough, without sending the chunk.
+        let result = chunked("ffffffff\r\n", 8);
+        assert_eq!(rejection(result), Some(Rejection::BodyTooLarge));
+    }
+
+    #[test]
+    fn refuses_other_transfer_codings() {
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n";
+        let result = Request::new(&mut Cursor::new(raw));
+        let refused = rejection(result);
+        assert_eq!(refused, Some(Rejection::UnsupportedTransferEncoding("gzip, chunked".to_string())));
+        assert_eq!(refused.map(|r| r.status()), Some(StatusCode::NOT_IMPLEMENTED));
+    }
+
+    #[test]
+    fn refuses_heads_that_are_too_large() {
+        let raw = format!("GET / HTTP/1.1\r\nX-Big: {}\r\n\r\n", "a".repeat(MAX_HEAD_SIZE));
+        let result = Request::new(&mut Cursor::new(raw));
+        assert_eq!(rejection(result), Some(Rejection::HeadTooLarge));
+    }
+}
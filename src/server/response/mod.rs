@@ -1,27 +1,24 @@
-use std::net::TcpStream;
+use std::borrow::Cow;
 use std::fs::File;
-use std::path::Path;
-use std::io::Error;
+use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::io::{self, Error, ErrorKind, SeekFrom};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::io::prelude::*;
 use std::collections::HashMap;
-
-fn status_to_named(status: u16) -> String {
-    let res = match status {
-        100 => "Continue",
-        200 => "OK",
-        301 => "Moved Permanently",
-        400 => "Bad Request",
-        401 => "Unauthorized",
-        403 => "Forbidden",
-        404 => "Not Found",
-        405 => "Method Not Allowed",
-        414 => "URI Too Long",
-        418 => "I'm a teapot",
-        500 => "Internal Server Error",
-        _ => panic!("Code not implemented! (sorry!)"),
-    };
-    res.to_string()
-}
+use server::status::StatusCode;
+use server::conditional::{self, ETag};
+use server::date;
+use server::mime::MimeTypes;
+use server::compression::{self, Compression};
+use server::negotiate;
+use server::redirect;
+use server::static_files::escape_html;
+use server::template::Templates;
+use server::value::Value;
+use server::range::{self, ByteRanges};
+use server::request::{find_header, Request};
 
 pub trait Pathable {
     fn parse(&self) -> String;
@@ -39,29 +36,80 @@ impl Pathable for Path {
     }
 }
 
+/// The parts of the request a response needs to know about,
+/// for ranged, conditional and negotiated responses.
+#[derive(Debug, Clone, Default)]
+pub struct RequestInfo {
+    pub method: String,
+    pub route: String,
+    pub headers: HashMap<String, String>,
+}
+
+impl RequestInfo {
+    pub fn new(request: &Request) -> RequestInfo {
+        RequestInfo {
+            method: request.method.clone(),
+            route: request.route.clone(),
+            headers: request.headers.clone(),
+        }
+    }
+
+    /// Gets a request header by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
 pub struct Response {
-    stream: TcpStream,
+    stream: Box<dyn Write + Send>,
     pub headers: HashMap<String, String>,
-    status: u16,
+    status: StatusCode,
+    /// Used to pick the `Content-Type` of files, set from the server's table.
+    pub(crate) mime_types: Arc<MimeTypes>,
+    /// Set from the server, `None` sends bodies as they are.
+    pub(crate) compression: Option<Arc<Compression>>,
+    /// Set from the server, what `render` renders from.
+    pub(crate) templates: Option<Arc<Templates>>,
+    /// Set from the server, see `Server::development`.
+    pub(crate) development: bool,
+    /// The request being answered, empty for a response made outside of a server.
+    pub(crate) request: RequestInfo,
+    /// Tag the body with a hash of it when sent, `Some(weak)`.
+    auto_etag: Option<bool>,
+    /// The handler already ran `check_preconditions`, so sending doesn't again.
+    preconditions_checked: bool,
 }
 
 impl Response {
     /// Creates a new response object for interacting with the user.
-    pub fn new(stream: TcpStream) -> Response {
+    /// Writes to any stream, a socket, a TLS wrapper, or a buffer for testing.
+    pub fn new<W: Write + Send + 'static>(stream: W) -> Response {
         Response {
-            stream: stream,
+            stream: Box::new(stream),
             headers: HashMap::new(),
-            status: 200,
+            status: StatusCode::OK,
+            mime_types: Arc::new(MimeTypes::new()),
+            compression: None,
+            templates: None,
+            development: false,
+            request: RequestInfo::default(),
+            auto_etag: None,
+            preconditions_checked: false,
         }
     }
 
     /// sets the status code for the request, allows for chaining
-    /// `response.status(200).send("Hello!")`
-    pub fn status(&mut self, code: u16) -> &mut Response {
-        self.status = code;
+    /// `response.status(200).send("Hello!")` or `response.status(StatusCode::CREATED)`
+    pub fn status<S: Into<StatusCode>>(&mut self, code: S) -> &mut Response {
+        self.status = code.into();
         self
     }
 
+    /// The status code that will be sent.
+    pub fn get_status(&self) -> StatusCode {
+        self.status
+    }
+
     // sets the content type header. allows for easier method chaining like
     /// `res.content_type("application/json").send(json!{ "hello": "world" })`
     pub fn content_type<T: ToString>(&mut self, setting: T) -> &mut Response {
@@ -69,38 +117,1170 @@ impl Response {
         self
     }
 
+    /// Whether a header has been set, ignoring the case of its name.
+    pub fn has_header(&self, name: &str) -> bool {
+        self.headers.keys().any(|k| k.eq_ignore_ascii_case(name))
+    }
+
+    /// Sets the `ETag` header, eg `res.etag(ETag::strong(post.version))`.
+    /// Conditional requests are then answered against it when the response is sent.
+    pub fn etag(&mut self, tag: ETag) -> &mut Response {
+        self.headers.retain(|k, _| !k.eq_ignore_ascii_case("ETag"));
+        self.headers.insert("ETag".to_string(), tag.to_string());
+        self
+    }
+
+    /// Tags the body with a hash of it when it's sent, unless an `ETag` is already set.
+    /// Weak tags say the content is equivalent rather than byte for byte the same.
+    pub fn auto_etag(&mut self, weak: bool) -> &mut Response {
+        self.auto_etag = Some(weak);
+        self
+    }
+
+    /// Checks the request's conditional headers against the current validators of the resource,
+    /// before a handler changes it. If they fail, `412 Precondition Failed`
+    /// (or `304 Not Modified` for a GET) is sent and `false` returned.
+    ///
+    /// `if !res.check_preconditions(Some(&current), None)? { return; }`
+    pub fn check_preconditions(
+        &mut self,
+        etag: Option<&ETag>,
+        last_modified: Option<SystemTime>,
+    ) -> Result<bool, Error> {
+        self.preconditions_checked = true;
+        match conditional::evaluate(&self.request, etag, last_modified) {
+            Some(status) => {
+                self.send_precondition_failure(status)?;
+                Ok(false)
+            }
+            None => Ok(true),
+        }
+    }
+
+    /// Evaluates the request's preconditions against the `ETag` and `Last-Modified` headers.
+    /// Only for successful reads, other methods have already made their change by the time
+    /// they respond and need `check_preconditions` beforehand.
+    fn failed_precondition(&self) -> Option<StatusCode> {
+        let is_read = self.request.method.eq_ignore_ascii_case("GET")
+            || self.request.method.eq_ignore_ascii_case("HEAD");
+        if !is_read || !self.status.is_success() || self.preconditions_checked {
+            return None;
+        }
+
+        let etag = find_header(&self.headers, "ETag").and_then(ETag::parse);
+        let last_modified = find_header(&self.headers, "Last-Modified").and_then(date::parse);
+        conditional::evaluate(&self.request, etag.as_ref(), last_modified)
+    }
+
+    /// Answers with `304 Not Modified`, keeping the validators, or `412 Precondition Failed`.
+    fn send_precondition_failure(&mut self, status: StatusCode) -> Result<&mut Response, Error> {
+        if status == StatusCode::NOT_MODIFIED && self.compressible() {
+            self.vary("Accept-Encoding");
+        }
+        self.headers.retain(|k, _| {
+            !k.eq_ignore_ascii_case("Content-Type")
+                && !k.eq_ignore_ascii_case("Content-Range")
+                && !k.eq_ignore_ascii_case("Transfer-Encoding")
+        });
+        self.status(status);
+
+        if status == StatusCode::NOT_MODIFIED {
+            self.write_body(&[])
+        } else {
+            self.content_type("text/plain; charset=utf-8");
+            self.write_body(b"Precondition Failed")
+        }
+    }
+
+    /// Renders a template from the server's `Templates` and sends it as HTML,
+    /// unless a `Content-Type` was set already.
+    /// If it can't be rendered, `500 Internal Server Error` is sent instead and the error returned,
+    /// with a page showing what went wrong in development mode.
+    ///
+    /// `res.render("dogs/show", Value::object().set("dog", dog))`
+    pub fn render<V: Into<Value>>(&mut self, template: &str, context: V) -> Result<&mut Response, Error> {
+        let rendered = match self.templates {
+            Some(ref templates) => templates.render_with(template, &context.into(), self.development),
+            None => Err(Error::other("No templates to render from, set them with `server.templates`")),
+        };
+
+        match rendered {
+            Ok(html) => {
+                if !self.has_header("Content-Type") {
+                    self.content_type("text/html; charset=utf-8");
+                }
+                self.send(html)
+            }
+            Err(e) => {
+                self.headers.retain(|k, _| !k.eq_ignore_ascii_case("Content-Type"));
+                self.status(StatusCode::INTERNAL_SERVER_ERROR);
+
+                match self.templates {
+                    Some(ref templates) if self.development => {
+                        let page = templates.error_page(&e);
+                        self.content_type("text/html; charset=utf-8").send(page)?;
+                    }
+                    _ => {
+                        self.content_type("text/plain; charset=utf-8").send("Internal Server Error")?;
+                    }
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Answers with whichever renderer's type best fits the request's `Accept` header,
+    /// or `406 Not Acceptable` if none do and there's no default.
+    ///
+    /// `res.format().on("application/json", |res| res.send(json)).on("text/html", |res| res.send(html)).send()`
+    pub fn format(&mut self) -> Format<'_> {
+        Format {
+            response: self,
+            renderers: Vec::new(),
+            default: None,
+        }
+    }
+
+    /// Redirects with `302 Found`. Relative URLs are resolved against the request's path,
+    /// so `res.redirect("edit")` from `/posts/5/` goes to `/posts/5/edit`.
+    pub fn redirect<T: ToString>(&mut self, url: T) -> Result<&mut Response, Error> {
+        self.redirect_with(StatusCode::FOUND, url)
+    }
+
+    /// Redirects with `308 Permanent Redirect`, which clients may remember and which keeps the method.
+    pub fn redirect_permanent<T: ToString>(&mut self, url: T) -> Result<&mut Response, Error> {
+        self.redirect_with(StatusCode::PERMANENT_REDIRECT, url)
+    }
+
+    /// Redirects with `303 See Other`, which is followed with a GET,
+    /// eg to show the result of a form POST.
+    pub fn see_other<T: ToString>(&mut self, url: T) -> Result<&mut Response, Error> {
+        self.redirect_with(StatusCode::SEE_OTHER, url)
+    }
+
+    /// Redirects back to the page the request came from, going by its `Referer`,
+    /// or to `fallback` when there isn't one or it's for another site.
+    pub fn back<T: ToString>(&mut self, fallback: T) -> Result<&mut Response, Error> {
+        let referer = self.request
+            .header("Referer")
+            .and_then(|r| redirect::same_site_referer(r, self.request.header("Host")));
+
+        match referer {
+            Some(v) => self.redirect(v),
+            None => self.redirect(fallback),
+        }
+    }
+
+    /// Redirects with any status, eg `res.redirect_with(StatusCode::MOVED_PERMANENTLY, "/new")`.
+    /// The body is a small page linking to the new location, for clients that don't follow it.
+    pub fn redirect_with<S, T>(&mut self, status: S, url: T) -> Result<&mut Response, Error>
+    where
+        S: Into<StatusCode>,
+        T: ToString,
+    {
+        let location = redirect::resolve(&self.request.route, &url.to_string());
+        let escaped = escape_html(&location);
+
+        self.headers.retain(|k, _| !k.eq_ignore_ascii_case("Location"));
+        self.headers.insert("Location".to_string(), location);
+        self.status(status)
+            .content_type("text/html; charset=utf-8")
+            .send(format!(
+                "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Redirecting</title></head>\n\
+                 <body>\n<p>Redirecting to <a href=\"{}\">{}</a></p>\n</body>\n</html>\n",
+                escaped, escaped
+            ))
+    }
+
+    /// Adds a request header the response depends on to `Vary`, eg `res.vary("Accept-Language")`.
+    pub fn vary(&mut self, header: &str) -> &mut Response {
+        let existing = self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("Vary"))
+            .map(|(k, v)| (k.clone(), v.clone()));
+
+        match existing {
+            Some((_, ref value)) if value.split(',').any(|v| {
+                let v = v.trim();
+                v == "*" || v.eq_ignore_ascii_case(header)
+            }) => (),
+            Some((key, value)) => {
+                self.headers.insert(key, format!("{}, {}", value, header));
+            }
+            None => {
+                self.headers.insert("Vary".to_string(), header.to_string());
+            }
+        }
+        self
+    }
+
+    /// Whether the body may be compressed, if the server has compression on.
+    fn compressible(&self) -> bool {
+        let compression = match self.compression {
+            Some(ref v) => v,
+            None => return false,
+        };
+
+        let no_transform = find_header(&self.headers, "Cache-Control")
+            .map(|v| v.to_lowercase().contains("no-transform"))
+            .unwrap_or(false);
+
+        !self.is_bodiless()
+            && !no_transform
+            && !self.has_header("Content-Encoding")
+            && compression.compressible(find_header(&self.headers, "Content-Type"))
+    }
+
+    /// Compresses the body with the encoding the client prefers, if it's worth it.
+    fn encode_body<'b>(&mut self, body: &'b [u8]) -> Cow<'b, [u8]> {
+        if !self.compressible() {
+            return Cow::Borrowed(body);
+        }
+        self.vary("Accept-Encoding");
+
+        let worth_it = self.compression.as_ref().map(|c| c.worth_it(body.len())).unwrap_or(false);
+        let encoding = self.request.header("Accept-Encoding").and_then(compression::negotiate);
+        let encoding = match encoding {
+            Some(v) if worth_it => v,
+            _ => return Cow::Borrowed(body),
+        };
+
+        let encoded = encoding.encode(body);
+        if encoded.len() >= body.len() {
+            return Cow::Borrowed(body);
+        }
+
+        self.headers.insert("Content-Encoding".to_string(), encoding.name().to_string());
+        // the compressed bytes differ, so a strong tag for the original no longer applies.
+        if let Some(tag) = find_header(&self.headers, "ETag").and_then(ETag::parse) {
+            if !tag.weak {
+                self.etag(ETag::weak(tag.tag));
+            }
+        }
+
+        Cow::Owned(encoded)
+    }
+
+    /// Sets a header unless one with the same name, in any case, is already set.
+    fn default_header<V: ToString>(&mut self, name: &str, value: V) {
+        if !self.has_header(name) {
+            self.headers.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    /// Whether the status forbids sending a body, 1xx, 204 and 304.
+    fn is_bodiless(&self) -> bool {
+        let code = self.status.as_u16();
+        self.status.is_informational() || code == 204 || code == 304
+    }
+
+    /// A HEAD gets the headers a GET would, without the body.
+    fn is_head(&self) -> bool {
+        self.request.method.eq_ignore_ascii_case("HEAD")
+    }
+
+    /// Sends the body, or `304`/`412` instead if the request's preconditions
+    /// don't hold against the response's `ETag` or `Last-Modified`.
     pub fn send<T: ToString>(&mut self, body: T) -> Result<&mut Response, Error> {
-        let payload = self.create_response(body.to_string());
+        let body = body.to_string();
+
+        if let Some(weak) = self.auto_etag {
+            if !self.has_header("ETag") {
+                self.etag(ETag::from_bytes(body.as_bytes(), weak));
+            }
+        }
+        if let Some(status) = self.failed_precondition() {
+            return self.send_precondition_failure(status);
+        }
+
+        let body = self.encode_body(body.as_bytes());
+        self.write_body(&body)
+    }
 
-        self.stream.write_all(payload.as_bytes())?;
+    fn write_body(&mut self, body: &[u8]) -> Result<&mut Response, Error> {
+        let payload = self.create_response(body);
+
+        self.stream.write_all(&payload)?;
+        self.stream.flush()?;
 
         Ok(self)
     }
 
-    /// Sends a file as the response. chain with `.content_type` to set the proper header
+    /// Starts a streamed response, sending the status and headers straight away.
+    /// The body is written through the returned writer, which uses chunked transfer encoding
+    /// unless a `Content-Length` header was set beforehand.
+    /// Preconditions aren't checked since the body isn't known yet, use `check_preconditions` first,
+    /// and the body isn't compressed.
+    ///
+    /// `let mut body = res.stream()?; writeln!(body, "row {}", 1)?; body.finish()?;`
+    pub fn stream(&mut self) -> Result<BodyWriter<'_>, Error> {
+        let length = self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("Content-Length"))
+            .map(|(_, v)| v.trim().parse::<u64>());
+
+        let mut mode = match length {
+            _ if self.is_bodiless() => {
+                self.headers.retain(|k, _| !k.eq_ignore_ascii_case("Content-Length"));
+                BodyMode::Empty
+            }
+            Some(Ok(length)) => BodyMode::Fixed(length),
+            Some(Err(_)) => return Err(Error::new(ErrorKind::InvalidInput, "Invalid Content-Length")),
+            None => {
+                self.headers.insert("Transfer-Encoding".to_string(), "chunked".to_string());
+                BodyMode::Chunked
+            }
+        };
+        if self.is_head() {
+            mode = BodyMode::Empty;
+        }
+
+        self.default_header("Date", date::now());
+        self.default_header("Connection", "close");
+
+        let head = self.create_head();
+        self.stream.write_all(head.as_bytes())?;
+
+        Ok(BodyWriter {
+            stream: &mut self.stream,
+            mode,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+            finished: false,
+        })
+    }
+
+    /// Sends a file as the response, streaming it from disk so any size or kind of file works.
+    /// The `Content-Type` is picked from the extension unless already set, and
+    /// `Content-Length`, `Last-Modified` and a strong `ETag` come from the file,
+    /// and conditional requests are answered with `304` or `412`.
+    /// If there is no such file a `404 Not Found` is sent instead.
+    ///
     /// By default, `.` is the project root. eg `src/ Cargo.lock Cargo.toml`. Use a static path for more clarity.
     pub fn send_file<T: Pathable>(&mut self, path: T) -> Result<&mut Response, Error> {
-        let mut contents = String::new();
+        let path = PathBuf::from(path.parse());
+        self.send_path(&path)
+    }
+
+    /// `send_file` for a `Path`, which keeps paths that aren't valid UTF-8 intact.
+    pub fn send_path(&mut self, path: &Path) -> Result<&mut Response, Error> {
+        let mut file = match File::open(path) {
+            Ok(v) => v,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return self.not_found(),
+            Err(e) => return Err(e),
+        };
+        let meta = file.metadata()?;
+        if meta.is_dir() {
+            return self.not_found();
+        }
+
+        let length = meta.len();
+        let modified = meta.modified().ok();
+        if let Some(modified) = modified {
+            self.headers.insert("Last-Modified".to_string(), date::format(modified));
+        }
+        self.default_header("ETag", ETag::from_metadata(length, modified));
+        self.default_header("Accept-Ranges", "bytes");
+        if self.development {
+            // browsers would otherwise guess the file is fresh for a while from its `Last-Modified`.
+            self.default_header("Cache-Control", "no-cache");
+        }
+
+        if let Some(status) = self.failed_precondition() {
+            return self.send_precondition_failure(status);
+        }
+
+        let ranges = match self.request.header("Range") {
+            Some(range) if self.accepts_range() => range::parse(range, length),
+            _ => ByteRanges::Full,
+        };
+
+        let mime = self.mime_types.for_path(path).to_string();
+
+        match ranges {
+            ByteRanges::Full => {
+                self.default_header("Content-Type", mime);
+
+                if length <= compression::MAX_FILE_SIZE && self.compressible() {
+                    let mut data = Vec::with_capacity(length as usize);
+                    file.read_to_end(&mut data)?;
+                    let body = self.encode_body(&data);
+                    self.write_body(&body)?;
+                    return Ok(self);
+                }
+
+                self.set_content_length(length);
+
+                let mut body = self.stream()?;
+                io::copy(&mut file, &mut body)?;
+                body.finish()?;
+            }
+            ByteRanges::Unsatisfiable => {
+                self.headers.insert("Content-Range".to_string(), format!("bytes */{}", length));
+                self.headers.retain(|k, _| !k.eq_ignore_ascii_case("Content-Type"));
+                self.content_type("text/plain; charset=utf-8")
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .send("Range Not Satisfiable")?;
+            }
+            ByteRanges::Ranges(ref ranges) if ranges.len() == 1 => {
+                let (first, last) = ranges[0];
+                self.default_header("Content-Type", mime);
+                self.headers.insert(
+                    "Content-Range".to_string(),
+                    format!("bytes {}-{}/{}", first, last, length),
+                );
+                self.set_content_length(last - first + 1);
+                self.status(StatusCode::PARTIAL_CONTENT);
+
+                file.seek(SeekFrom::Start(first))?;
+                let mut body = self.stream()?;
+                io::copy(&mut (&mut file).take(last - first + 1), &mut body)?;
+                body.finish()?;
+            }
+            ByteRanges::Ranges(ranges) => {
+                let mime = match find_header(&self.headers, "Content-Type") {
+                    Some(v) => v.to_string(),
+                    None => mime,
+                };
+                self.send_multipart_ranges(&mut file, &ranges, length, &mime)?;
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Whether a `Range` header should be honoured, only for plain GETs where any `If-Range`
+    /// validator still matches the file, otherwise the whole file is sent.
+    fn accepts_range(&self) -> bool {
+        if !self.request.method.eq_ignore_ascii_case("GET") || self.status != StatusCode::OK {
+            return false;
+        }
 
-        let path = path.parse();
-        let mut file = File::open(path)?;
-        file.read_to_string(&mut contents)?;
+        let validator = match self.request.header("If-Range") {
+            Some(v) => v.trim(),
+            None => return true,
+        };
 
-        self.send(contents)
+        // an entity tag, which must match strongly.
+        if validator.starts_with('"') || validator.starts_with("W/") {
+            return !validator.starts_with("W/")
+                && find_header(&self.headers, "ETag").map(|e| e.trim()) == Some(validator);
+        }
+
+        find_header(&self.headers, "Last-Modified") == Some(validator)
+    }
+
+    /// Sends several ranges of a file as a `multipart/byteranges` body.
+    fn send_multipart_ranges(
+        &mut self,
+        file: &mut File,
+        ranges: &[(u64, u64)],
+        length: u64,
+        mime: &str,
+    ) -> Result<(), Error> {
+        let boundary = multipart_boundary();
+        let part_heads = ranges
+            .iter()
+            .map(|&(first, last)| {
+                format!(
+                    "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                    boundary, mime, first, last, length
+                )
+            })
+            .collect::<Vec<String>>();
+        let tail = format!("\r\n--{}--\r\n", boundary);
+
+        let total = ranges
+            .iter()
+            .zip(&part_heads)
+            .map(|(&(first, last), head)| head.len() as u64 + last - first + 1)
+            .sum::<u64>() + tail.len() as u64;
+
+        self.headers.retain(|k, _| !k.eq_ignore_ascii_case("Content-Type"));
+        self.content_type(format!("multipart/byteranges; boundary={}", boundary));
+        self.set_content_length(total);
+        self.status(StatusCode::PARTIAL_CONTENT);
+
+        let mut body = self.stream()?;
+        for (&(first, last), head) in ranges.iter().zip(&part_heads) {
+            body.write_all(head.as_bytes())?;
+            file.seek(SeekFrom::Start(first))?;
+            io::copy(&mut (&mut *file).take(last - first + 1), &mut body)?;
+        }
+        body.write_all(tail.as_bytes())?;
+        body.finish()
+    }
+
+    fn set_content_length(&mut self, length: u64) {
+        self.headers.retain(|k, _| !k.eq_ignore_ascii_case("Content-Length"));
+        self.headers.insert("Content-Length".to_string(), length.to_string());
+    }
+
+    fn not_found(&mut self) -> Result<&mut Response, Error> {
+        self.headers.retain(|k, _| !k.eq_ignore_ascii_case("Content-Type"));
+        self.content_type("text/plain; charset=utf-8")
+            .status(StatusCode::NOT_FOUND)
+            .send("Not Found")
+    }
+
+    /// Serializes the status line, headers and body, filling in the framing headers.
+    fn create_response(&mut self, body: &[u8]) -> Vec<u8> {
+        self.headers.retain(|k, _| !k.eq_ignore_ascii_case("Content-Length"));
+
+        let body = if self.is_bodiless() {
+            &[]
+        } else {
+            self.headers.insert("Content-Length".to_string(), body.len().to_string());
+            body
+        };
+
+        self.default_header("Date", date::now());
+        // every response is the last on its connection.
+        self.default_header("Connection", "close");
+
+        let mut payload = self.create_head().into_bytes();
+        if !self.is_head() {
+            payload.extend_from_slice(body);
+        }
+        payload
     }
 
-    fn create_response(&self, body: String) -> String {
+    /// The status line and headers, ending with the blank line before the body.
+    fn create_head(&self) -> String {
         let headers = self.headers
             .iter()
             .map(|(key, value)| format!("{}: {}\r\n", key, value))
             .collect::<String>();
 
-        format!(
-            "HTTP/1.1 {} {}\r\n{}\r\n\r\n{}",
-            self.status,
-            status_to_named(self.status),
-            headers,
-            body
-        )
+        format!("HTTP/1.1 {}\r\n{}\r\n", self.status, headers)
+    }
+}
+
+type Renderer<'a> = Box<dyn for<'r> FnOnce(&'r mut Response) -> Result<&'r mut Response, Error> + 'a>;
+
+/// Picks how to render a response from the request's `Accept` header, see `Response::format`.
+pub struct Format<'a> {
+    response: &'a mut Response,
+    renderers: Vec<(String, Renderer<'a>)>,
+    default: Option<Renderer<'a>>,
+}
+
+impl<'a> Format<'a> {
+    /// Renders the response like this for clients that accept the type.
+    /// The `Content-Type` is set to it unless the renderer sets its own.
+    /// Earlier types are preferred when the client likes several equally.
+    pub fn on<T, F>(mut self, mime: T, renderer: F) -> Format<'a>
+    where
+        T: ToString,
+        F: for<'r> FnOnce(&'r mut Response) -> Result<&'r mut Response, Error> + 'a,
+    {
+        self.renderers.push((mime.to_string(), Box::new(renderer)));
+        self
+    }
+
+    /// Renders the response like this when no type is acceptable, instead of answering `406`.
+    pub fn default<F>(mut self, renderer: F) -> Format<'a>
+    where
+        F: for<'r> FnOnce(&'r mut Response) -> Result<&'r mut Response, Error> + 'a,
+    {
+        self.default = Some(Box::new(renderer));
+        self
+    }
+
+    pub fn send(self) -> Result<&'a mut Response, Error> {
+        let Format {
+            response,
+            mut renderers,
+            default,
+        } = self;
+        response.vary("Accept");
+
+        let chosen = {
+            let offers = renderers.iter().map(|(mime, _)| mime.as_str()).collect::<Vec<&str>>();
+            negotiate::media_type(response.request.header("Accept"), &offers)
+                .and_then(|mime| offers.iter().position(|&o| o == mime))
+        };
+
+        if let Some(i) = chosen {
+            let (mime, renderer) = renderers.swap_remove(i);
+            if !response.has_header("Content-Type") {
+                response.content_type(mime);
+            }
+            return renderer(response);
+        }
+
+        if let Some(renderer) = default {
+            return renderer(response);
+        }
+
+        let available = renderers.iter().map(|(mime, _)| mime.as_str()).collect::<Vec<&str>>().join(", ");
+        response
+            .status(StatusCode::NOT_ACCEPTABLE)
+            .content_type("text/plain; charset=utf-8")
+            .send(format!("Not Acceptable, available types: {}", available))
+    }
+}
+
+/// How much a `BodyWriter` buffers before sending a chunk.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+enum BodyMode {
+    /// The status or a HEAD request doesn't allow a body, writes are dropped.
+    Empty,
+    /// Exactly this many bytes are still to be written.
+    Fixed(u64),
+    Chunked,
+}
+
+/// Writes the body of a streamed response, see `Response::stream`.
+///
+/// Call `finish` once done to catch errors, dropping the writer finishes it quietly.
+pub struct BodyWriter<'a> {
+    stream: &'a mut Box<dyn Write + Send>,
+    mode: BodyMode,
+    buffer: Vec<u8>,
+    finished: bool,
+}
+
+impl<'a> BodyWriter<'a> {
+    /// Sends whatever is buffered, as a single chunk when chunked.
+    fn send_buffer(&mut self) -> Result<(), Error> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        if let BodyMode::Chunked = self.mode {
+            write!(self.stream, "{:x}\r\n", self.buffer.len())?;
+            self.stream.write_all(&self.buffer)?;
+            self.stream.write_all(b"\r\n")?;
+        } else {
+            self.stream.write_all(&self.buffer)?;
+        }
+
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Ends the body, failing if fewer bytes were written than the `Content-Length` promised.
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.end()
+    }
+
+    fn end(&mut self) -> Result<(), Error> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.send_buffer()?;
+
+        match self.mode {
+            BodyMode::Chunked => self.stream.write_all(b"0\r\n\r\n")?,
+            BodyMode::Fixed(remaining) if remaining > 0 => {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("Body ended {} bytes short of its Content-Length", remaining),
+                ));
+            }
+            _ => (),
+        }
+
+        self.stream.flush()
+    }
+}
+
+impl<'a> Write for BodyWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        match self.mode {
+            BodyMode::Empty => return Ok(buf.len()),
+            BodyMode::Fixed(remaining) => {
+                if buf.len() as u64 > remaining {
+                    return Err(Error::new(ErrorKind::InvalidInput, "Body is longer than its Content-Length"));
+                }
+                self.mode = BodyMode::Fixed(remaining - buf.len() as u64);
+            }
+            BodyMode::Chunked => (),
+        }
+
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.send_buffer()?;
+        }
+
+        Ok(buf.len())
+    }
+
+    /// Sends what has been written so far to the client.
+    fn flush(&mut self) -> Result<(), Error> {
+        self.send_buffer()?;
+        self.stream.flush()
+    }
+}
+
+impl<'a> Drop for BodyWriter<'a> {
+    fn drop(&mut self) {
+        let _ = self.end();
+    }
+}
+
+/// A boundary that won't turn up inside the parts it separates.
+fn multipart_boundary() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    format!("rusty_server_{:08x}{:08x}", nanos, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use server::deflate;
+    use std::sync::Mutex;
+
+    /// Collects what a response writes, shared so it can be read after the response is done.
+    #[derive(Clone, Default)]
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    /// A response to a `method` request, and what it writes.
+    fn response(method: &str) -> (Response, Sink) {
+        let sink = Sink::default();
+        let mut response = Response::new(sink.clone());
+        response.request.method = method.to_string();
+        response.request.route = "/".to_string();
+        (response, sink)
+    }
+
+    /// A sent response split into its status line, headers and body.
+    struct Sent {
+        status: String,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    }
+
+    impl Sent {
+        fn from(sink: &Sink) -> Sent {
+            let raw = sink.0.lock().unwrap().clone();
+            let split = raw.windows(4).position(|w| w == b"\r\n\r\n").expect("no end to the head");
+            let head = String::from_utf8(raw[..split].to_vec()).unwrap();
+            let mut lines = head.split("\r\n");
+
+            Sent {
+                status: lines.next().unwrap().to_string(),
+                headers: lines
+                    .map(|line| {
+                        let (key, value) = line.split_at(line.find(':').unwrap());
+                        (key.to_string(), value[1..].trim().to_string())
+                    })
+                    .collect(),
+                body: raw[split + 4..].to_vec(),
+            }
+        }
+
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+        }
+    }
+
+    #[test]
+    fn frames_the_body_with_its_length() {
+        let (mut res, sink) = response("GET");
+        res.content_type("text/plain").send("hello").unwrap();
+
+        let sent = Sent::from(&sink);
+        assert_eq!(sent.status, "HTTP/1.1 200 OK");
+        assert_eq!(sent.header("Content-Length"), Some("5"));
+        assert_eq!(sent.header("Content-Type"), Some("text/plain"));
+        assert_eq!(sent.header("Connection"), Some("close"));
+        assert!(sent.header("Date").and_then(date::parse).is_some());
+        assert_eq!(sent.body, b"hello");
+    }
+
+    #[test]
+    fn replaces_a_content_length_set_by_hand() {
+        let (mut res, sink) = response("GET");
+        res.headers.insert("content-length".to_string(), "99".to_string());
+        res.send("hello").unwrap();
+
+        let sent = Sent::from(&sink);
+        let lengths = sent.headers.iter().filter(|(k, _)| k.eq_ignore_ascii_case("Content-Length")).count();
+        assert_eq!((lengths, sent.header("Content-Length")), (1, Some("5")));
+    }
+
+    #[test]
+    fn keeps_a_date_set_by_hand() {
+        let (mut res, sink) = response("GET");
+        res.headers.insert("Date".to_string(), "Sun, 06 Nov 1994 08:49:37 GMT".to_string());
+        res.send("").unwrap();
+        assert_eq!(Sent::from(&sink).header("Date"), Some("Sun, 06 Nov 1994 08:49:37 GMT"));
+    }
+
+    #[test]
+    fn sends_no_body_when_the_status_forbids_one() {
+        for &status in &[204, 304] {
+            let (mut res, sink) = response("GET");
+            res.headers.insert("Content-Length".to_string(), "7".to_string());
+            res.status(status).send("ignored").unwrap();
+
+            let sent = Sent::from(&sink);
+            assert!(sent.status.starts_with(&format!("HTTP/1.1 {} ", status)), "{}", sent.status);
+            assert_eq!(sent.header("Content-Length"), None, "{}", status);
+            assert!(sent.body.is_empty(), "{}", status);
+        }
+    }
+
+    /// Joins a chunked body back together, checking every chunk is framed right.
+    fn dechunk(mut raw: &[u8]) -> (Vec<u8>, Vec<usize>) {
+        let (mut body, mut sizes) = (Vec::new(), Vec::new());
+        loop {
+            let end = raw.windows(2).position(|w| w == b"\r\n").unwrap();
+            let size = usize::from_str_radix(::std::str::from_utf8(&raw[..end]).unwrap(), 16).unwrap();
+            raw = &raw[end + 2..];
+            if size == 0 {
+                assert_eq!(raw, b"\r\n");
+                return (body, sizes);
+            }
+
+            body.extend_from_slice(&raw[..size]);
+            assert_eq!(&raw[size..size + 2], b"\r\n");
+            raw = &raw[size + 2..];
+            sizes.push(size);
+        }
+    }
+
+    #[test]
+    fn streams_chunked_without_a_content_length() {
+        let (mut res, sink) = response("GET");
+        {
+            let mut body = res.stream().unwrap();
+            write!(body, "first ").unwrap();
+            body.flush().unwrap();
+            body.write_all(&[b'x'; CHUNK_SIZE + 10]).unwrap();
+            body.finish().unwrap();
+        }
+
+        let sent = Sent::from(&sink);
+        assert_eq!(sent.header("Transfer-Encoding"), Some("chunked"));
+        assert_eq!(sent.header("Content-Length"), None);
+        assert!(sent.header("Date").is_some());
+
+        let (body, sizes) = dechunk(&sent.body);
+        assert_eq!(sizes, vec![6, CHUNK_SIZE + 10]);
+        assert_eq!(&body[..6], b"first ");
+        assert_eq!(body.len(), 6 + CHUNK_SIZE + 10);
+    }
+
+    #[test]
+    fn streams_as_is_with_a_content_length() {
+        let (mut res, sink) = response("GET");
+        res.headers.insert("Content-Length".to_string(), "11".to_string());
+        {
+            let mut body = res.stream().unwrap();
+            body.write_all(b"hello ").unwrap();
+            body.write_all(b"world").unwrap();
+            body.finish().unwrap();
+        }
+
+        let sent = Sent::from(&sink);
+        assert_eq!(sent.header("Transfer-Encoding"), None);
+        assert_eq!(sent.header("Content-Length"), Some("11"));
+        assert_eq!(sent.body, b"hello world");
+    }
+
+    #[test]
+    fn holds_streams_to_their_content_length() {
+        let (mut res, _) = response("GET");
+        res.headers.insert("Content-Length".to_string(), "4".to_string());
+        let mut body = res.stream().unwrap();
+        assert_eq!(body.write(b"too long").err().map(|e| e.kind()), Some(ErrorKind::InvalidInput));
+        body.write_all(b"ab").unwrap();
+        assert_eq!(body.finish().err().map(|e| e.kind()), Some(ErrorKind::UnexpectedEof));
+
+        let (mut res, _) = response("GET");
+        res.headers.insert("Content-Length".to_string(), "many".to_string());
+        assert_eq!(res.stream().err().map(|e| e.kind()), Some(ErrorKind::InvalidInput));
+    }
+
+    #[test]
+    fn finishes_a_dropped_stream() {
+        let (mut res, sink) = response("GET");
+        write!(res.stream().unwrap(), "dropped").unwrap();
+
+        let (body, _) = dechunk(&Sent::from(&sink).body);
+        assert_eq!(body, b"dropped");
+    }
+
+    #[test]
+    fn streams_nothing_when_there_is_no_body() {
+        let (mut res, sink) = response("HEAD");
+        {
+            let mut body = res.stream().unwrap();
+            body.write_all(b"dropped").unwrap();
+            body.finish().unwrap();
+        }
+        let sent = Sent::from(&sink);
+        assert_eq!(sent.header("Transfer-Encoding"), Some("chunked"));
+        assert!(sent.body.is_empty());
+
+        let (mut res, sink) = response("GET");
+        res.headers.insert("Content-Length".to_string(), "7".to_string());
+        res.status(204);
+        res.stream().unwrap().write_all(b"dropped").unwrap();
+        let sent = Sent::from(&sink);
+        assert_eq!(sent.header("Content-Length"), None);
+        assert_eq!(sent.header("Transfer-Encoding"), None);
+        assert!(sent.body.is_empty());
+    }
+
+    /// A fresh directory for a test's files.
+    fn file_dir(test: &str) -> PathBuf {
+        let dir = ::std::env::temp_dir().join(format!("rusty_server-{}-{}", test, ::std::process::id()));
+        let _ = ::std::fs::remove_dir_all(&dir);
+        ::std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Every byte value, enough times over to need several reads.
+    fn binary() -> Vec<u8> {
+        (0..100).flat_map(|_| 0..=255u8).collect()
+    }
+
+    #[test]
+    fn sends_binary_files_as_they_are() {
+        let dir = file_dir("send-binary");
+        let data = binary();
+        ::std::fs::write(dir.join("blob.bin"), &data).unwrap();
+
+        let (mut res, sink) = response("GET");
+        res.send_file(dir.join("blob.bin").display()).unwrap();
+
+        let sent = Sent::from(&sink);
+        assert_eq!(sent.status, "HTTP/1.1 200 OK");
+        assert_eq!(sent.header("Content-Type"), Some("application/octet-stream"));
+        assert_eq!(sent.header("Content-Length"), Some(&*data.len().to_string()));
+        assert_eq!(sent.header("Transfer-Encoding"), None);
+        assert_eq!(sent.header("Accept-Ranges"), Some("bytes"));
+        assert!(sent.header("Last-Modified").and_then(date::parse).is_some());
+        assert!(sent.header("ETag").and_then(ETag::parse).map(|t| !t.weak).unwrap_or(false));
+        assert!(sent.body == data);
+
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn picks_the_file_type_from_its_extension() {
+        let dir = file_dir("send-types");
+        for name in &["photo.PNG", "notes.txt", "data.custom"] {
+            ::std::fs::write(dir.join(name), b"\x89PNG\r\n").unwrap();
+        }
+
+        let cases: &[(&str, Option<&str>, &str)] = &[
+            ("photo.PNG", None, "image/png"),
+            ("notes.txt", None, "text/plain; charset=utf-8"),
+            ("data.custom", None, "application/x-custom"),
+            ("notes.txt", Some("text/markdown"), "text/markdown"),
+        ];
+
+        for &(name, set, expected) in cases {
+            let (mut res, sink) = response("GET");
+            Arc::make_mut(&mut res.mime_types).insert("custom", "application/x-custom");
+            if let Some(mime) = set {
+                res.content_type(mime);
+            }
+            res.send_path(&dir.join(name)).unwrap();
+            assert_eq!(Sent::from(&sink).header("Content-Type"), Some(expected), "{}", name);
+        }
+
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn answers_missing_files_with_404() {
+        let dir = file_dir("send-missing");
+        for path in &[dir.join("missing.bin"), dir.clone()] {
+            let (mut res, sink) = response("GET");
+            res.send_path(path).unwrap();
+
+            let sent = Sent::from(&sink);
+            assert_eq!(sent.status, "HTTP/1.1 404 Not Found", "{}", path.display());
+            assert_eq!(sent.body, b"Not Found");
+        }
+
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sends_ranges_of_binary_files() {
+        let dir = file_dir("send-ranges");
+        let data = binary();
+        ::std::fs::write(dir.join("blob.bin"), &data).unwrap();
+
+        let (mut res, sink) = response("GET");
+        res.request.headers.insert("Range".to_string(), "bytes=250-259".to_string());
+        res.send_path(&dir.join("blob.bin")).unwrap();
+
+        let sent = Sent::from(&sink);
+        assert_eq!(sent.status, "HTTP/1.1 206 Partial Content");
+        assert_eq!(sent.header("Content-Range"), Some(&*format!("bytes 250-259/{}", data.len())));
+        assert_eq!(sent.body, &data[250..260]);
+
+        // the same range over and over is sent once.
+        let (mut res, sink) = response("GET");
+        let repeated = vec!["0-"; 32].join(",");
+        res.request.headers.insert("Range".to_string(), format!("bytes={}", repeated));
+        res.send_path(&dir.join("blob.bin")).unwrap();
+
+        let sent = Sent::from(&sink);
+        assert_eq!(sent.status, "HTTP/1.1 206 Partial Content");
+        assert_eq!(sent.header("Content-Type"), Some("application/octet-stream"));
+        assert!(sent.body == data);
+
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn answers_head_for_a_file_without_the_body() {
+        let dir = file_dir("send-head");
+        ::std::fs::write(dir.join("blob.bin"), binary()).unwrap();
+
+        let (mut res, sink) = response("HEAD");
+        res.send_path(&dir.join("blob.bin")).unwrap();
+
+        let sent = Sent::from(&sink);
+        assert_eq!(sent.header("Content-Length"), Some("25600"));
+        assert!(sent.body.is_empty());
+
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A GET accepting `accept_encoding`, from a server that compresses bodies of 100 bytes or more.
+    fn compressing(accept_encoding: &str) -> (Response, Sink) {
+        let (mut res, sink) = response("GET");
+        res.compression = Some(Arc::new(Compression::new().threshold(100)));
+        res.request.headers.insert("Accept-Encoding".to_string(), accept_encoding.to_string());
+        (res, sink)
+    }
+
+    fn text(length: usize) -> String {
+        "hello dogs ".repeat(length / 11 + 1)[..length].to_string()
+    }
+
+    #[test]
+    fn compresses_with_the_encoding_the_client_prefers() {
+        let body = text(1000);
+        let cases: &[(&str, Option<&str>)] = &[
+            ("gzip", Some("gzip")),
+            ("gzip;q=0.5, deflate", Some("deflate")),
+            ("gzip;q=0, deflate", Some("deflate")),
+            ("gzip;q=0, deflate;q=0", None),
+            ("identity", None),
+        ];
+
+        for &(accept, expected) in cases {
+            let (mut res, sink) = compressing(accept);
+            res.content_type("text/plain").send(&body).unwrap();
+
+            let sent = Sent::from(&sink);
+            assert_eq!(sent.header("Content-Encoding"), expected, "{}", accept);
+            assert_eq!(sent.header("Vary"), Some("Accept-Encoding"), "{}", accept);
+            assert_eq!(sent.header("Content-Length"), Some(&*sent.body.len().to_string()), "{}", accept);
+
+            let decoded = match expected {
+                Some("gzip") => deflate::gunzip(&sent.body, 4096).unwrap(),
+                Some(_) => deflate::unzlib(&sent.body, 4096).unwrap(),
+                None => sent.body.clone(),
+            };
+            assert_eq!(decoded, body.as_bytes(), "{}", accept);
+            if expected.is_some() {
+                assert!(sent.body.len() < body.len(), "{}", accept);
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_small_and_precompressed_bodies_alone() {
+        let (mut res, sink) = compressing("gzip");
+        res.send(text(99)).unwrap();
+        let sent = Sent::from(&sink);
+        assert_eq!(sent.header("Content-Encoding"), None);
+        // a longer body of the same resource could be compressed.
+        assert_eq!(sent.header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(sent.body, text(99).as_bytes());
+
+        let (mut res, sink) = compressing("gzip");
+        res.content_type("image/png").send(text(1000)).unwrap();
+        let sent = Sent::from(&sink);
+        assert_eq!((sent.header("Content-Encoding"), sent.header("Vary")), (None, None));
+
+        let (mut res, sink) = compressing("gzip");
+        res.headers.insert("Cache-Control".to_string(), "no-transform".to_string());
+        res.send(text(1000)).unwrap();
+        assert_eq!(Sent::from(&sink).header("Content-Encoding"), None);
+    }
+
+    #[test]
+    fn adds_to_an_existing_vary() {
+        let (mut res, sink) = compressing("gzip");
+        res.vary("Accept-Language").send(text(1000)).unwrap();
+        assert_eq!(Sent::from(&sink).header("Vary"), Some("Accept-Language, Accept-Encoding"));
+    }
+
+    #[test]
+    fn weakens_strong_etags_on_compressed_bodies() {
+        let cases: &[(&str, ETag, &str)] = &[
+            ("gzip", ETag::strong("v1"), "W/\"v1\""),
+            ("gzip", ETag::weak("v1"), "W/\"v1\""),
+            ("identity", ETag::strong("v1"), "\"v1\""),
+        ];
+
+        for &(accept, ref tag, expected) in cases {
+            let (mut res, sink) = compressing(accept);
+            res.etag(tag.clone()).send(text(1000)).unwrap();
+            assert_eq!(Sent::from(&sink).header("ETag"), Some(expected), "{} {}", accept, tag);
+        }
+    }
+
+    #[test]
+    fn compresses_text_files() {
+        let dir = file_dir("send-compressed");
+        ::std::fs::write(dir.join("page.html"), text(5000)).unwrap();
+        ::std::fs::write(dir.join("blob.bin"), binary()).unwrap();
+
+        let (mut res, sink) = compressing("gzip");
+        res.send_path(&dir.join("page.html")).unwrap();
+        let sent = Sent::from(&sink);
+        assert_eq!(sent.header("Content-Encoding"), Some("gzip"));
+        assert!(sent.header("ETag").map(|t| t.starts_with("W/")).unwrap_or(false));
+        assert_eq!(deflate::gunzip(&sent.body, 8192).unwrap(), text(5000).as_bytes());
+
+        let (mut res, sink) = compressing("gzip");
+        res.send_path(&dir.join("blob.bin")).unwrap();
+        assert_eq!(Sent::from(&sink).header("Content-Encoding"), None);
+
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn answers_head_with_the_length_a_get_would_have() {
+        let (mut res, sink) = response("HEAD");
+        res.send("hello").unwrap();
+
+        let sent = Sent::from(&sink);
+        assert_eq!(sent.status, "HTTP/1.1 200 OK");
+        assert_eq!(sent.header("Content-Length"), Some("5"));
+        assert!(sent.body.is_empty());
     }
 }
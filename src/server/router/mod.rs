@@ -1,9 +1,13 @@
 use std::collections::HashMap;
 use std::string::ToString;
-use std::io::{Error, ErrorKind};
+use std::io::Error;
 use std::sync::Arc;
-use server::request::Request;
+use server::request::{Rejection, Request};
 use server::response::Response;
+use server::static_files::{StaticDir, STATIC_PARAM};
+use server::controller::{Controller, Resource};
+use server::state::State;
+use std::path::Path;
 
 #[derive(PartialEq, Eq, Hash, Clone)]
 pub enum Methods {
@@ -15,6 +19,35 @@ pub enum Methods {
     ALL,
 }
 
+impl Methods {
+    /// Every method a request can be routed by, in the order they're listed in `Allow`.
+    const ROUTABLE: [Methods; 5] = [Methods::GET, Methods::POST, Methods::PUT, Methods::PATCH, Methods::DELETE];
+
+    /// The routes a request's method is looked up in, `None` for one routes can't be set for.
+    /// HEAD is answered by the GET routes.
+    fn for_request(method: &str) -> Option<Methods> {
+        match method.to_uppercase().as_ref() {
+            "GET" | "HEAD" => Some(Methods::GET),
+            "POST" => Some(Methods::POST),
+            "PUT" => Some(Methods::PUT),
+            "DELETE" => Some(Methods::DELETE),
+            "PATCH" => Some(Methods::PATCH),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Methods::GET => "GET",
+            Methods::POST => "POST",
+            Methods::PUT => "PUT",
+            Methods::DELETE => "DELETE",
+            Methods::PATCH => "PATCH",
+            Methods::ALL => "*",
+        }
+    }
+}
+
 pub trait RouterMethod {
     fn parse(&self) -> Methods;
 }
@@ -28,33 +61,61 @@ impl<T: ToString> RouterMethod for T {
             "DELETE" => Methods::DELETE,
             "PATCH" => Methods::PATCH,
             "*" => Methods::ALL,
-            _ => panic!(format!("{} is not a (supported) method!", self.to_string())),
+            _ => panic!("{} is not a (supported) method!", self.to_string()),
         }
     }
 }
 
 impl RouterMethod for Methods {
     fn parse(&self) -> Methods {
-        let en = self.clone();
-        en
+        self.clone()
     }
 }
 
 pub trait RouterAction: Send + Sync + 'static {
-    fn call(&self, Request, Response) -> ();
+    fn call(&self, request: Request, response: Response);
+
+    /// Whether a POST may be routed here as a PUT, PATCH or DELETE, see `Controller`.
+    fn accepts_method_override(&self) -> bool {
+        false
+    }
 }
 
 impl<T> RouterAction for T
 where
-    T: Fn(Request, Response) -> () + Send + Sync + 'static,
+    T: Fn(Request, Response) + Send + Sync + 'static,
+{
+    fn call(&self, request: Request, response: Response) {
+        self(request, response);
+    }
+}
+
+/// Runs before an action, eg to check a login. Returning `false` stops the request there,
+/// after the middleware has answered it itself. Values for what runs after, like the logged in
+/// user, can be attached with `req.extensions.insert(user)`.
+///
+/// `|req: &mut Request, res: &mut Response| { if logged_in(req) { return true; } let _ = res.redirect("/login"); false }`
+pub trait Middleware: Send + Sync + 'static {
+    fn before(&self, request: &mut Request, response: &mut Response) -> bool;
+}
+
+impl<T> Middleware for T
+where
+    T: Fn(&mut Request, &mut Response) -> bool + Send + Sync + 'static,
 {
-    fn call(&self, request: Request, response: Response) -> () {
-        (&self)(request, response);
+    fn before(&self, request: &mut Request, response: &mut Response) -> bool {
+        self(request, response)
     }
 }
 
+/// A resolved route: the action to call and the params captured from the path.
+pub type RouteMatch = (Arc<dyn RouterAction>, HashMap<String, String>);
+
+#[derive(Default)]
 pub struct Router {
-    pub routes: HashMap<Methods, HashMap<String, Arc<RouterAction>>>,
+    pub routes: HashMap<Methods, HashMap<String, Arc<dyn RouterAction>>>,
+    /// Shared with this router's handlers over the server's, see `with_state`.
+    pub(crate) state: State,
 }
 
 impl Router {
@@ -62,9 +123,17 @@ impl Router {
     pub fn new() -> Router {
         Router {
             routes: HashMap::new(),
+            state: State::new(),
         }
     }
 
+    /// Shares a value with this router's handlers, read with `req.state::<T>()`.
+    /// It takes the place of any value of the same type set with `Server::with_state`.
+    pub fn with_state<T: Send + Sync + 'static>(&mut self, value: T) -> &mut Router {
+        self.state.insert(value);
+        self
+    }
+
     /// Creates a new route/path
     /// `route(HTTP_METHOD, PATH, ACTION)`
     ///
@@ -81,34 +150,70 @@ impl Router {
     {
         self.routes
             .entry(method.parse())
-            .or_insert(HashMap::new())
+            .or_default()
             .insert(path.to_string(), Arc::new(action));
         self
     }
 
 
     /// Searches the routers for the correct path, finding the action for the path.
-    /// It also finds params within the url, like `dog/:id/`.
+    /// It also finds params within the url, like `dog/:id/`,
+    /// and a trailing `*name` captures the rest of the path, like `files/*path`.
+    /// When several routes match, fixed segments win over params, so `/dogs/new` beats `/dogs/:id`.
+    ///
+    /// A HEAD is routed as a GET. When nothing matches the error holds a `Rejection`,
+    /// `MethodNotAllowed` if the path has routes for other methods and `NotImplemented`
+    /// for a method that can't be routed at all.
     pub fn find_route(
         &self,
         method: String,
         path: String,
-    ) -> Result<(Arc<RouterAction>, HashMap<String, String>), Error> {
-        let routes = self.routes.get(&method.parse());
-
-        let routes = match routes {
+    ) -> Result<RouteMatch, Error> {
+        let routed = match Methods::for_request(&method) {
             Some(v) => v,
-            None => return Err(Error::new(ErrorKind::NotFound, "404")),
+            None => return Err(Rejection::NotImplemented(method).into_error()),
         };
 
-        let split_path = path.to_string();
-        let split_path = split_path.split('/').collect::<Vec<&str>>();
+        if let Some(found) = self.routes.get(&routed).and_then(|routes| Router::match_path(routes, &path)) {
+            return Ok(found);
+        }
+
+        let allowed = Methods::ROUTABLE
+            .iter()
+            .filter(|m| self.routes.get(m).and_then(|routes| Router::match_path(routes, &path)).is_some())
+            .flat_map(|m| if *m == Methods::GET { vec!["GET", "HEAD"] } else { vec![m.name()] })
+            .collect::<Vec<&str>>();
+
+        if allowed.is_empty() {
+            Err(Rejection::NotFound.into_error())
+        } else {
+            Err(Rejection::MethodNotAllowed(allowed.join(", ")).into_error())
+        }
+    }
+
+    /// The best of one method's routes for the path.
+    fn match_path(routes: &HashMap<String, Arc<dyn RouterAction>>, path: &str) -> Option<RouteMatch> {
+        let split_path = split_segments(path);
+        let mut best: Option<(Vec<u8>, RouteMatch)> = None;
 
         for (route, method) in routes.iter() {
-            let template = route.split('/').collect::<Vec<&str>>();
+            let template = split_segments(route);
             let mut params: HashMap<String, String> = HashMap::new();
 
-            if template.iter().zip(&split_path).all(
+            // a `*name` segment swallows the rest of the path.
+            let wildcard = template.iter().position(|seg| seg.starts_with('*'));
+            let (template, rest) = match wildcard {
+                Some(pos) if split_path.len() >= pos => {
+                    let rest = split_path[pos..].join("/");
+                    params.insert(template[pos].trim_start_matches('*').to_string(), rest);
+                    (&template[..pos], &split_path[..pos])
+                }
+                Some(_) => continue,
+                None if template.len() == split_path.len() => (&template[..], &split_path[..]),
+                None => continue,
+            };
+
+            let matched = template.iter().zip(rest).all(
                 |(templ_seg, path_seg)| {
                     if templ_seg.contains(':') {
                         params.insert(
@@ -119,13 +224,26 @@ impl Router {
                     }
                     templ_seg == path_seg
                 },
-            ) {
-                let method_copy = method.clone();
-                return Ok((method_copy, params));
+            );
+            if !matched {
+                continue;
+            }
+
+            // fixed segments rank over params, earlier ones first, and wildcards last.
+            let mut rank = template
+                .iter()
+                .map(|seg| if seg.contains(':') { 1 } else { 2 })
+                .collect::<Vec<u8>>();
+            if wildcard.is_none() {
+                rank.push(1);
+            }
+
+            if best.as_ref().map(|(r, _)| rank > *r).unwrap_or(true) {
+                best = Some((rank, (method.clone(), params)));
             }
         }
 
-        Err(Error::new(ErrorKind::NotFound, "404"))
+        best.map(|(_, found)| found)
     }
 
     /// # Shorthand methods. .get instead of .route("GET")
@@ -148,4 +266,50 @@ impl Router {
     pub fn delete<T: RouterAction, S: ToString>(&mut self, path: S, action: T) -> &mut Router {
         self.route(Methods::DELETE, path, action)
     }
+
+    /// Serves the files under `root` at `mount`, eg `router.static_dir("/assets", "./public")`
+    /// serves `./public/css/site.css` as `/assets/css/site.css`.
+    pub fn static_dir<M: ToString, P: AsRef<Path>>(&mut self, mount: M, root: P) -> &mut Router {
+        self.static_dir_with(mount, StaticDir::new(root))
+    }
+
+    /// Serves a configured `StaticDir` at `mount`, for directory listings or a SPA fallback.
+    ///
+    /// `router.static_dir_with("/", StaticDir::new("./dist").fallback("index.html"))`
+    pub fn static_dir_with<M: ToString>(&mut self, mount: M, dir: StaticDir) -> &mut Router {
+        let mount = mount.to_string();
+        let path = format!("{}/*{}", mount.trim_end_matches('/'), STATIC_PARAM);
+        self.get(path, dir)
+    }
+
+    /// Routes the conventional RESTful paths at `path` to the actions a controller implements,
+    /// see `Controller` for the table.
+    ///
+    /// `router.resource("/dogs", DogController)`
+    pub fn resource<P: ToString, C: Controller>(&mut self, path: P, controller: C) -> &mut Router {
+        self.resource_with(path, Resource::new(controller))
+    }
+
+    /// Routes a configured `Resource`, for a subset of its actions or with middleware.
+    ///
+    /// `router.resource_with("/dogs", Resource::new(DogController).only(&[Action::Index, Action::Show]))`
+    pub fn resource_with<P: ToString, C: Controller>(&mut self, path: P, resource: Resource<C>) -> &mut Router {
+        let base = path.to_string().trim_end_matches('/').to_string();
+
+        for (action, handler) in resource.actions() {
+            for method in action.methods() {
+                self.routes
+                    .entry(method.clone())
+                    .or_default()
+                    .insert(format!("{}{}", base, action.suffix()), handler.clone());
+            }
+        }
+        self
+    }
+}
+
+/// Splits a path into its segments, ignoring a trailing `/` so `/dogs/` is `/dogs`.
+fn split_segments(path: &str) -> Vec<&str> {
+    let path = if path.len() > 1 { path.trim_end_matches('/') } else { path };
+    path.split('/').collect()
 }
@@ -0,0 +1,253 @@
+use std::thread::JoinHandle;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use server::connection::Address;
+
+/// The flag an accept loop watches to know when to stop taking connections.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    stop: Arc<AtomicBool>,
+    /// The listeners to connect to once triggered, so their blocked accepts return and see the flag.
+    wake: Arc<Mutex<Vec<Address>>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    /// Asks the server to stop, waking its accept loops.
+    pub fn trigger(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+
+        let wake = self.wake.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for address in wake.iter() {
+            let _ = address.connect();
+        }
+    }
+
+    /// Whether the server has been asked to stop, either from code or by a signal
+    /// if it is watching for them.
+    pub fn is_triggered(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+
+    /// Connects to `address` when triggered, for an accept loop blocked on it.
+    /// Must be called before the loop starts, or a trigger in between can be missed.
+    pub fn wake_on_trigger(&self, address: Address) {
+        self.wake
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(address);
+    }
+}
+
+/// Controls a running server, returned from `Server::spawn`.
+///
+/// Dropping the handle leaves the server running in the background.
+pub struct ShutdownHandle {
+    shutdown: Shutdown,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ShutdownHandle {
+    pub fn new(shutdown: Shutdown, thread: JoinHandle<()>) -> ShutdownHandle {
+        ShutdownHandle {
+            shutdown,
+            thread: Some(thread),
+        }
+    }
+
+    /// Stops accepting new connections, without waiting for the server to finish.
+    pub fn stop(&self) {
+        self.shutdown.trigger();
+    }
+
+    /// Also stop the server when the process receives SIGINT or SIGTERM.
+    /// Does nothing on platforms without unix signals.
+    pub fn on_signals(&self) -> &ShutdownHandle {
+        signals::watch(self.shutdown.clone());
+        self
+    }
+
+    /// Blocks until the server has stopped and its workers have been joined.
+    pub fn wait(mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    /// Stops the server and waits for the requests in flight to finish.
+    pub fn shutdown(self) {
+        self.stop();
+        self.wait();
+    }
+}
+
+#[cfg(unix)]
+mod signals {
+    use std::io::{ErrorKind, Read};
+    use std::os::raw::{c_int, c_void};
+    use std::os::unix::io::IntoRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::sync::{Mutex, MutexGuard, Once};
+    use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+    use std::thread;
+
+    use super::Shutdown;
+
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+
+    #[allow(non_camel_case_types)]
+    type sighandler_t = usize;
+    const SIG_ERR: sighandler_t = !0;
+
+    static RECEIVED: AtomicBool = AtomicBool::new(false);
+    static INSTALL: Once = Once::new();
+    /// The end of a socket pair the handler writes to, -1 until installed.
+    static WAKE_FD: AtomicI32 = AtomicI32::new(-1);
+    static WATCHERS: Mutex<Vec<Shutdown>> = Mutex::new(Vec::new());
+
+    extern "C" {
+        fn signal(signum: c_int, handler: sighandler_t) -> sighandler_t;
+        fn write(fd: c_int, buf: *const c_void, count: usize) -> isize;
+    }
+
+    // only touches an atomic and calls `write`, which keeps it safe to run inside a signal handler.
+    extern "C" fn on_signal(_: c_int) {
+        RECEIVED.store(true, Ordering::SeqCst);
+
+        let fd = WAKE_FD.load(Ordering::SeqCst);
+        if fd >= 0 {
+            let byte = 1u8;
+            unsafe {
+                write(fd, &byte as *const u8 as *const c_void, 1);
+            }
+        }
+    }
+
+    fn watchers() -> MutexGuard<'static, Vec<Shutdown>> {
+        WATCHERS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Triggers `shutdown` on SIGINT or SIGTERM.
+    pub fn watch(shutdown: Shutdown) {
+        INSTALL.call_once(install);
+
+        // registered before checking, so a signal in between is seen by one or the other.
+        watchers().push(shutdown.clone());
+        if RECEIVED.load(Ordering::SeqCst) {
+            shutdown.trigger();
+        }
+    }
+
+    /// Installs the handlers, with a thread that sleeps until one of them writes to it
+    /// and then triggers every shutdown watching.
+    fn install() {
+        let (mut reader, writer) = match UnixStream::pair() {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Couldn't watch for signals: {}", e);
+                return;
+            }
+        };
+        WAKE_FD.store(writer.into_raw_fd(), Ordering::SeqCst);
+
+        thread::spawn(move || {
+            let mut byte = [0; 1];
+            loop {
+                match reader.read(&mut byte) {
+                    Ok(0) => break,
+                    Ok(_) => {
+                        for shutdown in watchers().iter() {
+                            shutdown.trigger();
+                        }
+                    }
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                }
+            }
+        });
+
+        let handler = on_signal as extern "C" fn(c_int) as sighandler_t;
+        for &signum in &[SIGINT, SIGTERM] {
+            if unsafe { signal(signum, handler) } == SIG_ERR {
+                eprintln!("Couldn't install a handler for signal {}", signum);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod signals {
+    use super::Shutdown;
+
+    pub fn watch(_: Shutdown) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn trigger_is_seen_by_every_clone() {
+        let shutdown = Shutdown::new();
+        let clone = shutdown.clone();
+        assert!(!shutdown.is_triggered());
+
+        clone.trigger();
+        assert!(shutdown.is_triggered());
+        assert!(clone.is_triggered());
+        assert!(!Shutdown::new().is_triggered());
+    }
+
+    #[test]
+    fn trigger_wakes_a_blocked_accept() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let shutdown = Shutdown::new();
+        shutdown.wake_on_trigger(Address::Tcp(listener.local_addr().unwrap()));
+
+        let watching = shutdown.clone();
+        let acceptor = thread::spawn(move || {
+            let accepted = listener.accept().is_ok();
+            (accepted, watching.is_triggered())
+        });
+
+        thread::sleep(Duration::from_millis(20));
+        shutdown.trigger();
+        assert_eq!(acceptor.join().unwrap(), (true, true));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn trigger_wakes_a_blocked_unix_accept() {
+        use std::os::unix::net::UnixListener;
+
+        let path = ::std::env::temp_dir().join(format!("rusty_server-wake-{}.sock", ::std::process::id()));
+        let _ = ::std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let shutdown = Shutdown::new();
+        shutdown.wake_on_trigger(Address::Unix(path.clone()));
+
+        let acceptor = thread::spawn(move || listener.accept().is_ok());
+        shutdown.trigger();
+        assert!(acceptor.join().unwrap());
+
+        ::std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn trigger_skips_addresses_nobody_listens_on() {
+        let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let shutdown = Shutdown::new();
+        shutdown.wake_on_trigger(Address::Tcp(address));
+
+        shutdown.trigger();
+        assert!(shutdown.is_triggered());
+    }
+}
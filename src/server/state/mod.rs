@@ -0,0 +1,116 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+
+/// Values shared with handlers, one of each type, eg a connection pool or the app's config.
+/// Set them with `Server::with_state` or `Router::with_state` and read them with `req.state::<T>()`.
+///
+/// Cloning only clones an `Arc`, so each request gets its own handle for free.
+#[derive(Clone, Default)]
+pub struct State {
+    values: Arc<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>,
+}
+
+impl State {
+    pub fn new() -> State {
+        State::default()
+    }
+
+    /// Sets the value of its type, replacing any already set.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) {
+        Arc::make_mut(&mut self.values).insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// The value of a type, `None` if there isn't one.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .cloned()
+            .and_then(|v| v.downcast::<T>().ok())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// These values with `over`'s on top, for a router's state over the server's.
+    pub(crate) fn overlaid(&self, over: &State) -> State {
+        if over.is_empty() {
+            return self.clone();
+        }
+        if self.is_empty() {
+            return over.clone();
+        }
+
+        let mut values = (*self.values).clone();
+        values.extend(over.values.iter().map(|(k, v)| (*k, v.clone())));
+        State {
+            values: Arc::new(values),
+        }
+    }
+}
+
+impl Debug for State {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "State({} values)", self.values.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Config(&'static str);
+
+    #[derive(Debug, PartialEq)]
+    struct Pool(u32);
+
+    #[test]
+    fn keeps_one_value_per_type() {
+        let mut state = State::new();
+        assert!(state.get::<Config>().is_none());
+
+        state.insert(Config("first"));
+        state.insert(Pool(4));
+        state.insert(Config("second"));
+        assert_eq!(state.len(), 2);
+        assert_eq!(state.get::<Config>().as_deref(), Some(&Config("second")));
+        assert_eq!(state.get::<Pool>().as_deref(), Some(&Pool(4)));
+    }
+
+    #[test]
+    fn clones_share_values_set_before() {
+        let mut state = State::new();
+        state.insert(Pool(4));
+        let clone = state.clone();
+        state.insert(Pool(8));
+
+        assert_eq!(clone.get::<Pool>().as_deref(), Some(&Pool(4)));
+        assert_eq!(state.get::<Pool>().as_deref(), Some(&Pool(8)));
+    }
+
+    #[test]
+    fn overlaid_values_win() {
+        let mut server = State::new();
+        server.insert(Config("server"));
+        server.insert(Pool(4));
+        let mut router = State::new();
+        router.insert(Config("router"));
+
+        let overlaid = server.overlaid(&router);
+        assert_eq!(overlaid.get::<Config>().as_deref(), Some(&Config("router")));
+        assert_eq!(overlaid.get::<Pool>().as_deref(), Some(&Pool(4)));
+        // neither side is changed.
+        assert_eq!(server.get::<Config>().as_deref(), Some(&Config("server")));
+        assert_eq!(router.len(), 1);
+
+        assert_eq!(server.overlaid(&State::new()).len(), 2);
+        assert_eq!(State::new().overlaid(&router).get::<Config>().as_deref(), Some(&Config("router")));
+    }
+}
@@ -0,0 +1,318 @@
+use std::fs;
+use std::io::Error;
+use std::path::{Component, Path, PathBuf};
+
+use server::request::Request;
+use server::response::Response;
+use server::router::RouterAction;
+use server::status::StatusCode;
+
+/// The route param `Router::static_dir` captures the file path into.
+pub const STATIC_PARAM: &str = "static_path";
+
+/// Serves the files under a directory, mount it with `Router::static_dir_with`.
+///
+/// Requests can't reach outside of the root, paths with `..` are refused and
+/// symlinks that lead out of it are treated as missing.
+#[derive(Debug, Clone)]
+pub struct StaticDir {
+    root: PathBuf,
+    index: Option<String>,
+    listing: bool,
+    fallback: Option<PathBuf>,
+}
+
+impl StaticDir {
+    /// Serves `root`, with `index.html` for directories and no listings.
+    pub fn new<P: AsRef<Path>>(root: P) -> StaticDir {
+        StaticDir {
+            root: root.as_ref().to_path_buf(),
+            index: Some("index.html".to_string()),
+            listing: false,
+            fallback: None,
+        }
+    }
+
+    /// Sets the file served for a directory, `None` to never serve one.
+    pub fn index<T: ToString>(mut self, index: Option<T>) -> StaticDir {
+        self.index = index.map(|v| v.to_string());
+        self
+    }
+
+    /// Renders a list of the files in a directory that has no index file.
+    pub fn listing(mut self, listing: bool) -> StaticDir {
+        self.listing = listing;
+        self
+    }
+
+    /// Serves this file, relative to the root, for any path that doesn't exist.
+    /// For single page apps that do their own routing, eg `.fallback("index.html")`.
+    pub fn fallback<P: AsRef<Path>>(mut self, fallback: P) -> StaticDir {
+        self.fallback = Some(fallback.as_ref().to_path_buf());
+        self
+    }
+
+    fn serve(&self, request: &Request, response: &mut Response) -> Result<(), Error> {
+        let requested = request
+            .params
+            .as_ref()
+            .and_then(|p| p.get(STATIC_PARAM))
+            .map(|v| v.as_str())
+            .unwrap_or("");
+
+        let relative = match safe_relative_path(requested) {
+            Some(v) => v,
+            None => {
+                response
+                    .status(StatusCode::FORBIDDEN)
+                    .content_type("text/plain; charset=utf-8")
+                    .send("Forbidden")?;
+                return Ok(());
+            }
+        };
+
+        let root = match fs::canonicalize(&self.root) {
+            Ok(v) => v,
+            Err(_) => return self.missing(&self.root, response),
+        };
+
+        let target = match contained(&root, &relative) {
+            Some(v) => v,
+            None => return self.missing(&root, response),
+        };
+
+        if !target.is_dir() {
+            response.send_path(&target)?;
+            return Ok(());
+        }
+
+        if let Some(ref index) = self.index {
+            if let Some(index) = contained(&root, &relative.join(index)) {
+                if index.is_file() {
+                    response.send_path(&index)?;
+                    return Ok(());
+                }
+            }
+        }
+
+        if self.listing {
+            return self.send_listing(&request.route, &target, response);
+        }
+
+        self.missing(&root, response)
+    }
+
+    /// Answers a path that isn't there, with the fallback file if there is one.
+    fn missing(&self, root: &Path, response: &mut Response) -> Result<(), Error> {
+        if let Some(ref fallback) = self.fallback {
+            if let Some(fallback) = contained(root, fallback) {
+                response.send_path(&fallback)?;
+                return Ok(());
+            }
+        }
+
+        response
+            .status(StatusCode::NOT_FOUND)
+            .content_type("text/plain; charset=utf-8")
+            .send("Not Found")?;
+        Ok(())
+    }
+
+    fn send_listing(&self, route: &str, dir: &Path, response: &mut Response) -> Result<(), Error> {
+        let mut names = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let mut name = entry.file_name().to_string_lossy().to_string();
+                if entry.path().is_dir() {
+                    name.push('/');
+                }
+                name
+            })
+            .collect::<Vec<String>>();
+        names.sort();
+
+        let base = format!("{}/", route.trim_end_matches('/'));
+        let items = names
+            .iter()
+            .map(|name| {
+                format!(
+                    "<li><a href=\"{}{}\">{}</a></li>\n",
+                    escape_html(&base),
+                    escape_html(&encode_path(name)),
+                    escape_html(name)
+                )
+            })
+            .collect::<String>();
+
+        let title = escape_html(&base);
+        let page = format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {}</title></head>\n\
+             <body>\n<h1>Index of {}</h1>\n<ul>\n{}</ul>\n</body>\n</html>\n",
+            title, title, items
+        );
+
+        response.content_type("text/html; charset=utf-8").send(page)?;
+        Ok(())
+    }
+}
+
+impl RouterAction for StaticDir {
+    fn call(&self, request: Request, mut response: Response) {
+        let _ = self.serve(&request, &mut response);
+    }
+}
+
+/// Decodes a request path into a relative path, refusing anything that climbs out with `..`,
+/// is absolute, or holds characters that could be read as a path separator.
+fn safe_relative_path(requested: &str) -> Option<PathBuf> {
+    let decoded = percent_decode(requested)?;
+    if decoded.contains('\0') || decoded.contains('\\') {
+        return None;
+    }
+
+    let mut path = PathBuf::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => return None,
+            _ => (),
+        }
+
+        match Path::new(segment).components().next() {
+            Some(Component::Normal(_)) => path.push(segment),
+            _ => return None,
+        }
+    }
+
+    Some(path)
+}
+
+/// Resolves `relative` under `root` (already canonical), following symlinks,
+/// `None` if it doesn't exist or ends up outside of the root.
+fn contained(root: &Path, relative: &Path) -> Option<PathBuf> {
+    let target = fs::canonicalize(root.join(relative)).ok()?;
+
+    if target.starts_with(root) {
+        Some(target)
+    } else {
+        None
+    }
+}
+
+/// Decodes `%XX` escapes, `None` if an escape is malformed or the result isn't UTF-8.
+fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = input.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+/// Escapes the characters in a path segment that can't appear in a URL as they are.
+fn encode_path(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&#39;".to_string(),
+            _ => c.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_percent_escapes() {
+        let cases: &[(&str, Option<&str>)] = &[
+            ("plain.txt", Some("plain.txt")),
+            ("a%20b", Some("a b")),
+            ("%2e%2E", Some("..")),
+            ("%e2%9c%93", Some("\u{2713}")),
+            ("100%", None),
+            ("%2", None),
+            ("%zz", None),
+            ("%ff", None),
+        ];
+
+        for &(input, expected) in cases {
+            assert_eq!(percent_decode(input).as_deref(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn keeps_paths_under_the_root() {
+        let cases: &[(&str, Option<&str>)] = &[
+            ("", Some("")),
+            ("css/site.css", Some("css/site.css")),
+            ("./css//site.css/", Some("css/site.css")),
+            ("my%20file.txt", Some("my file.txt")),
+            ("../secret", None),
+            ("css/../../secret", None),
+            ("%2e%2e/secret", None),
+            ("%2E%2E%2Fsecret", None),
+            ("..\\secret", None),
+            ("css%5c..%5csecret", None),
+            ("secret%00.txt", None),
+            ("%zz", None),
+        ];
+
+        for &(requested, expected) in cases {
+            assert_eq!(safe_relative_path(requested), expected.map(PathBuf::from), "{}", requested);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlinks_out_of_the_root() {
+        use std::os::unix::fs::symlink;
+
+        let dir = ::std::env::temp_dir().join(format!("rusty_server-contained-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("public")).unwrap();
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+        fs::write(dir.join("public/page.txt"), "page").unwrap();
+        symlink(dir.join("secret.txt"), dir.join("public/escape.txt")).unwrap();
+        symlink(dir.join("public/page.txt"), dir.join("public/inside.txt")).unwrap();
+
+        let root = fs::canonicalize(dir.join("public")).unwrap();
+        assert_eq!(contained(&root, Path::new("page.txt")), Some(root.join("page.txt")));
+        assert_eq!(contained(&root, Path::new("inside.txt")), Some(root.join("page.txt")));
+        assert_eq!(contained(&root, Path::new("escape.txt")), None);
+        assert_eq!(contained(&root, Path::new("missing.txt")), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(escape_html("<a href=\"x\">'&'</a>"), "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;");
+    }
+}
@@ -0,0 +1,1219 @@
+//! HTML templates, loaded from a directory and compiled once.
+//!
+//! * `{{ dog.name }}` prints a value, HTML escaped, `{{ bio | raw }}` as it is.
+//!   The other filters are `upper`, `lower`, `trim`, `length` and `default("none")`.
+//! * `{% if dog.good and not dog.asleep %}`, `{% elif ... %}`, `{% else %}`, `{% endif %}`,
+//!   comparing with `==`, `!=`, `<`, `>`, `<=` and `>=`.
+//! * `{% for dog in dogs %} {{ loop.index }} {% else %} no dogs {% endfor %}`,
+//!   or `{% for key, value in object %}`. `loop` also has `index0`, `first`, `last` and `length`.
+//! * `{% include "partials/nav" %}` renders another template in place.
+//! * `{% extends "layout" %}` renders the layout instead, with the template's
+//!   `{% block content %}...{% endblock %}` replacing the layout's block of the same name.
+//! * `{# comments #}` are left out.
+//!
+//! Missing values print as nothing rather than being an error.
+//!
+//! In development mode, see `Server::development`, templates are compiled again whenever
+//! their file changes, and a template that fails is shown on an error page with its source.
+
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use server::static_files::escape_html;
+use server::value::Value;
+
+const DEFAULT_EXTENSION: &str = "html";
+
+/// Lines of source shown either side of the one with the error, on the error page.
+const ERROR_CONTEXT_LINES: usize = 3;
+
+/// How deeply includes and layouts can nest before it's taken to be a loop.
+const MAX_DEPTH: usize = 32;
+
+const FILTERS: [&str; 6] = ["raw", "upper", "lower", "trim", "length", "default"];
+
+/// A template that failed to load, compile or render, with where it went wrong.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateError {
+    pub template: String,
+    /// The line the problem is on, `0` if it isn't on any, like a missing template.
+    pub line: usize,
+    pub message: String,
+}
+
+impl TemplateError {
+    fn new<T: ToString>(template: &str, line: usize, message: T) -> TemplateError {
+        TemplateError {
+            template: template.to_string(),
+            line,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn from_error(error: &Error) -> Option<&TemplateError> {
+        error.get_ref().and_then(|e| e.downcast_ref::<TemplateError>())
+    }
+
+    fn into_error(self) -> Error {
+        Error::new(ErrorKind::InvalidData, self)
+    }
+}
+
+impl Display for TemplateError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "{}, line {}: {}", self.template, self.line, self.message)
+        } else {
+            write!(f, "{}: {}", self.template, self.message)
+        }
+    }
+}
+
+impl StdError for TemplateError {}
+
+/// The templates in a directory, set on the server with `server.templates(Templates::new("views"))`
+/// and rendered with `res.render("dogs/show", context)`.
+///
+/// Names are paths under the directory without the extension, so `dogs/show` is `views/dogs/show.html`.
+/// Each template is compiled the first time it's used and kept for after, unless reloading.
+pub struct Templates {
+    root: PathBuf,
+    extension: String,
+    reload: bool,
+    cache: Mutex<HashMap<String, Cached>>,
+}
+
+struct Cached {
+    template: Arc<Template>,
+    /// Whether it was read from a file rather than added from a string.
+    from_file: bool,
+    /// When the file was last modified as of compiling it.
+    modified: Option<SystemTime>,
+}
+
+impl Templates {
+    pub fn new<P: AsRef<Path>>(root: P) -> Templates {
+        Templates {
+            root: root.as_ref().to_path_buf(),
+            extension: DEFAULT_EXTENSION.to_string(),
+            reload: false,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The extension template files have, `html` by default.
+    pub fn extension<T: ToString>(mut self, extension: T) -> Templates {
+        self.extension = extension.to_string().trim_start_matches('.').to_string();
+        self
+    }
+
+    /// Compiles templates again when their files change, rather than keeping the first compile.
+    /// Always the case for `res.render` in development mode.
+    pub fn reload(mut self, reload: bool) -> Templates {
+        self.reload = reload;
+        self
+    }
+
+    /// Adds a template from a string rather than a file, eg one built into the binary.
+    pub fn add(&self, name: &str, source: &str) -> Result<(), Error> {
+        let template = Template::compile(name, source).map_err(|e| e.into_error())?;
+        let cached = Cached {
+            template: Arc::new(template),
+            from_file: false,
+            modified: None,
+        };
+        self.cache().insert(name.to_string(), cached);
+        Ok(())
+    }
+
+    /// Renders a template with the values in the context.
+    ///
+    /// A template that's missing or doesn't compile or render is an `InvalidData` error
+    /// carrying a `TemplateError`.
+    pub fn render(&self, name: &str, context: &Value) -> Result<String, Error> {
+        self.render_with(name, context, self.reload)
+    }
+
+    /// `render`, also reloading changed templates if `reload` is set.
+    pub(crate) fn render_with(&self, name: &str, context: &Value, reload: bool) -> Result<String, Error> {
+        let mut out = String::new();
+        let mut scope = Scope {
+            root: context,
+            locals: Vec::new(),
+        };
+
+        self.render_into(name, &mut scope, &mut out, reload || self.reload, 0)
+            .map(|_| out)
+            .map_err(|e| e.into_error())
+    }
+
+    fn get(&self, name: &str, reload: bool) -> Result<Arc<Template>, TemplateError> {
+        let cached = self.cache().get(name).map(|c| (c.template.clone(), c.from_file, c.modified));
+
+        let path = match cached {
+            Some((template, from_file, _)) if !reload || !from_file => return Ok(template),
+            Some((template, _, modified)) => {
+                let path = self.path_for(name).ok_or_else(|| TemplateError::new(name, 0, "Not a valid template name"))?;
+                if modified.is_some() && modified == modified_time(&path) {
+                    return Ok(template);
+                }
+                path
+            }
+            None => self.path_for(name).ok_or_else(|| TemplateError::new(name, 0, "Not a valid template name"))?,
+        };
+
+        // checked before reading, so a change made while reading is picked up next time.
+        let modified = modified_time(&path);
+        let source = fs::read_to_string(&path)
+            .map_err(|e| TemplateError::new(name, 0, format!("Can't read {}: {}", path.display(), e)))?;
+        let template = Arc::new(Template::compile(name, &source)?);
+
+        let cached = Cached {
+            template: template.clone(),
+            from_file: true,
+            modified,
+        };
+        self.cache().insert(name.to_string(), cached);
+        Ok(template)
+    }
+
+    /// The compiled templates. Entries are only ever swapped whole, so one left by
+    /// a thread that panicked is still sound and a poisoned lock is used as is.
+    fn cache(&self) -> MutexGuard<'_, HashMap<String, Cached>> {
+        self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// A template's source as it is now, for showing where an error is.
+    fn source(&self, name: &str) -> Option<String> {
+        if let Some(cached) = self.cache().get(name) {
+            if !cached.from_file {
+                return Some(cached.template.source.clone());
+            }
+        }
+
+        self.path_for(name).and_then(|path| fs::read_to_string(path).ok())
+    }
+
+    /// An HTML page describing an error from `render`, with the lines of the template around it.
+    pub(crate) fn error_page(&self, error: &Error) -> String {
+        let (heading, excerpt) = match TemplateError::from_error(error) {
+            Some(e) => {
+                let excerpt = match self.source(&e.template) {
+                    Some(ref source) if e.line > 0 => excerpt(source, e.line),
+                    _ => String::new(),
+                };
+                (format!("{}", e), excerpt)
+            }
+            None => (error.to_string(), String::new()),
+        };
+
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Template error</title>\n\
+             <style>body {{ font-family: sans-serif; margin: 2em; }} pre {{ background: #f6f6f6; padding: 1em; }}\n\
+             .error {{ background: #fdd; font-weight: bold; }}</style></head>\n\
+             <body>\n<h1>Template error</h1>\n<p>{}</p>\n{}<p><small>Shown in development mode only.</small></p>\n</body>\n</html>\n",
+            escape_html(&heading),
+            excerpt
+        )
+    }
+
+    /// The file for a template, `None` if the name would lead outside the directory.
+    fn path_for(&self, name: &str) -> Option<PathBuf> {
+        let relative = Path::new(name);
+        let safe = relative.components().all(|c| matches!(c, Component::Normal(_)));
+        if name.is_empty() || !safe {
+            return None;
+        }
+
+        let mut path = self.root.join(relative);
+        if path.extension().is_none() && !self.extension.is_empty() {
+            path.set_extension(&self.extension);
+        }
+        Some(path)
+    }
+
+    /// Renders a template and the layouts it extends.
+    fn render_into(&self, name: &str, scope: &mut Scope, out: &mut String, reload: bool, depth: usize) -> Result<(), TemplateError> {
+        if depth > MAX_DEPTH {
+            return Err(TemplateError::new(name, 0, "Templates include or extend each other in a loop"));
+        }
+
+        let mut chain = vec![self.get(name, reload)?];
+        while let Some(parent) = chain.last().unwrap().extends.clone() {
+            if chain.len() > MAX_DEPTH {
+                return Err(TemplateError::new(name, 0, "Templates extend each other in a loop"));
+            }
+            chain.push(self.get(&parent, reload)?);
+        }
+
+        // the block from the template furthest down the chain wins.
+        let mut blocks: HashMap<&str, (&str, &[Node])> = HashMap::new();
+        for template in &chain {
+            for (block, body) in &template.blocks {
+                blocks.entry(block.as_str()).or_insert((template.name.as_str(), body));
+            }
+        }
+
+        let layout = chain.last().unwrap();
+        let mut renderer = Renderer {
+            templates: self,
+            blocks: &blocks,
+            reload,
+            depth,
+        };
+        renderer.render(&layout.name, &layout.nodes, scope, out)
+    }
+}
+
+/// The file a template is read from, when it was last changed, `None` if that can't be told.
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// The numbered lines around `line`, with it highlighted.
+fn excerpt(source: &str, line: usize) -> String {
+    let first = line.saturating_sub(ERROR_CONTEXT_LINES).max(1);
+    let lines = source
+        .lines()
+        .enumerate()
+        .map(|(i, text)| (i + 1, text))
+        .skip(first - 1)
+        .take(line + ERROR_CONTEXT_LINES + 1 - first)
+        .map(|(number, text)| {
+            let class = if number == line { " class=\"error\"" } else { "" };
+            format!("<span{}>{:>4} | {}</span>\n", class, number, escape_html(text))
+        })
+        .collect::<String>();
+
+    format!("<pre>{}</pre>\n", lines)
+}
+
+/// A compiled template.
+struct Template {
+    name: String,
+    source: String,
+    extends: Option<String>,
+    nodes: Vec<Node>,
+    blocks: HashMap<String, Vec<Node>>,
+}
+
+impl Template {
+    fn compile(name: &str, source: &str) -> Result<Template, TemplateError> {
+        let mut parser = Parser {
+            name,
+            tokens: tokenize(name, source)?,
+            pos: 0,
+            extends: None,
+            blocks: HashMap::new(),
+        };
+
+        let (nodes, end) = parser.parse_nodes(&[])?;
+        if let Some((tag, line)) = end {
+            return Err(TemplateError::new(name, line, format!("Unexpected `{{% {} %}}`", tag)));
+        }
+
+        Ok(Template {
+            name: name.to_string(),
+            source: source.to_string(),
+            extends: parser.extends,
+            nodes,
+            blocks: parser.blocks,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Print {
+        expr: Expr,
+        filters: Vec<Filter>,
+        line: usize,
+    },
+    If {
+        branches: Vec<(Expr, Vec<Node>)>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        key: Option<String>,
+        value: String,
+        iterable: Expr,
+        body: Vec<Node>,
+        empty: Vec<Node>,
+        line: usize,
+    },
+    Include {
+        name: String,
+    },
+    Block {
+        name: String,
+        body: Vec<Node>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Filter {
+    name: String,
+    arg: Option<Expr>,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Literal(Value),
+    Path(Vec<String>),
+    Not(Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    And,
+    Or,
+}
+
+enum Token {
+    Text(String),
+    Print(String, usize),
+    Tag(String, usize),
+}
+
+/// Splits the source into text, `{{ }}` and `{% %}`, dropping `{# #}` comments.
+fn tokenize(name: &str, source: &str) -> Result<Vec<Token>, TemplateError> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    let mut line = 1;
+
+    loop {
+        let start = ["{{", "{%", "{#"]
+            .iter()
+            .filter_map(|open| rest.find(open))
+            .min();
+        let start = match start {
+            Some(i) => i,
+            None => {
+                if !rest.is_empty() {
+                    tokens.push(Token::Text(rest.to_string()));
+                }
+                return Ok(tokens);
+            }
+        };
+
+        if start > 0 {
+            tokens.push(Token::Text(rest[..start].to_string()));
+            line += rest[..start].matches('\n').count();
+        }
+
+        let open = &rest[start..start + 2];
+        let close = match open {
+            "{{" => "}}",
+            "{%" => "%}",
+            _ => "#}",
+        };
+        let inside = &rest[start + 2..];
+        let end = inside
+            .find(close)
+            .ok_or_else(|| TemplateError::new(name, line, format!("`{}` is never closed with `{}`", open, close)))?;
+
+        let content = &inside[..end];
+        match open {
+            "{{" => tokens.push(Token::Print(content.trim().to_string(), line)),
+            "{%" => tokens.push(Token::Tag(content.trim().to_string(), line)),
+            _ => {}
+        }
+
+        line += content.matches('\n').count();
+        rest = &inside[end + 2..];
+    }
+}
+
+/// A tag that ended a run of nodes, with its line.
+type ClosingTag = (String, usize);
+
+struct Parser<'a> {
+    name: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    extends: Option<String>,
+    blocks: HashMap<String, Vec<Node>>,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T: ToString>(&self, line: usize, message: T) -> TemplateError {
+        TemplateError::new(self.name, line, message)
+    }
+
+    /// Parses nodes up to one of the closing tags, returning which one it was and its line.
+    /// Running out of tokens returns no tag, which is only fine at the top level.
+    fn parse_nodes(&mut self, ends: &[&str]) -> Result<(Vec<Node>, Option<ClosingTag>), TemplateError> {
+        let mut nodes = Vec::new();
+
+        while self.pos < self.tokens.len() {
+            let token = match self.tokens[self.pos] {
+                Token::Text(ref text) => Token::Text(text.clone()),
+                Token::Print(ref content, line) => Token::Print(content.clone(), line),
+                Token::Tag(ref content, line) => Token::Tag(content.clone(), line),
+            };
+            self.pos += 1;
+
+            match token {
+                Token::Text(text) => nodes.push(Node::Text(text)),
+                Token::Print(content, line) => nodes.push(self.parse_print(&content, line)?),
+                Token::Tag(content, line) => {
+                    let (keyword, args) = match content.find(char::is_whitespace) {
+                        Some(i) => (&content[..i], content[i..].trim()),
+                        None => (content.as_str(), ""),
+                    };
+
+                    if ends.contains(&keyword) {
+                        return Ok((nodes, Some((content.clone(), line))));
+                    }
+                    nodes.push(self.parse_tag(keyword, args, line)?);
+                }
+            }
+        }
+
+        Ok((nodes, None))
+    }
+
+    /// Parses up to a closing tag, erroring if the template ends first.
+    fn parse_until(&mut self, ends: &[&str], opened: &str, line: usize) -> Result<(Vec<Node>, String, usize), TemplateError> {
+        match self.parse_nodes(ends)? {
+            (nodes, Some((tag, end_line))) => Ok((nodes, tag, end_line)),
+            (_, None) => Err(self.error(line, format!("`{{% {} %}}` is never closed with `{{% {} %}}`", opened, ends.last().unwrap()))),
+        }
+    }
+
+    fn parse_print(&mut self, content: &str, line: usize) -> Result<Node, TemplateError> {
+        let mut expr = ExprParser::new(self.name, content, line)?;
+        let value = expr.parse_expr()?;
+
+        let mut filters = Vec::new();
+        while expr.eat(&ExprToken::Pipe) {
+            let name = match expr.next() {
+                Some(ExprToken::Ident(name)) => name,
+                _ => return Err(self.error(line, "Expected a filter name after `|`")),
+            };
+            if !FILTERS.contains(&name.as_str()) {
+                return Err(self.error(line, format!("Unknown filter `{}`", name)));
+            }
+
+            let arg = if expr.eat(&ExprToken::LParen) {
+                let arg = expr.parse_expr()?;
+                expr.expect(&ExprToken::RParen)?;
+                Some(arg)
+            } else {
+                None
+            };
+            if name == "default" && arg.is_none() {
+                return Err(self.error(line, "`default` needs a value, eg `default(\"none\")`"));
+            }
+            filters.push(Filter { name, arg });
+        }
+        expr.finish()?;
+
+        Ok(Node::Print {
+            expr: value,
+            filters,
+            line,
+        })
+    }
+
+    fn parse_tag(&mut self, keyword: &str, args: &str, line: usize) -> Result<Node, TemplateError> {
+        match keyword {
+            "if" => {
+                let mut branches = Vec::new();
+                let mut condition = ExprParser::parse_all(self.name, args, line)?;
+                loop {
+                    let (body, tag, end_line) = self.parse_until(&["elif", "else", "endif"], "if", line)?;
+                    branches.push((condition, body));
+
+                    if let Some(elif) = tag.strip_prefix("elif") {
+                        condition = ExprParser::parse_all(self.name, elif.trim(), end_line)?;
+                    } else if tag == "else" {
+                        let (otherwise, _, _) = self.parse_until(&["endif"], "if", line)?;
+                        return Ok(Node::If { branches, otherwise });
+                    } else {
+                        return Ok(Node::If {
+                            branches,
+                            otherwise: Vec::new(),
+                        });
+                    }
+                }
+            }
+            "for" => {
+                let (vars, iterable) = match args.find(" in ") {
+                    Some(i) => (&args[..i], &args[i + 4..]),
+                    None => return Err(self.error(line, "Expected `{% for item in items %}`")),
+                };
+
+                let vars = vars.split(',').map(|v| v.trim()).collect::<Vec<&str>>();
+                if vars.len() > 2 || !vars.iter().all(|v| is_identifier(v)) {
+                    return Err(self.error(line, "Expected one or two names to loop with, eg `item` or `key, value`"));
+                }
+                let (key, value) = match vars.len() {
+                    2 => (Some(vars[0].to_string()), vars[1].to_string()),
+                    _ => (None, vars[0].to_string()),
+                };
+                let iterable = ExprParser::parse_all(self.name, iterable, line)?;
+
+                let (body, tag, _) = self.parse_until(&["else", "endfor"], "for", line)?;
+                let empty = if tag == "else" {
+                    self.parse_until(&["endfor"], "for", line)?.0
+                } else {
+                    Vec::new()
+                };
+
+                Ok(Node::For {
+                    key,
+                    value,
+                    iterable,
+                    body,
+                    empty,
+                    line,
+                })
+            }
+            "include" => Ok(Node::Include {
+                name: self.parse_name(args, line)?,
+            }),
+            "extends" => {
+                if self.extends.is_some() {
+                    return Err(self.error(line, "A template can only extend one layout"));
+                }
+                self.extends = Some(self.parse_name(args, line)?);
+                Ok(Node::Text(String::new()))
+            }
+            "block" => {
+                if !is_identifier(args) {
+                    return Err(self.error(line, "Expected a block name, eg `{% block content %}`"));
+                }
+                if self.blocks.contains_key(args) {
+                    return Err(self.error(line, format!("Block `{}` is defined twice", args)));
+                }
+
+                let (body, _, _) = self.parse_until(&["endblock"], "block", line)?;
+                self.blocks.insert(args.to_string(), body.clone());
+                Ok(Node::Block {
+                    name: args.to_string(),
+                    body,
+                })
+            }
+            _ => Err(self.error(line, format!("Unknown tag `{{% {} %}}`", keyword))),
+        }
+    }
+
+    /// A quoted template name, as in `{% include "partials/nav" %}`.
+    fn parse_name(&self, args: &str, line: usize) -> Result<String, TemplateError> {
+        match ExprParser::parse_all(self.name, args, line)? {
+            Expr::Literal(Value::String(name)) => Ok(name),
+            _ => Err(self.error(line, "Expected a quoted template name")),
+        }
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ExprToken {
+    /// A name or dotted path, including the `and`, `or`, `not` keywords.
+    Ident(String),
+    Str(String),
+    Number(f64),
+    Op(BinaryOp),
+    LParen,
+    RParen,
+    Pipe,
+}
+
+struct ExprParser<'a> {
+    name: &'a str,
+    line: usize,
+    tokens: Vec<ExprToken>,
+    pos: usize,
+}
+
+impl<'a> ExprParser<'a> {
+    fn new(name: &'a str, source: &str, line: usize) -> Result<ExprParser<'a>, TemplateError> {
+        Ok(ExprParser {
+            name,
+            line,
+            tokens: lex_expr(name, source, line)?,
+            pos: 0,
+        })
+    }
+
+    /// Parses a whole expression, erroring on anything left over.
+    fn parse_all(name: &'a str, source: &str, line: usize) -> Result<Expr, TemplateError> {
+        let mut parser = ExprParser::new(name, source, line)?;
+        let expr = parser.parse_expr()?;
+        parser.finish()?;
+        Ok(expr)
+    }
+
+    fn error<T: ToString>(&self, message: T) -> TemplateError {
+        TemplateError::new(self.name, self.line, message)
+    }
+
+    fn peek(&self) -> Option<&ExprToken> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<ExprToken> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &ExprToken) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        self.eat(&ExprToken::Ident(keyword.to_string()))
+    }
+
+    fn expect(&mut self, token: &ExprToken) -> Result<(), TemplateError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(format!("Expected {}", describe(token))))
+        }
+    }
+
+    fn finish(&self) -> Result<(), TemplateError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(token) => Err(self.error(format!("Unexpected {}", describe(token)))),
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, TemplateError> {
+        let mut left = self.parse_and()?;
+        while self.eat_keyword("or") {
+            let right = self.parse_and()?;
+            left = Expr::Binary(Box::new(left), BinaryOp::Or, Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, TemplateError> {
+        let mut left = self.parse_not()?;
+        while self.eat_keyword("and") {
+            let right = self.parse_not()?;
+            left = Expr::Binary(Box::new(left), BinaryOp::And, Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, TemplateError> {
+        if self.eat_keyword("not") {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, TemplateError> {
+        let left = self.parse_primary()?;
+        if let Some(&ExprToken::Op(op)) = self.peek() {
+            self.pos += 1;
+            let right = self.parse_primary()?;
+            return Ok(Expr::Binary(Box::new(left), op, Box::new(right)));
+        }
+        Ok(left)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, TemplateError> {
+        match self.next() {
+            Some(ExprToken::LParen) => {
+                let expr = self.parse_expr()?;
+                self.expect(&ExprToken::RParen)?;
+                Ok(expr)
+            }
+            Some(ExprToken::Str(v)) => Ok(Expr::Literal(Value::String(v))),
+            Some(ExprToken::Number(v)) => Ok(Expr::Literal(Value::Number(v))),
+            Some(ExprToken::Ident(ref v)) if v == "true" => Ok(Expr::Literal(Value::Bool(true))),
+            Some(ExprToken::Ident(ref v)) if v == "false" => Ok(Expr::Literal(Value::Bool(false))),
+            Some(ExprToken::Ident(ref v)) if v == "null" => Ok(Expr::Literal(Value::Null)),
+            Some(ExprToken::Ident(ref v)) if ["and", "or", "not"].contains(&v.as_str()) => {
+                Err(self.error(format!("Expected a value before `{}`", v)))
+            }
+            Some(ExprToken::Ident(v)) => {
+                let path = v.split('.').map(|p| p.to_string()).collect::<Vec<String>>();
+                if path.iter().any(|p| p.is_empty()) {
+                    return Err(self.error(format!("`{}` isn't a valid name", v)));
+                }
+                Ok(Expr::Path(path))
+            }
+            Some(token) => Err(self.error(format!("Unexpected {}", describe(&token)))),
+            None => Err(self.error("Expected a value")),
+        }
+    }
+}
+
+fn describe(token: &ExprToken) -> String {
+    match *token {
+        ExprToken::Ident(ref v) => format!("`{}`", v),
+        ExprToken::Str(ref v) => format!("\"{}\"", v),
+        ExprToken::Number(v) => format!("`{}`", Value::Number(v)),
+        ExprToken::Op(_) => "a comparison".to_string(),
+        ExprToken::LParen => "`(`".to_string(),
+        ExprToken::RParen => "`)`".to_string(),
+        ExprToken::Pipe => "`|`".to_string(),
+    }
+}
+
+fn lex_expr(name: &str, source: &str, line: usize) -> Result<Vec<ExprToken>, TemplateError> {
+    let chars = source.chars().collect::<Vec<char>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '"' || c == '\'' {
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(TemplateError::new(name, line, "String is never closed")),
+                    Some(&q) if q == c => break,
+                    Some(&'\\') => {
+                        match chars.get(i + 1) {
+                            Some(&'n') => value.push('\n'),
+                            Some(&'t') => value.push('\t'),
+                            Some(&e) => value.push(e),
+                            None => return Err(TemplateError::new(name, line, "String is never closed")),
+                        }
+                        i += 1;
+                    }
+                    Some(&v) => value.push(v),
+                }
+                i += 1;
+            }
+            tokens.push(ExprToken::Str(value));
+            i += 1;
+        } else if c.is_ascii_digit() || (c == '-' && next.map(|n| n.is_ascii_digit()).unwrap_or(false)) {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let number = chars[start..i].iter().collect::<String>();
+            let number = number
+                .parse::<f64>()
+                .map_err(|_| TemplateError::new(name, line, format!("`{}` isn't a number", number)))?;
+            tokens.push(ExprToken::Number(number));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                i += 1;
+            }
+            tokens.push(ExprToken::Ident(chars[start..i].iter().collect()));
+        } else {
+            let (token, length) = match (c, next) {
+                ('=', Some('=')) => (ExprToken::Op(BinaryOp::Eq), 2),
+                ('!', Some('=')) => (ExprToken::Op(BinaryOp::Ne), 2),
+                ('<', Some('=')) => (ExprToken::Op(BinaryOp::Le), 2),
+                ('>', Some('=')) => (ExprToken::Op(BinaryOp::Ge), 2),
+                ('<', _) => (ExprToken::Op(BinaryOp::Lt), 1),
+                ('>', _) => (ExprToken::Op(BinaryOp::Gt), 1),
+                ('(', _) => (ExprToken::LParen, 1),
+                (')', _) => (ExprToken::RParen, 1),
+                ('|', _) => (ExprToken::Pipe, 1),
+                _ => return Err(TemplateError::new(name, line, format!("Unexpected `{}`", c))),
+            };
+            tokens.push(token);
+            i += length;
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// The context with the variables set by enclosing loops, innermost last.
+struct Scope<'v> {
+    root: &'v Value,
+    locals: Vec<(String, Value)>,
+}
+
+impl<'v> Scope<'v> {
+    fn lookup(&self, path: &[String]) -> Value {
+        let (first, rest) = match path.split_first() {
+            Some(v) => v,
+            None => return Value::Null,
+        };
+
+        let start = self.locals
+            .iter()
+            .rev()
+            .find(|(name, _)| name == first)
+            .map(|(_, value)| value)
+            .or_else(|| self.root.get(first));
+
+        rest.iter()
+            .try_fold(start, |value, key| value.map(|v| v.get(key)))
+            .and_then(|v| v)
+            .cloned()
+            .unwrap_or(Value::Null)
+    }
+}
+
+struct Renderer<'t> {
+    templates: &'t Templates,
+    /// The blocks of the template being rendered and its layouts, by name, with the template they're from.
+    blocks: &'t HashMap<&'t str, (&'t str, &'t [Node])>,
+    reload: bool,
+    depth: usize,
+}
+
+impl<'t> Renderer<'t> {
+    fn render(&mut self, template: &str, nodes: &[Node], scope: &mut Scope, out: &mut String) -> Result<(), TemplateError> {
+        for node in nodes {
+            match *node {
+                Node::Text(ref text) => out.push_str(text),
+                Node::Print {
+                    ref expr,
+                    ref filters,
+                    line,
+                } => {
+                    let mut value = evaluate(expr, scope);
+                    let mut raw = false;
+
+                    for filter in filters {
+                        value = match filter.name.as_str() {
+                            "raw" => {
+                                raw = true;
+                                value
+                            }
+                            "upper" => Value::String(value.to_string().to_uppercase()),
+                            "lower" => Value::String(value.to_string().to_lowercase()),
+                            "trim" => Value::String(value.to_string().trim().to_string()),
+                            "length" => match value {
+                                Value::String(ref v) => Value::from(v.chars().count()),
+                                Value::Array(ref v) => Value::from(v.len()),
+                                Value::Object(ref v) => Value::from(v.len()),
+                                Value::Null => Value::from(0),
+                                _ => return Err(TemplateError::new(template, line, "Only strings, arrays and objects have a length")),
+                            },
+                            _ => {
+                                if value.is_null() || value == Value::String(String::new()) {
+                                    filter.arg.as_ref().map(|a| evaluate(a, scope)).unwrap_or(Value::Null)
+                                } else {
+                                    value
+                                }
+                            }
+                        };
+                    }
+
+                    if raw {
+                        out.push_str(&value.to_string());
+                    } else {
+                        out.push_str(&escape_html(&value.to_string()));
+                    }
+                }
+                Node::If {
+                    ref branches,
+                    ref otherwise,
+                } => {
+                    let body = branches
+                        .iter()
+                        .find(|(condition, _)| evaluate(condition, scope).is_truthy())
+                        .map(|(_, body)| body)
+                        .unwrap_or(otherwise);
+                    self.render(template, body, scope, out)?;
+                }
+                Node::For {
+                    ref key,
+                    ref value,
+                    ref iterable,
+                    ref body,
+                    ref empty,
+                    line,
+                } => {
+                    let items: Vec<(Value, Value)> = match evaluate(iterable, scope) {
+                        Value::Array(items) => items
+                            .into_iter()
+                            .enumerate()
+                            .map(|(i, item)| (Value::from(i), item))
+                            .collect(),
+                        Value::Object(fields) => fields
+                            .into_iter()
+                            .map(|(k, v)| (Value::String(k.clone()), if key.is_some() { v } else { Value::String(k) }))
+                            .collect(),
+                        Value::Null => Vec::new(),
+                        _ => return Err(TemplateError::new(template, line, "Only arrays and objects can be looped over")),
+                    };
+
+                    if items.is_empty() {
+                        self.render(template, empty, scope, out)?;
+                        continue;
+                    }
+
+                    let length = items.len();
+                    for (i, (item_key, item)) in items.into_iter().enumerate() {
+                        let state = Value::object()
+                            .set("index", i + 1)
+                            .set("index0", i)
+                            .set("first", i == 0)
+                            .set("last", i + 1 == length)
+                            .set("length", length);
+
+                        let pushed = if key.is_some() { 3 } else { 2 };
+                        scope.locals.push(("loop".to_string(), state));
+                        if let Some(ref key) = *key {
+                            scope.locals.push((key.clone(), item_key));
+                        }
+                        scope.locals.push((value.clone(), item));
+
+                        let result = self.render(template, body, scope, out);
+                        let remaining = scope.locals.len() - pushed;
+                        scope.locals.truncate(remaining);
+                        result?;
+                    }
+                }
+                Node::Include { ref name } => {
+                    self.templates.render_into(name, scope, out, self.reload, self.depth + 1)?;
+                }
+                Node::Block { ref name, ref body } => {
+                    let (from, block) = match self.blocks.get(name.as_str()) {
+                        Some(&(from, block)) => (from, block),
+                        None => (template, &body[..]),
+                    };
+                    self.render(from, block, scope, out)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn evaluate(expr: &Expr, scope: &Scope) -> Value {
+    match *expr {
+        Expr::Literal(ref v) => v.clone(),
+        Expr::Path(ref path) => scope.lookup(path),
+        Expr::Not(ref e) => Value::Bool(!evaluate(e, scope).is_truthy()),
+        Expr::Binary(ref left, BinaryOp::And, ref right) => {
+            Value::Bool(evaluate(left, scope).is_truthy() && evaluate(right, scope).is_truthy())
+        }
+        Expr::Binary(ref left, BinaryOp::Or, ref right) => {
+            Value::Bool(evaluate(left, scope).is_truthy() || evaluate(right, scope).is_truthy())
+        }
+        Expr::Binary(ref left, op, ref right) => {
+            let left = evaluate(left, scope);
+            let right = evaluate(right, scope);
+
+            let ordering = match (&left, &right) {
+                (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+                (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+                _ => None,
+            };
+
+            Value::Bool(match op {
+                BinaryOp::Eq => left == right,
+                BinaryOp::Ne => left != right,
+                BinaryOp::Lt => ordering.map(|o| o.is_lt()).unwrap_or(false),
+                BinaryOp::Gt => ordering.map(|o| o.is_gt()).unwrap_or(false),
+                BinaryOp::Le => ordering.map(|o| o.is_le()).unwrap_or(false),
+                BinaryOp::Ge => ordering.map(|o| o.is_ge()).unwrap_or(false),
+                BinaryOp::And | BinaryOp::Or => unreachable!(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str, context: &Value) -> Result<String, Error> {
+        let templates = Templates::new("missing");
+        templates.add("page", source)?;
+        templates.render("page", context)
+    }
+
+    fn template_error(result: Result<String, Error>) -> Option<TemplateError> {
+        result.err().and_then(|e| TemplateError::from_error(&e).cloned())
+    }
+
+    #[test]
+    fn renders_values_and_filters() {
+        let dog = Value::object()
+            .set("name", "<b>Rex</b>")
+            .set("bio", "<i>good</i>")
+            .set("age", 3)
+            .set("tricks", vec!["sit", "roll"]);
+        let context = Value::object().set("dog", dog).set("title", "  Dogs  ");
+
+        let cases: &[(&str, &str)] = &[
+            ("{{ dog.name }}", "&lt;b&gt;Rex&lt;/b&gt;"),
+            ("{{ dog.bio | raw }}", "<i>good</i>"),
+            ("{{ dog.name | upper }}", "&lt;B&gt;REX&lt;/B&gt;"),
+            ("{{ title | trim | lower }}", "dogs"),
+            ("{{ dog.tricks | length }}", "2"),
+            ("{{ dog.owner | default(\"none\") }}", "none"),
+            ("[{{ dog.owner }}]", "[]"),
+            ("a{# left out #}b", "ab"),
+            ("{% if dog.age > 2 and not dog.asleep %}old{% else %}young{% endif %}", "old"),
+            ("{% if dog.age == 1 %}one{% elif dog.age == 3 %}three{% endif %}", "three"),
+            ("{% for t in dog.tricks %}{{ loop.index }}.{{ t }}{% if not loop.last %},{% endif %}{% endfor %}", "1.sit,2.roll"),
+            ("{% for t in dog.missing %}{{ t }}{% else %}no tricks{% endfor %}", "no tricks"),
+        ];
+
+        for &(source, expected) in cases {
+            assert_eq!(render(source, &context).unwrap(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn renders_layouts_and_includes() {
+        let templates = Templates::new("missing");
+        templates
+            .add("layout", "<title>{% block title %}Dogs{% endblock %}</title>{% include \"nav\" %}{% block content %}{% endblock %}")
+            .unwrap();
+        templates.add("nav", "<nav>{{ user }}</nav>").unwrap();
+        templates
+            .add("show", "{% extends \"layout\" %}{% block content %}<p>{{ name }}</p>{% endblock %}")
+            .unwrap();
+        templates
+            .add("named", "{% extends \"show\" %}{% block title %}{{ name }}{% endblock %}")
+            .unwrap();
+
+        let context = Value::object().set("user", "ann").set("name", "Rex & co");
+        assert_eq!(
+            templates.render("show", &context).unwrap(),
+            "<title>Dogs</title><nav>ann</nav><p>Rex &amp; co</p>"
+        );
+        assert_eq!(
+            templates.render("named", &context).unwrap(),
+            "<title>Rex &amp; co</title><nav>ann</nav><p>Rex &amp; co</p>"
+        );
+    }
+
+    #[test]
+    fn reports_where_templates_fail() {
+        let context = Value::object();
+
+        let error = template_error(render("line one\n{% if x %}never closed", &context)).unwrap();
+        assert_eq!((error.template.as_str(), error.line), ("page", 2));
+
+        let error = template_error(render("{% endif %}", &context)).unwrap();
+        assert_eq!(error.line, 1);
+
+        let error = template_error(render("{{ name | shout }}", &context)).unwrap();
+        assert_eq!(error.line, 1);
+
+        let error = template_error(render("{% include \"nowhere\" %}", &context)).unwrap();
+        assert_eq!((error.template.as_str(), error.line), ("nowhere", 0));
+
+        let templates = Templates::new("missing");
+        templates.add("a", "{% include \"b\" %}").unwrap();
+        templates.add("b", "{% include \"a\" %}").unwrap();
+        assert!(template_error(templates.render("a", &context)).is_some());
+    }
+
+    #[test]
+    fn refuses_names_outside_the_directory() {
+        let templates = Templates::new("views");
+        for name in &["", "../secret", "/etc/passwd", "dogs/../../secret"] {
+            let error = template_error(templates.render(name, &Value::object())).unwrap();
+            assert_eq!(error.message, "Not a valid template name", "{}", name);
+        }
+    }
+
+    /// A fresh directory with one template file in it.
+    fn template_dir(test: &str, source: &str) -> PathBuf {
+        let dir = ::std::env::temp_dir().join(format!("rusty_server-{}-{}", test, ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("page.html"), source).unwrap();
+        dir
+    }
+
+    /// Rewrites the template, moving its modified time on so the change is seen
+    /// however coarse the filesystem's clock is.
+    fn rewrite(dir: &Path, source: &str) {
+        let path = dir.join("page.html");
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        fs::write(&path, source).unwrap();
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_modified(modified + ::std::time::Duration::from_secs(10)).unwrap();
+    }
+
+    #[test]
+    fn reloads_changed_files_only_when_asked() {
+        let dir = template_dir("reload", "first {{ name }}");
+        let context = Value::object().set("name", "Rex");
+        let kept = Templates::new(&dir);
+        let reloaded = Templates::new(&dir).reload(true);
+        assert_eq!(kept.render("page", &context).unwrap(), "first Rex");
+        assert_eq!(reloaded.render("page", &context).unwrap(), "first Rex");
+
+        rewrite(&dir, "second {{ name }}");
+        assert_eq!(kept.render("page", &context).unwrap(), "first Rex");
+        assert_eq!(reloaded.render("page", &context).unwrap(), "second Rex");
+        // as `res.render` does in development mode.
+        assert_eq!(kept.render_with("page", &context, true).unwrap(), "second Rex");
+
+        rewrite(&dir, "{% if %}");
+        assert!(template_error(reloaded.render("page", &context)).is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn shows_the_failing_line_on_the_error_page() {
+        let dir = template_dir("error-page", "<h1>Dogs</h1>\n{{ name | shout }}\n<p>end</p>");
+        let templates = Templates::new(&dir);
+        let error = templates.render("page", &Value::object()).err().unwrap();
+
+        let page = templates.error_page(&error);
+        assert!(page.contains("page, line 2: "), "{}", page);
+        assert!(page.contains("<span>   1 | &lt;h1&gt;Dogs&lt;/h1&gt;</span>"), "{}", page);
+        assert!(page.contains("<span class=\"error\">   2 | {{ name | shout }}</span>"), "{}", page);
+        assert!(page.contains("<span>   3 | &lt;p&gt;end&lt;/p&gt;</span>"), "{}", page);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_working_after_a_panic_holding_the_cache() {
+        let templates = Arc::new(Templates::new("missing"));
+        templates.add("page", "hi {{ name }}").unwrap();
+
+        let poisoner = templates.clone();
+        let _ = ::std::thread::spawn(move || {
+            let _cache = poisoner.cache();
+            panic!("poisoning the template cache");
+        })
+        .join();
+        assert!(templates.cache.is_poisoned());
+
+        let context = Value::object().set("name", "Rex");
+        assert_eq!(templates.render("page", &context).unwrap(), "hi Rex");
+        templates.add("other", "ok").unwrap();
+        assert_eq!(templates.render("other", &context).unwrap(), "ok");
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::io::{Cursor, Error, ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use server::Server;
+use server::bind::ListenerOptions;
+use server::router::Router;
+
+/// Sends requests straight through a server's routing without opening any sockets.
+///
+/// `TestClient::from_router(router).get("/dogs/1").header("Accept", "text/html").send()`
+pub struct TestClient {
+    server: Server,
+    listener: ListenerOptions,
+}
+
+impl TestClient {
+    /// A client for every router registered on `server`.
+    pub fn new(server: Server) -> TestClient {
+        TestClient {
+            server,
+            listener: ListenerOptions::new(),
+        }
+    }
+
+    /// A client for a single router, mounted at `/`.
+    pub fn from_router(router: Router) -> TestClient {
+        let mut server = Server::new();
+        server.register("/", router);
+        TestClient::new(server)
+    }
+
+    /// Sends requests as if they came in on an internal listener, so internal routers are reachable.
+    pub fn internal(mut self) -> TestClient {
+        self.listener = self.listener.internal();
+        self
+    }
+
+    /// Starts building a request with any method.
+    pub fn request<M: ToString, P: ToString>(&self, method: M, path: P) -> TestRequest<'_> {
+        TestRequest {
+            client: self,
+            method: method.to_string().to_uppercase(),
+            path: path.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// # Shorthand methods. .get instead of .request("GET")
+    pub fn get<P: ToString>(&self, path: P) -> TestRequest<'_> {
+        self.request("GET", path)
+    }
+
+    pub fn post<P: ToString>(&self, path: P) -> TestRequest<'_> {
+        self.request("POST", path)
+    }
+
+    pub fn put<P: ToString>(&self, path: P) -> TestRequest<'_> {
+        self.request("PUT", path)
+    }
+
+    pub fn patch<P: ToString>(&self, path: P) -> TestRequest<'_> {
+        self.request("PATCH", path)
+    }
+
+    pub fn delete<P: ToString>(&self, path: P) -> TestRequest<'_> {
+        self.request("DELETE", path)
+    }
+}
+
+/// A request being built by a `TestClient`.
+pub struct TestRequest<'a> {
+    client: &'a TestClient,
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl<'a> TestRequest<'a> {
+    /// Adds a header, can be called more than once for the same name.
+    pub fn header<K: ToString, V: ToString>(mut self, key: K, value: V) -> TestRequest<'a> {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Adds a `Cookie` header.
+    pub fn cookie<K: ToString, V: ToString>(self, key: K, value: V) -> TestRequest<'a> {
+        let cookie = format!("{}={}", key.to_string(), value.to_string());
+        self.header("Cookie", cookie)
+    }
+
+    /// Sets the request body, a `Content-Length` is added for it when sent.
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> TestRequest<'a> {
+        self.body = body.into();
+        self
+    }
+
+    /// Runs the request through the server, returning whatever it responded with,
+    /// eg `404 Not Found` when no route matches. Fails if the request never got a response.
+    pub fn send(self) -> Result<TestResponse, Error> {
+        let mut raw = format!("{} {} HTTP/1.1\r\n", self.method, self.path);
+        for (key, value) in &self.headers {
+            raw.push_str(&format!("{}: {}\r\n", key, value));
+        }
+        if !self.body.is_empty() {
+            raw.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        raw.push_str("\r\n");
+
+        let mut input = raw.into_bytes();
+        input.extend_from_slice(&self.body);
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let stream = MemoryStream {
+            input: Cursor::new(input),
+            output: output.clone(),
+        };
+
+        let handled = self.client
+            .server
+            .parse_incoming(stream, &self.client.listener);
+
+        let output = output.lock().map_err(|_| Error::other("Lock Error"))?;
+        // a refused request, eg with a body too large, is still answered by the server.
+        if let Err(e) = handled {
+            if output.is_empty() {
+                return Err(e);
+            }
+        }
+        TestResponse::parse(&output)
+    }
+}
+
+/// The response a handler wrote, split back up for inspecting.
+#[derive(Debug, Clone)]
+pub struct TestResponse {
+    pub status: u16,
+    /// The reason phrase from the status line, eg `Not Found`.
+    pub reason: String,
+    /// The headers in the order they were sent.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    fn parse(raw: &[u8]) -> Result<TestResponse, Error> {
+        let malformed = || Error::new(ErrorKind::InvalidData, "Malformed Response");
+
+        let split = match raw.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(v) => v,
+            None => return Err(malformed()),
+        };
+        let head = String::from_utf8_lossy(&raw[..split]);
+        let body = raw[split + 4..].to_vec();
+
+        let mut lines = head.split("\r\n");
+        let mut status_line = match lines.next() {
+            Some(v) => v.splitn(3, ' '),
+            None => return Err(malformed()),
+        };
+        let (status, reason) = match (status_line.next(), status_line.next(), status_line.next()) {
+            (Some(_), Some(code), reason) => (
+                code.parse::<u16>().map_err(|_| malformed())?,
+                reason.unwrap_or("").to_string(),
+            ),
+            _ => return Err(malformed()),
+        };
+
+        let mut headers = Vec::new();
+        for line in lines {
+            let mut sep = line.splitn(2, ':');
+            match (sep.next(), sep.next()) {
+                (Some(k), Some(v)) => headers.push((k.trim().to_string(), v.trim().to_string())),
+                _ => return Err(malformed()),
+            }
+        }
+
+        let mut response = TestResponse {
+            status,
+            reason,
+            headers,
+            body,
+        };
+
+        let chunked = response
+            .header("Transfer-Encoding")
+            .map(|v| v.eq_ignore_ascii_case("chunked"))
+            .unwrap_or(false);
+        // a HEAD gets the chunked header without any chunks.
+        if chunked && !response.body.is_empty() {
+            response.body = TestResponse::dechunk(&response.body).ok_or_else(malformed)?;
+        }
+
+        Ok(response)
+    }
+
+    /// Joins the chunks of a chunked body back together.
+    fn dechunk(mut raw: &[u8]) -> Option<Vec<u8>> {
+        let mut body = Vec::new();
+
+        loop {
+            let line_end = raw.windows(2).position(|w| w == b"\r\n")?;
+            let size = String::from_utf8_lossy(&raw[..line_end]);
+            let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+            raw = &raw[line_end + 2..];
+
+            if size == 0 {
+                return Some(body);
+            }
+
+            if raw.len() < size + 2 {
+                return None;
+            }
+            body.extend_from_slice(&raw[..size]);
+            raw = &raw[size + 2..];
+        }
+    }
+
+    /// The first header with this name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The body as a string, lossily decoded.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+
+    /// The cookies set by `Set-Cookie` headers, as `name => value` without their attributes.
+    pub fn cookies(&self) -> HashMap<String, String> {
+        self.headers
+            .iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case("Set-Cookie"))
+            .filter_map(|(_, v)| {
+                let pair = v.split(';').next()?;
+                let mut pair = pair.splitn(2, '=');
+                match (pair.next(), pair.next()) {
+                    (Some(k), Some(v)) => Some((k.trim().to_string(), v.trim().to_string())),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Reads the request from a buffer and collects the response into a shared one.
+struct MemoryStream {
+    input: Cursor<Vec<u8>>,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let mut output = self.output.lock().map_err(|_| Error::other("Lock Error"))?;
+        output.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
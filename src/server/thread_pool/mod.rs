@@ -1,9 +1,13 @@
+use std::any::Any;
 use std::thread;
-use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard};
 
 pub struct ThreadPool {
     workers: Vec<Worker>,
     sender: mpsc::Sender<Message>,
+    shared: Arc<Shared>,
 }
 
 enum Message {
@@ -11,17 +15,82 @@ enum Message {
     Terminate,
 }
 
-trait FnBox {
-    fn call_box(self: Box<Self>);
+/// Work for the pool, any `FnOnce()` closure or a type of its own,
+/// eg so `try_execute` can hand back what it holds when the queue is full.
+pub trait Task {
+    fn run(self: Box<Self>);
 }
 
-impl<F: FnOnce()> FnBox for F {
-    fn call_box(self: Box<F>) {
+impl<F: FnOnce()> Task for F {
+    fn run(self: Box<F>) {
         (*self)();
     }
 }
 
-type Job = Box<FnBox + Send + 'static>;
+type Job = Box<dyn Task + Send + 'static>;
+
+/// Describes a job that panicked while running on one of the pool's workers.
+#[derive(Debug, Clone)]
+pub struct JobPanic {
+    /// The id of the worker the job was running on.
+    pub worker: usize,
+    /// The panic message, if the payload was a string.
+    pub message: String,
+}
+
+/// Called with the details of every job that panics.
+pub type PanicHandler = dyn Fn(&JobPanic) + Send + Sync + 'static;
+
+/// The state shared between the pool and all of its workers.
+struct Shared {
+    receiver: Mutex<mpsc::Receiver<Message>>,
+    panic_handler: Arc<PanicHandler>,
+    /// The most jobs that may wait in the queue, `None` for no limit.
+    capacity: Option<usize>,
+    /// How many jobs are waiting for a worker.
+    queued: Mutex<usize>,
+    /// Signalled whenever a worker takes a job off the queue.
+    space: Condvar,
+}
+
+impl Shared {
+    /// Locks the job queue, recovering it if another worker panicked while holding it.
+    fn receiver(&self) -> MutexGuard<'_, mpsc::Receiver<Message>> {
+        self.receiver
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn queued(&self) -> MutexGuard<'_, usize> {
+        self.queued
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Marks a job as taken off the queue, waking anyone waiting for space.
+    fn dequeued(&self) {
+        let mut queued = self.queued();
+        *queued = queued.saturating_sub(1);
+        self.space.notify_one();
+    }
+
+    fn report(&self, worker: usize, payload: Box<dyn Any + Send>) {
+        let message = match payload.downcast::<String>() {
+            Ok(v) => *v,
+            Err(payload) => match payload.downcast::<&'static str>() {
+                Ok(v) => v.to_string(),
+                Err(_) => "Box<Any>".to_string(),
+            },
+        };
+
+        (self.panic_handler)(&JobPanic { worker, message });
+    }
+}
+
+/// The default panic handler, logs the panic and carries on.
+pub fn log_panic(panic: &JobPanic) {
+    eprintln!("Worker {} recovered from a panicking job: {}", panic.worker, panic.message);
+}
 
 impl ThreadPool {
     /// Creates a new instance of ThreadPool
@@ -32,32 +101,122 @@ impl ThreadPool {
     ///
     /// The `new` function will panic if the size is zero.
     pub fn new(size: usize) -> ThreadPool {
+        ThreadPool::with_panic_handler(size, Arc::new(log_panic))
+    }
+
+    /// Creates a new ThreadPool with an unbounded queue that calls `handler` whenever a job panics.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size is zero.
+    pub fn with_panic_handler(size: usize, handler: Arc<PanicHandler>) -> ThreadPool {
+        ThreadPool::build(size, None, handler)
+    }
+
+    /// Creates a new ThreadPool of `size` workers whose queue holds at most `capacity` jobs,
+    /// calling `handler` whenever a job panics.
+    ///
+    /// A panicking job never takes its worker down with it, the panic is caught,
+    /// reported to the handler and the worker moves on to the next job.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size or the capacity is zero.
+    pub fn build(size: usize, capacity: Option<usize>, handler: Arc<PanicHandler>) -> ThreadPool {
         assert!(size > 0);
+        assert!(capacity != Some(0));
 
         let (sender, receiver) = mpsc::channel();
         let mut workers = Vec::with_capacity(size);
 
-        let receiver = Arc::new(Mutex::new(receiver));
+        let shared = Arc::new(Shared {
+            receiver: Mutex::new(receiver),
+            panic_handler: handler,
+            capacity,
+            queued: Mutex::new(0),
+            space: Condvar::new(),
+        });
 
         for id in 0..size {
-            workers.push(Worker::new(id, receiver.clone()));
+            workers.push(Worker::new(id, shared.clone()));
         }
 
-        ThreadPool { workers, sender }
+        ThreadPool { workers, sender, shared }
     }
 
-    pub fn execute<F>(&self, f: F)
+    /// Queues a job, blocking until there is room for it if the queue is full.
+    pub fn execute<T>(&self, task: T)
     where
-        F: FnOnce() + Send + 'static,
+        T: Task + Send + 'static,
     {
-        let job = Box::new(f);
+        let mut queued = self.shared.queued();
+
+        if let Some(capacity) = self.shared.capacity {
+            while *queued >= capacity {
+                queued = self.shared
+                    .space
+                    .wait(queued)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+            }
+        }
+
+        *queued += 1;
+        self.sender.send(Message::NewJob(Box::new(task))).unwrap();
+    }
+
+    /// Queues a job if there is room for it, handing it back if the queue is full.
+    /// The check and the queueing happen under one lock, so two callers can't both take the last spot.
+    pub fn try_execute<T>(&self, task: T) -> Result<(), T>
+    where
+        T: Task + Send + 'static,
+    {
+        let mut queued = self.shared.queued();
+
+        if let Some(capacity) = self.shared.capacity {
+            if *queued >= capacity {
+                return Err(task);
+            }
+        }
 
-        self.sender.send(Message::NewJob(job)).unwrap();
+        *queued += 1;
+        self.sender.send(Message::NewJob(Box::new(task))).unwrap();
+        Ok(())
+    }
+}
+
+impl ThreadPool {
+    /// Stops the pool once the jobs already queued have run, waiting at most `grace`
+    /// for them to finish. Workers still busy after that are left to finish on their own.
+    pub fn shutdown(mut self, grace: Duration) {
+        let deadline = Instant::now() + grace;
+
+        for _ in &self.workers {
+            let _ = self.sender.send(Message::Terminate);
+        }
+
+        for worker in self.workers.drain(..) {
+            while let Some(thread) = worker.take_thread() {
+                while !thread.is_finished() && Instant::now() < deadline {
+                    thread::sleep(Duration::from_millis(10));
+                }
+
+                if !thread.is_finished() {
+                    break;
+                }
+
+                let _ = thread.join();
+            }
+        }
     }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
+        // already shut down
+        if self.workers.is_empty() {
+            return;
+        }
+
         println!("Sending a terminate to all workers");
 
         for _ in &mut self.workers {
@@ -67,38 +226,205 @@ impl Drop for ThreadPool {
         for worker in &mut self.workers {
             println!("Shutting down worker {}", worker.id);
 
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+            // a worker that died hands its slot to a replacement thread,
+            // so keep joining until the slot stays empty.
+            while let Some(thread) = worker.take_thread() {
+                let _ = thread.join();
             }
         }
     }
 }
 
+type ThreadSlot = Arc<Mutex<Option<thread::JoinHandle<()>>>>;
+
 pub struct Worker {
     id: usize,
-    thread: Option<thread::JoinHandle<()>>,
+    thread: ThreadSlot,
+}
+
+/// Lives on a worker's stack, if the worker unwinds it spawns a replacement.
+struct Sentinel {
+    id: usize,
+    shared: Arc<Shared>,
+    slot: ThreadSlot,
+}
+
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            Worker::spawn(self.id, self.shared.clone(), self.slot.clone());
+        }
+    }
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
-        let thread = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv().unwrap();
+    fn new(id: usize, shared: Arc<Shared>) -> Worker {
+        let thread = Arc::new(Mutex::new(None));
+        Worker::spawn(id, shared, thread.clone());
 
-            match message {
-                Message::NewJob(job) => {
-                    job.call_box();
-                }
-                Message::Terminate => {
-                    println!("Worker {} was told to terminate", id);
+        Worker { id, thread }
+    }
 
-                    break;
+    fn take_thread(&self) -> Option<thread::JoinHandle<()>> {
+        self.thread
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take()
+    }
+
+    fn spawn(id: usize, shared: Arc<Shared>, slot: ThreadSlot) {
+        let sentinel_slot = slot.clone();
+        let thread = thread::spawn(move || {
+            let sentinel = Sentinel { id, shared, slot: sentinel_slot };
+
+            loop {
+                let message = match sentinel.shared.receiver().recv() {
+                    Ok(v) => v,
+                    Err(_) => break, // the pool is gone
+                };
+
+                match message {
+                    Message::NewJob(job) => {
+                        sentinel.shared.dequeued();
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| job.run()));
+
+                        if let Err(payload) = result {
+                            sentinel.shared.report(id, payload);
+                        }
+                    }
+                    Message::Terminate => {
+                        println!("Worker {} was told to terminate", id);
+
+                        break;
+                    }
                 }
             }
         });
 
-        Worker {
-            id,
-            thread: Some(thread),
+        *slot.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(thread);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_execute_hands_the_job_back_when_the_queue_is_full() {
+        let pool = ThreadPool::build(1, Some(1), Arc::new(log_panic));
+        let (started, wait_started) = mpsc::channel();
+        let (release, wait_release) = mpsc::channel::<()>();
+
+        // keeps the only worker busy so the next job has to wait in the queue.
+        pool.execute(move || {
+            started.send(()).unwrap();
+            let _ = wait_release.recv();
+        });
+        wait_started.recv().unwrap();
+
+        assert!(pool.try_execute(|| ()).is_ok());
+        let rejected = pool.try_execute(|| ());
+        assert!(rejected.is_err());
+
+        release.send(()).unwrap();
+        pool.shutdown(Duration::from_secs(1));
+    }
+
+    /// A pool of `size` workers whose handler records each panic's message,
+    /// and panics itself for a message of `"escape"`.
+    fn recording_pool(size: usize) -> (ThreadPool, Arc<Mutex<Vec<String>>>) {
+        let panics = Arc::new(Mutex::new(Vec::new()));
+        let recorded = panics.clone();
+        let handler = move |panic: &JobPanic| {
+            recorded.lock().unwrap().push(panic.message.clone());
+            if panic.message == "escape" {
+                panic!("the handler failed too");
+            }
+        };
+        (ThreadPool::with_panic_handler(size, Arc::new(handler)), panics)
+    }
+
+    /// Runs a job on every worker at once, which only finishes if there are `size` of them alive.
+    fn all_workers_run(pool: &ThreadPool, size: usize) -> bool {
+        let barrier = Arc::new(::std::sync::Barrier::new(size));
+        let (done, finished) = mpsc::channel();
+        for _ in 0..size {
+            let barrier = barrier.clone();
+            let done = done.clone();
+            pool.execute(move || {
+                barrier.wait();
+                done.send(()).unwrap();
+            });
+        }
+        (0..size).all(|_| finished.recv_timeout(Duration::from_secs(5)).is_ok())
+    }
+
+    #[test]
+    fn reports_panicking_jobs_and_keeps_every_worker() {
+        let (pool, panics) = recording_pool(2);
+        pool.execute(|| panic!("job {} failed", 1));
+        pool.execute(|| panic!("static message"));
+
+        assert!(all_workers_run(&pool, 2));
+        let mut seen = panics.lock().unwrap().clone();
+        seen.sort();
+        assert_eq!(seen, vec!["job 1 failed".to_string(), "static message".to_string()]);
+
+        pool.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn respawns_a_worker_that_dies() {
+        let (pool, panics) = recording_pool(2);
+        // the handler's own panic isn't caught, so it takes the worker down.
+        pool.execute(|| panic!("escape"));
+        pool.execute(|| panic!("escape"));
+
+        assert!(all_workers_run(&pool, 2));
+        assert_eq!(*panics.lock().unwrap(), vec!["escape".to_string(), "escape".to_string()]);
+
+        pool.execute(|| panic!("after"));
+        assert!(all_workers_run(&pool, 2));
+        assert_eq!(panics.lock().unwrap().last().map(|m| m.as_str()), Some("after"));
+
+        pool.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn shutdown_runs_the_queued_jobs_within_the_grace_period() {
+        let pool = ThreadPool::new(2);
+        let (done, finished) = mpsc::channel();
+        for i in 0..6 {
+            let done = done.clone();
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(20));
+                done.send(i).unwrap();
+            });
         }
+
+        pool.shutdown(Duration::from_secs(5));
+        let mut ran = finished.try_iter().collect::<Vec<i32>>();
+        ran.sort();
+        assert_eq!(ran, vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn shutdown_stops_waiting_after_the_grace_period() {
+        let pool = ThreadPool::new(1);
+        let (release, wait_release) = mpsc::channel::<()>();
+        let (started, wait_started) = mpsc::channel();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            let _ = wait_release.recv_timeout(Duration::from_secs(10));
+        });
+        wait_started.recv().unwrap();
+
+        let start = Instant::now();
+        pool.shutdown(Duration::from_millis(100));
+        let waited = start.elapsed();
+        assert!(waited >= Duration::from_millis(100), "{:?}", waited);
+        assert!(waited < Duration::from_secs(5), "{:?}", waited);
+
+        let _ = release.send(());
     }
 }
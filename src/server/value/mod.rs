@@ -0,0 +1,464 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+/// How deeply arrays and objects can nest in parsed JSON.
+const MAX_JSON_DEPTH: usize = 128;
+
+/// A loosely typed value, like a JSON one, for passing data to templates.
+///
+/// `Value::object().set("name", "Rex").set("tags", vec!["good", "boy"])`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Value {
+    #[default]
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+impl Value {
+    /// An empty object, to `set` fields on.
+    pub fn object() -> Value {
+        Value::Object(BTreeMap::new())
+    }
+
+    /// Sets a field, turning the value into an object first if it isn't one.
+    pub fn set<K: ToString, V: Into<Value>>(mut self, key: K, value: V) -> Value {
+        self.insert(key, value);
+        self
+    }
+
+    /// `set` for a value that isn't being built up in one go.
+    pub fn insert<K: ToString, V: Into<Value>>(&mut self, key: K, value: V) {
+        if let Value::Object(ref mut fields) = *self {
+            fields.insert(key.to_string(), value.into());
+            return;
+        }
+
+        let mut fields = BTreeMap::new();
+        fields.insert(key.to_string(), value.into());
+        *self = Value::Object(fields);
+    }
+
+    /// A field of an object or, for a numeric key, an item of an array.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match *self {
+            Value::Object(ref fields) => fields.get(key),
+            Value::Array(ref items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => None,
+        }
+    }
+
+    /// Follows a dotted path like `dog.owner.name`, `None` if any part is missing.
+    pub fn pointer(&self, path: &str) -> Option<&Value> {
+        path.split('.')
+            .filter(|p| !p.is_empty())
+            .try_fold(self, |value, key| value.get(key))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::String(ref v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Number(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Value::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+
+    /// Whether the value counts as true in a condition. `null`, `false`, zero
+    /// and empty strings, arrays and objects don't.
+    pub fn is_truthy(&self) -> bool {
+        match *self {
+            Value::Null => false,
+            Value::Bool(v) => v,
+            Value::Number(v) => v != 0.0,
+            Value::String(ref v) => !v.is_empty(),
+            Value::Array(ref v) => !v.is_empty(),
+            Value::Object(ref v) => !v.is_empty(),
+        }
+    }
+
+    /// Parses JSON, an `InvalidData` error saying where if it isn't valid.
+    pub fn from_json(json: &str) -> Result<Value, Error> {
+        let mut parser = JsonParser {
+            bytes: json.as_bytes(),
+            pos: 0,
+            depth: 0,
+        };
+
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+        if parser.pos < parser.bytes.len() {
+            return Err(parser.error("Unexpected text after the value"));
+        }
+        Ok(value)
+    }
+
+    /// The value as JSON.
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        self.write_json(&mut out);
+        out
+    }
+
+    fn write_json(&self, out: &mut String) {
+        match *self {
+            Value::Null => out.push_str("null"),
+            Value::Bool(v) => out.push_str(if v { "true" } else { "false" }),
+            Value::Number(v) if !v.is_finite() => out.push_str("null"),
+            Value::Number(v) => out.push_str(&format_number(v)),
+            Value::String(ref v) => write_json_string(v, out),
+            Value::Array(ref items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    item.write_json(out);
+                }
+                out.push(']');
+            }
+            Value::Object(ref fields) => {
+                out.push('{');
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_json_string(key, out);
+                    out.push(':');
+                    value.write_json(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+}
+
+struct JsonParser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn error(&self, message: &str) -> Error {
+        Error::new(ErrorKind::InvalidData, format!("Invalid JSON at byte {}: {}", self.pos, message))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(&b' ') | Some(&b'\t') | Some(&b'\n') | Some(&b'\r') = self.bytes.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&byte) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), Error> {
+        if self.eat(byte) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected `{}`", byte as char)))
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, Error> {
+        self.skip_whitespace();
+        match self.bytes.get(self.pos) {
+            Some(&b'{') => self.parse_nested(|p| p.parse_object()),
+            Some(&b'[') => self.parse_nested(|p| p.parse_array()),
+            Some(&b'"') => self.parse_string().map(Value::String),
+            Some(&b't') => self.parse_literal("true", Value::Bool(true)),
+            Some(&b'f') => self.parse_literal("false", Value::Bool(false)),
+            Some(&b'n') => self.parse_literal("null", Value::Null),
+            Some(&b) if b == b'-' || b.is_ascii_digit() => self.parse_number(),
+            Some(_) => Err(self.error("Expected a value")),
+            None => Err(self.error("Unexpected end")),
+        }
+    }
+
+    fn parse_nested<F>(&mut self, parse: F) -> Result<Value, Error>
+    where
+        F: FnOnce(&mut JsonParser<'a>) -> Result<Value, Error>,
+    {
+        if self.depth >= MAX_JSON_DEPTH {
+            return Err(self.error("Nested too deeply"));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn parse_object(&mut self) -> Result<Value, Error> {
+        self.expect(b'{')?;
+        let mut fields = BTreeMap::new();
+        if self.eat(b'}') {
+            return Ok(Value::Object(fields));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.bytes.get(self.pos) != Some(&b'"') {
+                return Err(self.error("Expected a key"));
+            }
+            let key = self.parse_string()?;
+            self.expect(b':')?;
+            fields.insert(key, self.parse_value()?);
+
+            if self.eat(b'}') {
+                return Ok(Value::Object(fields));
+            }
+            self.expect(b',')?;
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Value, Error> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        if self.eat(b']') {
+            return Ok(Value::Array(items));
+        }
+
+        loop {
+            items.push(self.parse_value()?);
+            if self.eat(b']') {
+                return Ok(Value::Array(items));
+            }
+            self.expect(b',')?;
+        }
+    }
+
+    fn parse_literal(&mut self, literal: &str, value: Value) -> Result<Value, Error> {
+        if self.bytes[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("Expected a value"))
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Value, Error> {
+        let start = self.pos;
+        while let Some(&b) = self.bytes.get(self.pos) {
+            match b {
+                b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E' => self.pos += 1,
+                _ => break,
+            }
+        }
+
+        // `str::parse` is laxer than JSON, which has no `+1`, `.5`, `1.` or leading zeros.
+        let text = std::str::from_utf8(&self.bytes[start..self.pos]).unwrap_or("");
+        let digits = text.trim_start_matches('-');
+        let leading_zero = digits.starts_with('0') && digits[1..].starts_with(|c: char| c.is_ascii_digit());
+        let empty_fraction = digits.contains(".e") || digits.contains(".E") || digits.ends_with('.');
+        let valid = digits.starts_with(|c: char| c.is_ascii_digit()) && !leading_zero && !empty_fraction;
+
+        match text.parse::<f64>() {
+            Ok(v) if valid && v.is_finite() => Ok(Value::Number(v)),
+            _ => {
+                self.pos = start;
+                Err(self.error("Invalid number"))
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, Error> {
+        self.pos += 1;
+        let mut bytes = Vec::new();
+
+        loop {
+            match self.bytes.get(self.pos) {
+                None => return Err(self.error("String is never closed")),
+                Some(&b'"') => {
+                    self.pos += 1;
+                    return String::from_utf8(bytes).map_err(|_| self.error("String isn't valid UTF-8"));
+                }
+                Some(&b'\\') => {
+                    let escaped = match self.bytes.get(self.pos + 1) {
+                        Some(&b'"') => '"',
+                        Some(&b'\\') => '\\',
+                        Some(&b'/') => '/',
+                        Some(&b'b') => '\u{8}',
+                        Some(&b'f') => '\u{c}',
+                        Some(&b'n') => '\n',
+                        Some(&b'r') => '\r',
+                        Some(&b't') => '\t',
+                        Some(&b'u') => {
+                            self.pos += 2;
+                            let c = self.parse_unicode_escape()?;
+                            let mut buffer = [0; 4];
+                            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                            continue;
+                        }
+                        _ => return Err(self.error("Invalid escape")),
+                    };
+                    bytes.push(escaped as u8);
+                    self.pos += 2;
+                }
+                Some(&b) if b < 0x20 => return Err(self.error("Control character in string")),
+                Some(&b) => {
+                    bytes.push(b);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    /// The character after a `\u`, joining a surrogate pair written as two escapes.
+    fn parse_unicode_escape(&mut self) -> Result<char, Error> {
+        let first = self.parse_hex4()?;
+        if !(0xD800..0xDC00).contains(&first) {
+            return std::char::from_u32(first).ok_or_else(|| self.error("Invalid \\u escape"));
+        }
+
+        if !self.bytes[self.pos..].starts_with(b"\\u") {
+            return Err(self.error("Unpaired surrogate in \\u escape"));
+        }
+        self.pos += 2;
+        let second = self.parse_hex4()?;
+        if !(0xDC00..0xE000).contains(&second) {
+            return Err(self.error("Unpaired surrogate in \\u escape"));
+        }
+
+        let c = 0x10000 + ((first - 0xD800) << 10) + (second - 0xDC00);
+        std::char::from_u32(c).ok_or_else(|| self.error("Invalid \\u escape"))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, Error> {
+        let hex = self.bytes
+            .get(self.pos..self.pos + 4)
+            .filter(|h| h.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u32::from_str_radix(h, 16).ok())
+            .ok_or_else(|| self.error("Invalid \\u escape"))?;
+        self.pos += 4;
+        Ok(hex)
+    }
+}
+
+/// Whole numbers are written without a fraction, `3` rather than `3.0`.
+fn format_number(v: f64) -> String {
+    if v.fract() == 0.0 && v.abs() < 1e15 {
+        format!("{}", v as i64)
+    } else {
+        format!("{}", v)
+    }
+}
+
+fn write_json_string(v: &str, out: &mut String) {
+    out.push('"');
+    for c in v.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Strings as they are, `null` as nothing, and anything else as JSON.
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Value::Null => Ok(()),
+            Value::String(ref v) => write!(f, "{}", v),
+            Value::Number(v) => write!(f, "{}", format_number(v)),
+            ref other => write!(f, "{}", other.to_json()),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(v: bool) -> Value {
+        Value::Bool(v)
+    }
+}
+
+macro_rules! from_number {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for Value {
+                fn from(v: $t) -> Value {
+                    Value::Number(v as f64)
+                }
+            }
+        )*
+    };
+}
+
+from_number!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize, f32, f64);
+
+impl<'a> From<&'a str> for Value {
+    fn from(v: &'a str) -> Value {
+        Value::String(v.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(v: String) -> Value {
+        Value::String(v)
+    }
+}
+
+impl<'a> From<&'a String> for Value {
+    fn from(v: &'a String) -> Value {
+        Value::String(v.clone())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Value {
+        v.map(|v| v.into()).unwrap_or(Value::Null)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(v: Vec<T>) -> Value {
+        Value::Array(v.into_iter().map(|v| v.into()).collect())
+    }
+}
+
+impl<K: ToString, V: Into<Value>> From<BTreeMap<K, V>> for Value {
+    fn from(v: BTreeMap<K, V>) -> Value {
+        Value::Object(v.into_iter().map(|(k, v)| (k.to_string(), v.into())).collect())
+    }
+}
+
+impl<K: ToString, V: Into<Value>> From<HashMap<K, V>> for Value {
+    fn from(v: HashMap<K, V>) -> Value {
+        Value::Object(v.into_iter().map(|(k, v)| (k.to_string(), v.into())).collect())
+    }
+}
@@ -0,0 +1,36 @@
+extern crate rusty_server;
+
+use rusty_server::request::Request;
+use rusty_server::response::Response;
+use rusty_server::router::Router;
+use rusty_server::server::conditional::ETag;
+use rusty_server::test_client::TestClient;
+
+fn client() -> TestClient {
+    let mut router = Router::new();
+    router.get("/dog", |_: Request, mut res: Response| {
+        let _ = res.etag(ETag::strong("v2")).send("Rex");
+    });
+    TestClient::from_router(router)
+}
+
+#[test]
+fn head_with_a_matching_tag_is_not_modified() {
+    let res = client()
+        .request("HEAD", "/dog")
+        .header("If-None-Match", "\"v2\"")
+        .send()
+        .unwrap();
+
+    assert_eq!(res.status, 304);
+    assert_eq!(res.header("ETag"), Some("\"v2\""));
+    assert!(res.body.is_empty());
+}
+
+#[test]
+fn get_with_a_stale_tag_gets_the_body() {
+    let res = client().get("/dog").header("If-None-Match", "\"v1\"").send().unwrap();
+
+    assert_eq!(res.status, 200);
+    assert_eq!(res.text(), "Rex");
+}
@@ -0,0 +1,59 @@
+extern crate rusty_server;
+
+use rusty_server::request::Request;
+use rusty_server::response::Response;
+use rusty_server::router::Router;
+use rusty_server::server::controller::{Action, Controller, Resource};
+use rusty_server::test_client::TestClient;
+
+struct CurrentUser(String);
+
+/// Finds the user from a header, answering `401` without one.
+fn login(req: &mut Request, res: &mut Response) -> bool {
+    let user = match req.header("X-User") {
+        Some(v) => v.to_string(),
+        None => {
+            let _ = res.status(401).send("Unauthorized");
+            return false;
+        }
+    };
+    req.extensions.insert(CurrentUser(user));
+    true
+}
+
+struct Dogs;
+
+impl Controller for Dogs {
+    const ACTIONS: &'static [Action] = &[Action::Index, Action::Create];
+
+    fn index(&self, req: Request, mut res: Response) {
+        let user = req.extensions.get::<CurrentUser>().map(|u| u.0.as_str()).unwrap_or("nobody");
+        let _ = res.send(format!("dogs for {}", user));
+    }
+
+    fn create(&self, req: Request, mut res: Response) {
+        let user = req.extensions.get::<CurrentUser>().map(|u| u.0.as_str()).unwrap_or("nobody");
+        let _ = res.status(201).send(format!("created by {}", user));
+    }
+}
+
+fn client() -> TestClient {
+    let mut router = Router::new();
+    router.resource_with("/dogs", Resource::new(Dogs).before_only(&[Action::Create], login));
+    TestClient::from_router(router)
+}
+
+#[test]
+fn middleware_hands_values_to_the_handler() {
+    let res = client().post("/dogs").header("X-User", "ann").send().unwrap();
+    assert_eq!((res.status, res.text()), (201, "created by ann".to_string()));
+
+    let res = client().post("/dogs").send().unwrap();
+    assert_eq!((res.status, res.text()), (401, "Unauthorized".to_string()));
+}
+
+#[test]
+fn handlers_without_the_middleware_see_no_value() {
+    let res = client().get("/dogs").header("X-User", "ann").send().unwrap();
+    assert_eq!(res.text(), "dogs for nobody");
+}
@@ -0,0 +1,43 @@
+extern crate rusty_server;
+
+use rusty_server::Server;
+use rusty_server::request::Request;
+use rusty_server::response::Response;
+use rusty_server::router::Router;
+use rusty_server::test_client::TestClient;
+
+fn server() -> Server {
+    let mut router = Router::new();
+    router.get("/", |_: Request, mut res: Response| {
+        let _ = res.send("home");
+    });
+
+    let mut server = Server::new();
+    server.register("/", router);
+    server
+}
+
+#[test]
+fn sends_a_server_header() {
+    let res = TestClient::new(server()).get("/").send().unwrap();
+    assert_eq!(res.header("Server"), Some("rusty_server"));
+
+    let mut named = server();
+    named.server_header("dogs/1.0");
+    let res = TestClient::new(named).get("/").send().unwrap();
+    assert_eq!(res.header("Server"), Some("dogs/1.0"));
+
+    let mut anonymous = server();
+    anonymous.no_server_header();
+    let res = TestClient::new(anonymous).get("/").send().unwrap();
+    assert_eq!(res.header("Server"), None);
+    assert!(res.header("Date").is_some());
+}
+
+#[test]
+fn sends_a_server_header_on_rejections() {
+    let res = TestClient::new(server()).get("/missing").send().unwrap();
+    assert_eq!(res.status, 404);
+    assert_eq!(res.header("Server"), Some("rusty_server"));
+    assert_eq!(res.header("Content-Length"), Some(&*res.body.len().to_string()));
+}
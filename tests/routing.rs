@@ -0,0 +1,176 @@
+extern crate rusty_server;
+
+use rusty_server::Server;
+use rusty_server::request::Request;
+use rusty_server::response::Response;
+use rusty_server::router::Router;
+use rusty_server::server::controller::{Action, Controller, Resource};
+use rusty_server::test_client::TestClient;
+
+fn reply(text: &'static str) -> impl Fn(Request, Response) {
+    move |_: Request, mut res: Response| {
+        let _ = res.send(text);
+    }
+}
+
+fn dogs() -> TestClient {
+    let mut router = Router::new();
+    router
+        .get("/dogs", reply("index"))
+        .get("/dogs/new", reply("new"))
+        .get("/dogs/:id", |req: Request, mut res: Response| {
+            let id = req.params.as_ref().and_then(|p| p.get("id").cloned()).unwrap_or_default();
+            let _ = res.send(format!("dog {}", id));
+        })
+        .post("/dogs", reply("create"))
+        .get("/files/*path", |req: Request, mut res: Response| {
+            let path = req.params.as_ref().and_then(|p| p.get("path").cloned()).unwrap_or_default();
+            let _ = res.send(path);
+        });
+    TestClient::from_router(router)
+}
+
+#[test]
+fn routes_by_method_and_path() {
+    let client = dogs();
+
+    let cases: &[(&str, &str, u16, &str)] = &[
+        ("GET", "/dogs", 200, "index"),
+        ("GET", "/dogs/", 200, "index"),
+        ("POST", "/dogs", 200, "create"),
+        ("GET", "/dogs/new", 200, "new"),
+        ("GET", "/dogs/7", 200, "dog 7"),
+        ("GET", "/files/a/b.txt", 200, "a/b.txt"),
+        ("GET", "/dogs/7/extra", 404, "Not Found"),
+        ("GET", "/cats", 404, "Not Found"),
+    ];
+
+    for &(method, path, status, body) in cases {
+        let res = client.request(method, path).send().unwrap();
+        assert_eq!(res.status, status, "{} {}", method, path);
+        assert_eq!(res.text(), body, "{} {}", method, path);
+    }
+}
+
+#[test]
+fn head_is_routed_as_get_without_a_body() {
+    let res = dogs().request("HEAD", "/dogs/7").send().unwrap();
+
+    assert_eq!(res.status, 200);
+    assert_eq!(res.header("Content-Length"), Some("5"));
+    assert!(res.body.is_empty());
+}
+
+#[test]
+fn other_methods_on_a_routed_path_are_not_allowed() {
+    let res = dogs().delete("/dogs").send().unwrap();
+
+    assert_eq!(res.status, 405);
+    assert_eq!(res.header("Allow"), Some("GET, HEAD, POST"));
+}
+
+#[test]
+fn methods_that_cant_be_routed_are_not_implemented() {
+    for method in &["OPTIONS", "TRACE", "BREW"] {
+        let res = dogs().request(*method, "/dogs").send().unwrap();
+        assert_eq!(res.status, 501, "{}", method);
+    }
+}
+
+#[test]
+fn longer_mounts_are_tried_first_and_misses_fall_through() {
+    let mut api = Router::new();
+    api.get("/dogs", reply("api dogs"));
+    let mut site = Router::new();
+    site.get("/api/health", reply("site health")).get("/apiary", reply("apiary"));
+
+    let mut server = Server::new();
+    server.register("/", site);
+    server.register("/api", api);
+    let client = TestClient::new(server);
+
+    assert_eq!(client.get("/api/dogs").send().unwrap().text(), "api dogs");
+    assert_eq!(client.get("/api/health").send().unwrap().text(), "site health");
+    assert_eq!(client.get("/apiary").send().unwrap().text(), "apiary");
+}
+
+struct Dogs;
+
+impl Controller for Dogs {
+    const ACTIONS: &'static [Action] = &[Action::Create, Action::Update, Action::Destroy];
+
+    fn create(&self, _: Request, mut res: Response) {
+        let _ = res.send("create");
+    }
+
+    fn update(&self, _: Request, mut res: Response) {
+        let _ = res.send("update");
+    }
+
+    fn destroy(&self, _: Request, mut res: Response) {
+        let _ = res.send("destroy");
+    }
+}
+
+#[test]
+fn resource_routes_take_method_overrides() {
+    let mut router = Router::new();
+    router.resource("/dogs", Dogs);
+    let client = TestClient::from_router(router);
+
+    let res = client
+        .post("/dogs/1")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("_method=DELETE")
+        .send()
+        .unwrap();
+    assert_eq!(res.text(), "destroy");
+
+    let res = client.post("/dogs/1").header("X-HTTP-Method-Override", "PATCH").send().unwrap();
+    assert_eq!(res.text(), "update");
+
+    let res = client.post("/dogs").header("X-HTTP-Method-Override", "DELETE").send().unwrap();
+    assert_eq!(res.text(), "create");
+}
+
+#[test]
+fn other_routes_ignore_method_overrides() {
+    let mut router = Router::new();
+    router
+        .post("/dogs/:id", reply("post"))
+        .delete("/dogs/:id", reply("delete"));
+    let client = TestClient::from_router(router);
+
+    let res = client.post("/dogs/1").header("X-HTTP-Method-Override", "DELETE").send().unwrap();
+    assert_eq!(res.text(), "post");
+}
+
+#[test]
+fn actions_left_out_of_a_resource_are_not_routed() {
+    let mut router = Router::new();
+    router.resource_with("/dogs", Resource::new(Dogs).except(&[Action::Destroy]));
+    let client = TestClient::from_router(router);
+
+    let res = client.delete("/dogs/1").send().unwrap();
+    assert_eq!(res.status, 405);
+    assert_eq!(res.header("Allow"), Some("PUT, PATCH"));
+}
+
+#[test]
+fn actions_a_controller_doesnt_implement_are_not_routed() {
+    let mut router = Router::new();
+    router.resource("/dogs", Dogs);
+    let client = TestClient::from_router(router);
+
+    let cases: &[(&str, &str, u16, Option<&str>)] = &[
+        ("GET", "/dogs", 405, Some("POST")),
+        ("GET", "/dogs/1", 405, Some("PUT, PATCH, DELETE")),
+        ("GET", "/dogs/1/edit", 404, None),
+    ];
+
+    for &(method, path, status, allow) in cases {
+        let res = client.request(method, path).send().unwrap();
+        assert_eq!(res.status, status, "{} {}", method, path);
+        assert_eq!(res.header("Allow"), allow, "{} {}", method, path);
+    }
+}
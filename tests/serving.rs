@@ -0,0 +1,146 @@
+extern crate rusty_server;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rusty_server::Server;
+use rusty_server::request::Request;
+use rusty_server::response::Response;
+use rusty_server::router::Router;
+use rusty_server::server::bind::ListenerOptions;
+
+fn server() -> Server {
+    let mut router = Router::new();
+    router
+        .get("/", |_: Request, mut res: Response| {
+            let _ = res.send("home");
+        })
+        .get("/slow", |_: Request, mut res: Response| {
+            thread::sleep(Duration::from_millis(300));
+            let _ = res.send("slow");
+        });
+
+    let mut admin = Router::new();
+    admin.get("/stats", |_: Request, mut res: Response| {
+        let _ = res.send("stats");
+    });
+
+    let mut server = Server::new();
+    server.register("/", router).register_internal("/admin", admin);
+    server
+}
+
+/// Sends a GET over a real connection, returning the whole raw response.
+fn get<S: Read + Write>(mut stream: S, path: &str) -> String {
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn connect(addr: SocketAddr) -> TcpStream {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream
+}
+
+#[test]
+fn shutdown_returns_promptly_when_idle() {
+    let bound = server().bind(0).unwrap();
+    let addr = bound.local_addr().unwrap();
+    let handle = bound.spawn();
+    assert!(get(connect(addr), "/").ends_with("\r\n\r\nhome"));
+
+    let start = Instant::now();
+    handle.shutdown();
+    assert!(start.elapsed() < Duration::from_secs(2), "{:?}", start.elapsed());
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn shutdown_finishes_requests_in_flight() {
+    let bound = server().bind(0).unwrap();
+    let addr = bound.local_addr().unwrap();
+    let handle = bound.spawn();
+
+    let request = thread::spawn(move || get(connect(addr), "/slow"));
+    thread::sleep(Duration::from_millis(100));
+    handle.shutdown();
+
+    let response = request.join().unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("slow"), "{}", response);
+}
+
+#[test]
+fn shutdown_gives_up_on_requests_after_the_grace_period() {
+    let mut server = server();
+    server.grace_period(Duration::from_millis(50));
+    let bound = server.bind(0).unwrap();
+    let addr = bound.local_addr().unwrap();
+    let handle = bound.spawn();
+
+    let request = thread::spawn(move || get(connect(addr), "/slow"));
+    thread::sleep(Duration::from_millis(50));
+    let start = Instant::now();
+    handle.shutdown();
+    assert!(start.elapsed() < Duration::from_millis(250), "{:?}", start.elapsed());
+
+    // the worker left behind still answers.
+    assert!(request.join().unwrap().ends_with("slow"));
+}
+
+#[test]
+fn serves_every_listener_with_the_same_routes() {
+    let bound = server()
+        .bind(0)
+        .unwrap()
+        .bind("127.0.0.1:0")
+        .unwrap()
+        .bind_with(0, ListenerOptions::new().internal())
+        .unwrap();
+    let addrs = bound.local_addrs().unwrap();
+    let handle = bound.spawn();
+
+    for addr in &addrs {
+        assert!(get(connect(*addr), "/").ends_with("\r\n\r\nhome"), "{}", addr);
+    }
+
+    // the admin routes are only on the internal listener.
+    assert!(get(connect(addrs[0]), "/admin/stats").starts_with("HTTP/1.1 404"));
+    assert!(get(connect(addrs[1]), "/admin/stats").starts_with("HTTP/1.1 404"));
+    assert!(get(connect(addrs[2]), "/admin/stats").ends_with("\r\n\r\nstats"));
+
+    handle.shutdown();
+}
+
+#[cfg(unix)]
+#[test]
+fn serves_a_unix_socket() {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let dir = std::env::temp_dir().join(format!("rusty_server-serve-unix-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("app.sock");
+    drop(UnixListener::bind(&path).unwrap());
+
+    let handle = server()
+        .bind_unix(&path, ListenerOptions::new().mode(0o600).internal())
+        .unwrap()
+        .spawn();
+
+    let connect = || {
+        let stream = UnixStream::connect(&path).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream
+    };
+    assert!(get(connect(), "/").ends_with("\r\n\r\nhome"));
+    assert!(get(connect(), "/admin/stats").ends_with("\r\n\r\nstats"));
+
+    handle.shutdown();
+    assert!(!path.exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,43 @@
+extern crate rusty_server;
+
+use rusty_server::Server;
+use rusty_server::request::Request;
+use rusty_server::response::Response;
+use rusty_server::router::Router;
+use rusty_server::test_client::TestClient;
+
+struct Config(&'static str);
+
+struct Pool(u32);
+
+fn report(req: Request, mut res: Response) {
+    let config = req.state::<Config>().map(|c| c.0).unwrap_or("none");
+    let pool = req.state::<Pool>().map(|p| p.0).unwrap_or(0);
+    let _ = res.send(format!("{} {}", config, pool));
+}
+
+#[test]
+fn router_state_takes_the_place_of_the_servers() {
+    let mut dogs = Router::new();
+    dogs.with_state(Config("dogs")).get("/", report);
+    let mut cats = Router::new();
+    cats.get("/", report);
+
+    let mut server = Server::new();
+    server
+        .with_state(Config("server"))
+        .with_state(Pool(4))
+        .register("/dogs", dogs)
+        .register("/cats", cats);
+    let client = TestClient::new(server);
+
+    assert_eq!(client.get("/dogs").send().unwrap().text(), "dogs 4");
+    assert_eq!(client.get("/cats").send().unwrap().text(), "server 4");
+}
+
+#[test]
+fn missing_state_is_none() {
+    let mut router = Router::new();
+    router.get("/", report);
+    assert_eq!(TestClient::from_router(router).get("/").send().unwrap().text(), "none 0");
+}
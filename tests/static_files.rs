@@ -0,0 +1,141 @@
+extern crate rusty_server;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rusty_server::router::Router;
+use rusty_server::server::static_files::StaticDir;
+use rusty_server::test_client::TestClient;
+
+/// A fresh site with a public directory, and a secret file next to it.
+fn site(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rusty_server-static-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("public/css")).unwrap();
+    fs::create_dir_all(dir.join("public/docs")).unwrap();
+    fs::write(dir.join("secret.txt"), "secret").unwrap();
+    fs::write(dir.join("public/index.html"), "<h1>home</h1>").unwrap();
+    fs::write(dir.join("public/css/site.css"), "body {}").unwrap();
+    fs::write(dir.join("public/docs/guide.txt"), "guide").unwrap();
+    dir
+}
+
+fn client(dir: &Path) -> TestClient {
+    let public = dir.join("public");
+    let mut router = Router::new();
+    router
+        .static_dir("/assets", &public)
+        .static_dir_with("/browse", StaticDir::new(&public).listing(true))
+        .static_dir_with("/app", StaticDir::new(&public).index(None::<String>).fallback("index.html"));
+    TestClient::from_router(router)
+}
+
+#[test]
+fn serves_files_and_index_files() {
+    let dir = site("serve");
+    let client = client(&dir);
+
+    let res = client.get("/assets/css/site.css").send().unwrap();
+    assert_eq!((res.status, res.text()), (200, "body {}".to_string()));
+    assert_eq!(res.header("Content-Type"), Some("text/css; charset=utf-8"));
+
+    for path in &["/assets", "/assets/"] {
+        let res = client.get(path).send().unwrap();
+        assert_eq!((res.status, res.text()), (200, "<h1>home</h1>".to_string()), "{}", path);
+    }
+
+    // no index file and no listing.
+    let res = client.get("/assets/docs/").send().unwrap();
+    assert_eq!(res.status, 404);
+    assert_eq!(client.get("/assets/missing.txt").send().unwrap().status, 404);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn refuses_paths_out_of_the_root() {
+    let dir = site("traversal");
+    let client = client(&dir);
+
+    let cases: &[&str] = &[
+        "/assets/../secret.txt",
+        "/assets/css/../../secret.txt",
+        "/assets/%2e%2e/secret.txt",
+        "/assets/%2E%2E%2Fsecret.txt",
+        "/assets/..%5csecret.txt",
+        "/assets/css%5C..%5C..%5Csecret.txt",
+        "/assets/index.html%00.css",
+    ];
+
+    for &path in cases {
+        let res = client.get(path).send().unwrap();
+        assert_eq!(res.status, 403, "{}", path);
+        assert!(!res.text().contains("secret"), "{}", path);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn treats_symlinks_out_of_the_root_as_missing() {
+    use std::os::unix::fs::symlink;
+
+    let dir = site("symlink");
+    symlink(dir.join("secret.txt"), dir.join("public/escape.txt")).unwrap();
+    symlink(&dir, dir.join("public/up")).unwrap();
+    let client = client(&dir);
+
+    for path in &["/assets/escape.txt", "/assets/up/secret.txt"] {
+        let res = client.get(path).send().unwrap();
+        assert_eq!(res.status, 404, "{}", path);
+        assert!(!res.text().contains("secret"), "{}", path);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn serves_the_fallback_for_missing_paths() {
+    let dir = site("fallback");
+    let client = client(&dir);
+
+    for path in &["/app/dogs/5", "/app/", "/app/docs"] {
+        let res = client.get(path).send().unwrap();
+        assert_eq!((res.status, res.text()), (200, "<h1>home</h1>".to_string()), "{}", path);
+    }
+    assert_eq!(client.get("/app/css/site.css").send().unwrap().text(), "body {}");
+    // a refused path isn't a missing one.
+    assert_eq!(client.get("/app/%2e%2e/secret.txt").send().unwrap().status, 403);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn lists_directories_with_escaped_names() {
+    let dir = site("listing");
+    fs::write(dir.join("public/docs/<b>&\"x\".txt"), "").unwrap();
+    fs::create_dir(dir.join("public/docs/sub dir")).unwrap();
+    let client = client(&dir);
+
+    let res = client.get("/browse/docs").send().unwrap();
+    assert_eq!(res.status, 200);
+    assert_eq!(res.header("Content-Type"), Some("text/html; charset=utf-8"));
+
+    let page = res.text();
+    assert!(page.contains("<title>Index of /browse/docs/</title>"), "{}", page);
+    assert!(
+        page.contains("<li><a href=\"/browse/docs/%3Cb%3E%26%22x%22.txt\">&lt;b&gt;&amp;&quot;x&quot;.txt</a></li>"),
+        "{}",
+        page
+    );
+    assert!(page.contains("<li><a href=\"/browse/docs/guide.txt\">guide.txt</a></li>"), "{}", page);
+    assert!(page.contains("<li><a href=\"/browse/docs/sub%20dir/\">sub dir/</a></li>"), "{}", page);
+    assert!(!page.contains("<b>"), "{}", page);
+
+    // a directory with an index file still serves it.
+    assert_eq!(client.get("/browse/").send().unwrap().text(), "<h1>home</h1>");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
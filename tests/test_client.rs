@@ -0,0 +1,106 @@
+extern crate rusty_server;
+
+use std::io::Write;
+
+use rusty_server::Server;
+use rusty_server::request::Request;
+use rusty_server::response::Response;
+use rusty_server::router::Router;
+use rusty_server::test_client::TestClient;
+
+fn client() -> TestClient {
+    let mut router = Router::new();
+    router
+        .get("/hello", |req: Request, mut res: Response| {
+            let name = req.header("X-Name").unwrap_or("world").to_string();
+            let _ = res.content_type("text/plain").send(format!("hello {}", name));
+        })
+        .post("/echo", |req: Request, mut res: Response| {
+            let body = req.body_bytes.clone().unwrap_or_default();
+            let _ = res.status(201).send(String::from_utf8_lossy(&body));
+        })
+        .get("/cookies", |req: Request, mut res: Response| {
+            let sent = req.header("Cookie").unwrap_or("").to_string();
+            res.headers.insert("Set-Cookie".to_string(), "session=abc; Path=/; HttpOnly".to_string());
+            let _ = res.send(sent);
+        })
+        .get("/stream", |_: Request, mut res: Response| {
+            let mut body = res.stream().unwrap();
+            for i in 0..3 {
+                writeln!(body, "row {}", i).unwrap();
+                body.flush().unwrap();
+            }
+            body.finish().unwrap();
+        });
+    TestClient::from_router(router)
+}
+
+#[test]
+fn sends_headers_and_reads_the_response() {
+    let res = client().get("/hello").header("X-Name", "Rex").send().unwrap();
+
+    assert_eq!(res.status, 200);
+    assert_eq!(res.reason, "OK");
+    assert_eq!(res.header("content-type"), Some("text/plain"));
+    assert_eq!(res.header("Content-Length"), Some("9"));
+    assert_eq!(res.text(), "hello Rex");
+}
+
+#[test]
+fn sends_a_body_with_its_length() {
+    let res = client().post("/echo").body(&b"bones\x00and\xffbytes"[..]).send().unwrap();
+
+    assert_eq!(res.status, 201);
+    assert_eq!(res.reason, "Created");
+    assert_eq!(res.text(), String::from_utf8_lossy(b"bones\x00and\xffbytes"));
+}
+
+#[test]
+fn sends_and_reads_cookies() {
+    let res = client().get("/cookies").cookie("theme", "dark").send().unwrap();
+
+    assert_eq!(res.text(), "theme=dark");
+    assert_eq!(res.cookies().get("session").map(|v| v.as_str()), Some("abc"));
+}
+
+#[test]
+fn joins_chunked_bodies() {
+    let res = client().get("/stream").send().unwrap();
+
+    assert_eq!(res.header("Transfer-Encoding"), Some("chunked"));
+    assert_eq!(res.text(), "row 0\nrow 1\nrow 2\n");
+}
+
+#[test]
+fn unmatched_requests_get_the_servers_answer() {
+    let res = client().get("/nowhere").send().unwrap();
+
+    assert_eq!(res.status, 404);
+    assert_eq!(res.reason, "Not Found");
+}
+
+#[test]
+fn internal_routers_need_an_internal_client() {
+    let mut admin = Router::new();
+    admin.get("/stats", |_: Request, mut res: Response| {
+        let _ = res.send("stats");
+    });
+    let mut server = Server::new();
+    server.register_internal("/admin", admin);
+
+    let res = TestClient::new(server.clone()).get("/admin/stats").send().unwrap();
+    assert_eq!(res.status, 404);
+
+    let res = TestClient::new(server).internal().get("/admin/stats").send().unwrap();
+    assert_eq!(res.text(), "stats");
+}
+
+#[test]
+fn requests_the_server_refuses_are_still_answered() {
+    let mut server = Server::new();
+    server.max_body_size(4);
+    server.register("/", Router::new());
+
+    let res = TestClient::new(server).post("/").body("too long").send().unwrap();
+    assert_eq!(res.status, 413);
+}